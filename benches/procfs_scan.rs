@@ -0,0 +1,131 @@
+//! Compares the procfs scanner against reading the same files of every process into new strings, both without a filter
+//! and when only a few names are tracked, over a synthetic procfs tree with as many processes as a busy host and over
+//! this system's /proc. Run with `cargo bench`
+
+#[cfg(target_os = "linux")]
+mod procfs {
+    use std::{collections::HashSet, path::Path};
+
+    use criterion::{black_box, Criterion};
+    use simple_process_tracker_rs::process_scanner::{ProcessScanner, SystemScanner};
+
+    const PROCESS_COUNT: u32 = 5000;
+
+    /// Write a procfs tree where every process has a comm, status and cgroup file, next to entries that do not belong
+    /// to a process
+    fn create_tree(dir: &Path) {
+        _ = std::fs::remove_dir_all(dir);
+
+        for pid in 1..=PROCESS_COUNT {
+            let process_dir = dir.join(pid.to_string());
+            std::fs::create_dir_all(&process_dir).unwrap();
+            std::fs::write(process_dir.join("comm"), format!("process-{}\n", pid % 500)).unwrap();
+            std::fs::write(
+                process_dir.join("status"),
+                format!(
+                    "Name:\tprocess-{}\nPid:\t{pid}\nUid:\t1000\t1000\t1000\t1000\n",
+                    pid % 500
+                ),
+            )
+            .unwrap();
+            std::fs::write(
+                process_dir.join("cgroup"),
+                format!("0::/user.slice/user-1000.slice/app-{pid}.scope\n"),
+            )
+            .unwrap();
+        }
+
+        for entry in ["self", "sys", "net"] {
+            std::fs::create_dir_all(dir.join(entry)).unwrap();
+        }
+    }
+
+    /// How processes were scanned before, with a new string for every entry and every file read. Like the scanner, the
+    /// status and cgroup of a process are only read when its name is wanted
+    fn read_to_string_per_pid(
+        dir: &Path,
+        names: Option<&HashSet<String>>,
+    ) -> Vec<(String, String, String)> {
+        let mut processes = vec![];
+
+        for entry in std::fs::read_dir(dir).unwrap().flatten() {
+            if entry
+                .file_name()
+                .to_str()
+                .and_then(|pid| pid.parse::<u32>().ok())
+                .is_none()
+            {
+                continue;
+            }
+
+            let Ok(name) = std::fs::read_to_string(entry.path().join("comm")) else {
+                continue;
+            };
+            let name = name.trim().to_owned();
+
+            if names.is_some_and(|names| !names.contains(&name)) {
+                continue;
+            }
+
+            let status = std::fs::read_to_string(entry.path().join("status")).unwrap_or_default();
+            let cgroup = std::fs::read_to_string(entry.path().join("cgroup")).unwrap_or_default();
+            processes.push((name, status, cgroup));
+        }
+
+        processes
+    }
+
+    fn bench_dir(c: &mut Criterion, group_name: &str, dir: &Path) {
+        let mut group = c.benchmark_group(group_name);
+
+        group.bench_function("read_to_string_per_pid", |b| {
+            b.iter(|| black_box(read_to_string_per_pid(dir, None)))
+        });
+
+        let mut scanner = SystemScanner::with_proc_dir(dir);
+        group.bench_function("system_scanner", |b| {
+            b.iter(|| black_box(scanner.scan().unwrap()))
+        });
+
+        let tracked: HashSet<String> = HashSet::from(["process-1".into(), "mpv".into()]);
+
+        group.bench_function("read_to_string_per_pid_tracked_only", |b| {
+            b.iter(|| black_box(read_to_string_per_pid(dir, Some(&tracked))))
+        });
+
+        let mut scanner = SystemScanner::with_proc_dir(dir);
+        scanner.filter_names(tracked.clone());
+        group.bench_function("system_scanner_tracked_only", |b| {
+            b.iter(|| black_box(scanner.scan().unwrap()))
+        });
+
+        group.finish();
+    }
+
+    pub fn bench(c: &mut Criterion) {
+        // A tmpfs is closer to procfs than a disk, where looking up paths takes most of the time
+        let base_dir = if Path::new("/dev/shm").is_dir() {
+            "/dev/shm".into()
+        } else {
+            std::env::temp_dir()
+        };
+        let dir = base_dir.join(format!(
+            "simple-process-tracker-bench-{}-procfs",
+            std::process::id()
+        ));
+        create_tree(&dir);
+
+        bench_dir(c, "synthetic_procfs", &dir);
+        bench_dir(c, "system_procfs", Path::new("/proc"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(target_os = "linux")]
+criterion::criterion_group!(benches, procfs::bench);
+#[cfg(target_os = "linux")]
+criterion::criterion_main!(benches);
+
+#[cfg(not(target_os = "linux"))]
+fn main() {}
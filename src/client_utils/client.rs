@@ -1,25 +1,91 @@
 use futures_lite::{io::BufReader, AsyncBufReadExt, AsyncWriteExt};
-use interprocess::local_socket::tokio::LocalSocketStream;
+use interprocess::local_socket::{tokio::LocalSocketStream, NameTypeSupport};
 
 use crate::{
-    commands::Commands,
-    get_socket_name,
-    structures::{config::Config, process::Process},
+    check_socket_owner,
+    commands::{ArchiveAction, Commands},
+    current_uid, get_profile, get_server_socket_name, get_socket_name, list_profiles,
+    server_utils::peer_credentials::peer_credentials,
+    socket_name_from,
+    structures::{audit_log::AuditEntry, client_config::ClientConfig, process::Process},
+    DisplayTimezone,
 };
 
-use super::response_handler::{handle_export_command, handle_view_command};
+use super::response_handler::{
+    handle_audit_command, handle_export_command, handle_settings_command, handle_view_command,
+};
 
-pub async fn handle_user_command(command: Commands) {
-    if let Err(e) = send_command(command).await {
+pub async fn handle_user_command(command: Commands, socket_override: Option<&str>) {
+    if let Err(e) = send_command(command, socket_override).await {
         eprintln!("{e}");
         std::process::exit(1);
     }
 }
 
-async fn send_command(command: Commands) -> Result<(), Box<dyn std::error::Error>> {
-    let conn = LocalSocketStream::connect(get_socket_name())
+async fn send_command(
+    mut command: Commands,
+    socket_override: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client_config = ClientConfig::read().unwrap_or_default();
+
+    // Client side options are saved here and never reach the server
+    if let Commands::Option(config_cmd) = &mut command {
+        if let Some(duration_format) = config_cmd.duration_format.take() {
+            client_config.duration_format = duration_format;
+            client_config.save()?;
+
+            println!("changed client config");
+
+            if !config_cmd.has_server_options() {
+                return Ok(());
+            }
+        }
+    }
+
+    // The server may run in another directory, so it must be given absolute paths to read and write. It also shows
+    // durations in the client's format
+    match &mut command {
+        Commands::Import(import_cmd) => {
+            import_cmd.path = std::path::absolute(&import_cmd.path)?;
+            import_cmd
+                .duration_format
+                .get_or_insert(client_config.duration_format);
+        }
+        Commands::Option(config_cmd) => {
+            if let Some(sync_dir) = config_cmd
+                .sync_dir
+                .as_mut()
+                .filter(|dir| !dir.as_os_str().is_empty())
+            {
+                *sync_dir = std::path::absolute(&sync_dir)?
+            }
+        }
+        Commands::Archive(archive_cmd) => match &mut archive_cmd.action {
+            ArchiveAction::Create { path } | ArchiveAction::Restore { path } => {
+                *path = std::path::absolute(&path)?
+            }
+        },
+        _ => {}
+    }
+
+    let socket_name = get_socket_name(socket_override)?;
+
+    let conn = LocalSocketStream::connect(socket_name.as_str())
         .await
-        .map_err(|e| format!("server may not be running -> {e}"))?;
+        .map_err(|e| format!("server may not be running on socket {socket_name} -> {e}"))?;
+
+    // The socket file may have been replaced after its owner was checked, so the server itself has to be ours too
+    if socket_name != get_server_socket_name(socket_override, false) {
+        let server_uid = peer_credentials(&conn).uid;
+
+        if server_uid != current_uid() {
+            return Err(format!(
+                "server on socket {socket_name} runs as uid {} -> not connecting to a server of another user, pass --socket to connect anyway",
+                server_uid.map_or_else(|| "?".to_string(), |uid| uid.to_string())
+            )
+            .into());
+        }
+    }
 
     let (reader, mut writer) = conn.into_split();
 
@@ -36,19 +102,49 @@ async fn send_command(command: Commands) -> Result<(), Box<dyn std::error::Error
 
     match command {
         Commands::View(_) | Commands::Export(_) => {
-            let processes: Vec<(usize, Process)> = serde_json::from_str(&response?)?;
+            let processes: Vec<(usize, Process, u64)> = serde_json::from_str(&response?)?;
+            let processes: Vec<(usize, Process)> = processes
+                .into_iter()
+                .map(|(id, process, instances)| {
+                    (
+                        id,
+                        Process {
+                            instances,
+                            ..process
+                        },
+                    )
+                })
+                .collect();
 
             match command {
-                Commands::View(view_cmd) => handle_view_command(view_cmd.debug, processes)?,
-                Commands::Export(export_cmd) => handle_export_command(&export_cmd.path, processes)?,
+                Commands::View(view_cmd) => handle_view_command(
+                    &view_cmd,
+                    view_cmd.tz.unwrap_or(DisplayTimezone::Local),
+                    view_cmd
+                        .duration_format
+                        .unwrap_or(client_config.duration_format),
+                    processes,
+                )?,
+                Commands::Export(export_cmd) => handle_export_command(
+                    &export_cmd,
+                    export_cmd
+                        .duration_format
+                        .unwrap_or(client_config.duration_format),
+                    processes,
+                )?,
 
                 _ => unreachable!(),
             }
         }
 
+        Commands::Audit(audit_cmd) => {
+            let entries: Vec<AuditEntry> = serde_json::from_str(&response?)?;
+            handle_audit_command(audit_cmd.tz.unwrap_or(DisplayTimezone::Local), entries);
+        }
+
         Commands::Settings => {
-            let config: Config = serde_json::from_str(&response?)?;
-            println!("{config:#?}");
+            let settings = serde_json::from_str(&response?)?;
+            handle_settings_command(settings, &client_config);
         }
 
         _ => {
@@ -59,3 +155,39 @@ async fn send_command(command: Commands) -> Result<(), Box<dyn std::error::Error
 
     Ok(())
 }
+
+/// List every profile with the socket its server listens on by default and whether it is running
+pub async fn show_profiles() {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(std::path::PathBuf::from);
+
+    let mut profiles = list_profiles();
+
+    // A profile that has not saved anything yet is still shown while it is being used
+    if !profiles.iter().any(|profile| profile == get_profile()) {
+        profiles.push(get_profile().into());
+    }
+
+    for profile in profiles {
+        let socket_name = |runtime_dir| {
+            socket_name_from(None, None, runtime_dir, NameTypeSupport::query(), &profile)
+        };
+        let private = socket_name(runtime_dir.clone());
+        // A server that is shared with other users listens outside the runtime dir
+        let shared = socket_name(None);
+
+        let (socket_name, status) = if LocalSocketStream::connect(private.as_str()).await.is_ok() {
+            (private, "running")
+        } else if shared != private
+            && check_socket_owner(&shared).is_ok()
+            && LocalSocketStream::connect(shared.as_str()).await.is_ok()
+        {
+            (shared, "running")
+        } else {
+            (private, "stopped")
+        };
+
+        let current = if profile == get_profile() { "*" } else { " " };
+
+        println!("{current} {profile:<20} {status:<8} {socket_name}");
+    }
+}
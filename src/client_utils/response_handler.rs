@@ -1,17 +1,35 @@
-use std::path::Path;
-
 use comfy_table::{
     modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Cell, CellAlignment, ContentArrangement,
     Table,
 };
 
-use crate::{duration_to_string, structures::process::Process, ACTIVE_ICON, PAUSED_ICON};
+use crate::{
+    commands::{Export, ExportFormat, View, ViewColumn},
+    format_datetime, format_duration, format_kib,
+    structures::{
+        audit_log::AuditEntry, client_config::ClientConfig, config::Setting,
+        csv_process::write_csv, process::Process,
+    },
+    DisplayTimezone, DurationFormat, ACTIVE_ICON, PAUSED_ICON,
+};
 
 pub fn handle_view_command(
-    debug: bool,
-    processes: Vec<(usize, Process)>,
+    view_cmd: &View,
+    timezone: DisplayTimezone,
+    duration_format: DurationFormat,
+    mut processes: Vec<(usize, Process)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if debug {
+    let mut columns = view_cmd.columns.clone();
+
+    if let Some(rank) = view_cmd.rank {
+        processes.sort_by_key(|(_, process)| std::cmp::Reverse(rank.value_of(process)));
+
+        if let Some(column) = rank.column().filter(|column| !columns.contains(column)) {
+            columns.push(column);
+        }
+    }
+
+    if view_cmd.debug {
         println!("{:#?}", processes);
     } else {
         let mut table = Table::new();
@@ -21,16 +39,31 @@ pub fn handle_view_command(
             .apply_modifier(UTF8_ROUND_CORNERS)
             .set_content_arrangement(ContentArrangement::Dynamic);
 
-        table.set_header([
+        let mut header = vec![
             Cell::new("#").set_alignment(CellAlignment::Center),
             Cell::new("Tracking").set_alignment(CellAlignment::Center),
             Cell::new("Running").set_alignment(CellAlignment::Center),
             Cell::new("Name").set_alignment(CellAlignment::Center),
             Cell::new("Duration").set_alignment(CellAlignment::Center),
+        ];
+        header.extend(columns.iter().map(|column| {
+            let title = match column {
+                ViewColumn::Launches => "Launches",
+                ViewColumn::LongestSession => "Longest session",
+                ViewColumn::Instances => "Instances",
+                ViewColumn::Cpu => "CPU time",
+                ViewColumn::PeakRss => "Peak memory",
+                ViewColumn::AvgRss => "Average memory",
+                ViewColumn::PeakInstances => "Peak instances",
+            };
+            Cell::new(title).set_alignment(CellAlignment::Center)
+        }));
+        header.extend([
             Cell::new("Notes").set_alignment(CellAlignment::Center),
             Cell::new("Last seen").set_alignment(CellAlignment::Center),
             Cell::new("Date added").set_alignment(CellAlignment::Center),
         ]);
+        table.set_header(header);
 
         for (id, process) in processes {
             let tracking_icon = if process.is_tracked {
@@ -45,19 +78,37 @@ pub fn handle_view_command(
                 PAUSED_ICON
             };
 
-            table.add_row([
+            let mut row = vec![
                 Cell::new(id),
                 Cell::new(tracking_icon).set_alignment(CellAlignment::Center),
                 Cell::new(running_icon).set_alignment(CellAlignment::Center),
                 Cell::new(&process.name).set_alignment(CellAlignment::Center),
-                Cell::new(duration_to_string(process.duration))
+                Cell::new(format_duration(process.duration, duration_format))
                     .set_alignment(CellAlignment::Center),
+            ];
+            row.extend(columns.iter().map(|column| {
+                let usage = &process.usage;
+                let value = match column {
+                    ViewColumn::Launches => process.launches.to_string(),
+                    ViewColumn::LongestSession => {
+                        format_duration(process.longest_session, duration_format)
+                    }
+                    ViewColumn::Instances => process.instances.to_string(),
+                    ViewColumn::Cpu => format_duration(usage.cpu_millis / 1000, duration_format),
+                    ViewColumn::PeakRss => format_kib(usage.peak_rss_kib),
+                    ViewColumn::AvgRss => format_kib(usage.avg_rss_kib),
+                    ViewColumn::PeakInstances => usage.peak_instances.to_string(),
+                };
+                Cell::new(value).set_alignment(CellAlignment::Center)
+            }));
+            row.extend([
                 Cell::new(&process.notes).set_alignment(CellAlignment::Center),
-                Cell::new(process.last_seen_date.format("%Y/%m/%d %H:%M:%S"))
+                Cell::new(format_datetime(&process.last_seen_date, timezone))
                     .set_alignment(CellAlignment::Center),
-                Cell::new(process.added_date.format("%Y/%m/%d %H:%M:%S"))
+                Cell::new(format_datetime(&process.added_date, timezone))
                     .set_alignment(CellAlignment::Center),
             ]);
+            table.add_row(row);
         }
 
         println!("{table}");
@@ -67,9 +118,12 @@ pub fn handle_view_command(
 }
 
 pub fn handle_export_command(
-    export_path: &Path,
+    export_cmd: &Export,
+    duration_format: DurationFormat,
     processes: Vec<(usize, Process)>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let export_path = &export_cmd.path;
+
     let file = std::fs::OpenOptions::new()
         .create(true)
         .write(true)
@@ -79,7 +133,10 @@ pub fn handle_export_command(
 
     let processes: Vec<Process> = processes.into_iter().map(|(_, process)| process).collect();
 
-    serde_json::to_writer_pretty(file, &processes)?;
+    match export_cmd.format() {
+        ExportFormat::Json => serde_json::to_writer_pretty(file, &processes)?,
+        ExportFormat::Csv => write_csv(file, &processes, export_cmd.delimiter, duration_format)?,
+    }
 
     println!(
         "exported {:?} to {}",
@@ -92,3 +149,56 @@ pub fn handle_export_command(
 
     Ok(())
 }
+
+pub fn handle_audit_command(timezone: DisplayTimezone, entries: Vec<AuditEntry>) {
+    if entries.is_empty() {
+        println!("no matching audit entries");
+        return;
+    }
+
+    let unknown = || "?".to_string();
+
+    for entry in entries {
+        println!(
+            "{} uid {} pid {}{} {}",
+            format_datetime(&entry.date, timezone),
+            entry.peer.uid.map_or_else(unknown, |uid| uid.to_string()),
+            entry.peer.pid.map_or_else(unknown, |pid| pid.to_string()),
+            if entry.succeeded { "" } else { " failed" },
+            entry.command
+        );
+
+        for change in entry.changes {
+            println!(
+                "    {}.{}: {} -> {}",
+                change.target, change.field, change.before, change.after
+            );
+        }
+    }
+}
+
+/// Print every setting as it would be written in the config file, followed by where its value came from
+pub fn handle_settings_command(settings: Vec<Setting>, client_config: &ClientConfig) {
+    let width = settings
+        .iter()
+        .map(|setting| setting.name.len())
+        .max()
+        .unwrap_or_default();
+
+    for setting in settings {
+        println!(
+            "{:<width$} = {:<40} # {}",
+            setting.name,
+            setting.value.to_string(),
+            setting.source
+        );
+    }
+
+    println!(
+        "{:<width$} = {:<40} # client.json",
+        "duration_format",
+        serde_json::to_value(client_config.duration_format)
+            .expect("must serialize")
+            .to_string()
+    );
+}
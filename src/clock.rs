@@ -0,0 +1,74 @@
+#[cfg(any(test, feature = "test-util"))]
+use std::sync::Arc;
+use std::{future::Future, pin::Pin, time::Duration};
+
+use chrono::{DateTime, Utc};
+#[cfg(any(test, feature = "test-util"))]
+use tokio::sync::watch;
+
+/// The source of the current time and of delays for everything that depends on time passing
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// The real time of the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A clock that only moves when advanced manually. Sleeping on it waits until the clock has been advanced
+/// past the wake up time. Clones share the same time
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone)]
+pub struct FakeClock {
+    now: Arc<watch::Sender<DateTime<Utc>>>,
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl FakeClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(watch::channel(start).0),
+        }
+    }
+
+    /// Move the clock forward and wake up everything sleeping until a time that has now passed
+    pub fn advance(&self, duration: Duration) {
+        let duration = chrono::Duration::from_std(duration).expect("duration out of range");
+        self.now.send_modify(|now| *now += duration);
+    }
+
+    /// Set the clock to the given time, even if it lies in the past
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.send_replace(now);
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let wake_up =
+            self.now() + chrono::Duration::from_std(duration).expect("duration out of range");
+        let mut receiver = self.now.subscribe();
+
+        Box::pin(async move {
+            // The sender lives as long as self, so waiting can only end once the wake up time is reached
+            _ = receiver.wait_for(|now| *now >= wake_up).await;
+        })
+    }
+}
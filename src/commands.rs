@@ -11,15 +11,40 @@ pub struct Add {
     /// Set the process icon
     #[arg(short, long)]
     pub icon: Option<PathBuf>,
-    /// Set the process duration in HH:MM:SS
+    /// Set the process duration, e.g. 01:30:00, 1h30m, 90m or PT1H30M
     #[arg(short, long)]
     pub duration: Option<String>,
     /// Set notes about this process
     #[arg(short, long)]
     pub notes: Option<String>,
-    /// Overwrite the date this process was added in YYYY/MM/DD HH:MM:SS format
+    /// Overwrite the date this process was added, e.g. "2023/05/20 18:00:00", 2023-05-20T18:00:00+02:00,
+    /// "yesterday 18:00" or "3 days ago"
     #[arg(short, long)]
     pub added_date: Option<String>,
+    /// Check if this process is running every this many seconds instead of the poll interval of the config
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_PROCESS_POLL_INTERVAL..))]
+    pub poll_interval: Option<u64>,
+    /// Do not count runs shorter than this, e.g. 30s or 5m
+    #[arg(long)]
+    pub min_session: Option<String>,
+    /// Whose instances count as the process running: me, any or a list of user IDs such as 1000,1001.
+    /// Defaults to me
+    #[arg(short, long)]
+    pub users: Option<crate::structures::process::Users>,
+    /// Count the processes whose cgroup path, systemd unit, Flatpak app ID or container ID matches this glob pattern
+    /// instead of the processes with this name, e.g. "app-flatpak-org.mozilla.firefox-*.scope"
+    #[arg(short, long)]
+    pub cgroup: Option<String>,
+}
+
+#[derive(Debug, Default, Parser, Serialize, Deserialize)]
+pub struct Processes {
+    /// Show the user that owns each process. A name is shown once for every user running it
+    #[arg(short, long, default_value_t = false)]
+    pub users: bool,
+    /// Show the systemd unit, Flatpak app ID and container ID of each process, which can be matched with "add --cgroup"
+    #[arg(short, long, default_value_t = false)]
+    pub cgroups: bool,
 }
 
 #[derive(Debug, Parser, Serialize, Deserialize)]
@@ -29,6 +54,87 @@ pub struct View {
     /// Debug print the processes
     #[arg(short, long, default_value_t = false)]
     pub debug: bool,
+    /// Show dates in this timezone instead of the local one, e.g. UTC, +02:00 or Europe/Sofia
+    #[arg(long)]
+    #[serde(skip)]
+    pub tz: Option<crate::DisplayTimezone>,
+    /// How to show durations instead of the default set with "option --duration-format"
+    #[arg(short = 'f', long, value_enum)]
+    #[serde(skip)]
+    pub duration_format: Option<crate::DurationFormat>,
+    /// Show the processes of another host in the sync directory, or "all" to combine every host
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Show how each process has been used and how heavy it has been on the system in extra columns, e.g.
+    /// launches,cpu,peak-rss
+    #[arg(short, long, value_enum, value_delimiter = ',')]
+    #[serde(skip)]
+    pub columns: Vec<ViewColumn>,
+    /// List the processes from the highest to the lowest of this instead of by ID
+    #[arg(short, long, value_enum)]
+    #[serde(skip)]
+    pub rank: Option<Rank>,
+}
+
+/// An extra column of the view command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ViewColumn {
+    /// How many times the process started running
+    Launches,
+    /// The longest single run
+    LongestSession,
+    /// How many instances are running right now
+    Instances,
+    /// CPU time used while tracked, summed across all instances
+    Cpu,
+    /// The most resident memory used at once, summed across all instances
+    PeakRss,
+    /// The resident memory used on average while running, summed across all instances
+    AvgRss,
+    /// The most instances that ran at once
+    PeakInstances,
+}
+
+/// What the view command can rank processes by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum Rank {
+    Duration,
+    Launches,
+    LongestSession,
+    Instances,
+    Cpu,
+    PeakRss,
+    AvgRss,
+    PeakInstances,
+}
+
+impl Rank {
+    /// The column that shows what is ranked, if it is not shown by default
+    pub fn column(&self) -> Option<ViewColumn> {
+        match self {
+            Rank::Duration => None,
+            Rank::Launches => Some(ViewColumn::Launches),
+            Rank::LongestSession => Some(ViewColumn::LongestSession),
+            Rank::Instances => Some(ViewColumn::Instances),
+            Rank::Cpu => Some(ViewColumn::Cpu),
+            Rank::PeakRss => Some(ViewColumn::PeakRss),
+            Rank::AvgRss => Some(ViewColumn::AvgRss),
+            Rank::PeakInstances => Some(ViewColumn::PeakInstances),
+        }
+    }
+
+    pub fn value_of(&self, process: &crate::structures::process::Process) -> u64 {
+        match self {
+            Rank::Duration => process.duration,
+            Rank::Launches => process.launches,
+            Rank::LongestSession => process.longest_session,
+            Rank::Instances => process.instances,
+            Rank::Cpu => process.usage.cpu_millis,
+            Rank::PeakRss => process.usage.peak_rss_kib,
+            Rank::AvgRss => process.usage.avg_rss_kib,
+            Rank::PeakInstances => process.usage.peak_instances,
+        }
+    }
 }
 
 #[derive(Debug, Parser, Serialize, Deserialize)]
@@ -42,15 +148,30 @@ pub struct Change {
     /// Set the process icon
     #[arg(short, long, group = "Action")]
     pub icon: Option<PathBuf>,
-    /// Set the process duration in HH:MM:SS
+    /// Set the process duration, e.g. 01:30:00, 1h30m, 90m or PT1H30M
     #[arg(short, long, group = "Action")]
     pub duration: Option<String>,
     /// Set notes about this process
     #[arg(short, long, group = "Action")]
     pub notes: Option<String>,
-    /// Overwrite the date this process was added in YYYY/MM/DD HH:MM:SS format
+    /// Overwrite the date this process was added, e.g. "2023/05/20 18:00:00", 2023-05-20T18:00:00+02:00,
+    /// "yesterday 18:00" or "3 days ago"
     #[arg(short, long, group = "Action")]
     pub added_date: Option<String>,
+    /// Check if this process is running every this many seconds instead of the poll interval of the config.
+    /// 0 goes back to the poll interval of the config
+    #[arg(long, group = "Action")]
+    pub poll_interval: Option<u64>,
+    /// Do not count runs shorter than this, e.g. 30s or 5m. 0 counts every run
+    #[arg(long, group = "Action")]
+    pub min_session: Option<String>,
+    /// Whose instances count as the process running: me, any or a list of user IDs such as 1000,1001
+    #[arg(short, long, group = "Action")]
+    pub users: Option<crate::structures::process::Users>,
+    /// Count the processes whose cgroup path, systemd unit, Flatpak app ID or container ID matches this glob pattern
+    /// instead of the processes with the same name. An empty pattern goes back to matching by name
+    #[arg(short, long, group = "Action")]
+    pub cgroup: Option<String>,
 }
 
 #[derive(Debug, Parser, Serialize, Deserialize)]
@@ -72,20 +193,237 @@ pub struct Move {
     pub direction: MoveDirection,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
 #[derive(Debug, Parser, Serialize, Deserialize)]
 pub struct Export {
-    /// Where to save the exported JSON file
+    /// Where to save the exported file
     pub path: PathBuf,
     /// The process IDs to export in 0-3,5,7 format
     pub ids: Option<String>,
+    /// The format of the exported file. Defaults to CSV if the path ends in .csv and JSON otherwise
+    #[arg(long, value_enum)]
+    pub format: Option<ExportFormat>,
+    /// The field delimiter for CSV exports, e.g. ";" or tab
+    #[arg(long, default_value = ",", value_parser = crate::parse_delimiter)]
+    pub delimiter: u8,
+    /// How to show the human readable duration column of CSV exports instead of the default set with
+    /// "option --duration-format"
+    #[arg(short = 'f', long, value_enum)]
+    #[serde(skip)]
+    pub duration_format: Option<crate::DurationFormat>,
+}
+
+impl Export {
+    pub fn format(&self) -> ExportFormat {
+        self.format.unwrap_or_else(|| match self.path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ImportFormat {
+    /// JSON exported by Simple process tracker
+    Json,
+    /// JSON from the old Simple process tracker
+    Legacy,
+    /// CSV exported by Simple process tracker or written by hand
+    Csv,
+    /// A bucket export from ActivityWatch. Window events are summed by app name
+    ActivityWatch,
+    /// The output of "timew export". Intervals are summed by tag
+    Timewarrior,
+    /// A detailed report CSV from Toggl Track. Time entries are summed by project
+    Toggl,
+}
+
+impl ImportFormat {
+    /// Whether the format records sessions of time, which are summed into existing processes,
+    /// instead of whole processes, which are skipped if they already exist
+    pub fn is_session_based(&self) -> bool {
+        matches!(self, Self::ActivityWatch | Self::Timewarrior | Self::Toggl)
+    }
+}
+
+/// What to do when an imported process has the same name as an already tracked one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ConflictStrategy {
+    /// Keep the tracked process unchanged
+    Skip,
+    /// Replace the tracked process with the imported one
+    Overwrite,
+    /// Add the imported duration to the tracked duration
+    Sum,
+    /// Keep the longer of the two durations
+    Max,
+    /// Keep the tracked duration and append the imported notes to the tracked notes
+    MergeNotes,
 }
 
 #[derive(Debug, Parser, Serialize, Deserialize)]
 pub struct Import {
     pub path: PathBuf,
-    /// Whether the provided json is from the old Simple process tracker
-    #[arg(short, long, default_value_t = false)]
-    pub legacy: bool,
+    /// The format of the imported file. Defaults to CSV if the path ends in .csv and JSON otherwise
+    #[arg(short, long, value_enum)]
+    pub format: Option<ImportFormat>,
+    /// The field delimiter for CSV imports, e.g. ";" or tab
+    #[arg(long, default_value = ",", value_parser = crate::parse_delimiter)]
+    pub delimiter: u8,
+    /// What to do with processes that are already tracked. Defaults to sum for formats from other trackers and
+    /// skip otherwise. Except for skip and overwrite, the earliest added date and the latest last seen date are kept
+    /// and the process stays tracked if either side is tracked
+    #[arg(short, long, value_enum)]
+    pub on_conflict: Option<ConflictStrategy>,
+    /// Show what would happen to each process without changing anything
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+    /// How to show the durations of the imported processes instead of the default set with
+    /// "option --duration-format"
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub duration_format: Option<crate::DurationFormat>,
+}
+
+impl Import {
+    pub fn format(&self) -> ImportFormat {
+        self.format.unwrap_or_else(|| match self.path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ImportFormat::Csv,
+            _ => ImportFormat::Json,
+        })
+    }
+}
+
+#[derive(Debug, Subcommand, Serialize, Deserialize)]
+pub enum ArchiveAction {
+    /// Save processes, configuration and the undo history to a single versioned file with a checksum. The server writes the file
+    /// atomically, so an interrupted archive never leaves a half written file behind
+    Create { path: PathBuf },
+
+    /// Replace all processes, configuration and the undo history with the ones in the given archive
+    Restore { path: PathBuf },
+}
+
+#[derive(Debug, Parser, Serialize, Deserialize)]
+pub struct Archive {
+    #[command(subcommand)]
+    pub action: ArchiveAction,
+}
+
+#[derive(Debug, Parser, Serialize, Deserialize)]
+pub struct History {
+    /// List the recent operations that can be undone, newest first
+    #[arg(long, required = true)]
+    pub ops: bool,
+    /// How many operations to list
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+#[derive(Debug, Parser, Serialize, Deserialize)]
+pub struct Audit {
+    /// Only show requests sent by the user with this ID
+    #[arg(short, long)]
+    pub uid: Option<u32>,
+    /// Only show requests that changed the process with this name
+    #[arg(short, long)]
+    pub process: Option<String>,
+    /// Only show requests made after this date time
+    #[arg(short, long)]
+    pub since: Option<String>,
+    /// How many of the most recent requests to show
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub limit: usize,
+    /// Show dates in this timezone instead of the local one, e.g. UTC, +02:00 or Europe/Sofia
+    #[arg(long)]
+    #[serde(skip)]
+    pub tz: Option<crate::DisplayTimezone>,
+}
+
+/// Settings for this launch only. They take priority over the config file and the SPT_* environment variables,
+/// e.g. SPT_POLL_INTERVAL, and are not saved
+#[derive(Debug, Default, Parser, Serialize, Deserialize)]
+pub struct Launch {
+    /// How often to check if the tracked processes are still running in seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_POLL_INTERVAL..))]
+    pub poll_interval: Option<u64>,
+    /// How often to update the durations for processes that are currently running in seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_DURATION_UPDATE_INTERVAL..))]
+    pub duration_update_interval: Option<u64>,
+    /// How often to autosave in case the program quits unexpectedly in seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_AUTOSAVE_INTERVAL..))]
+    pub autosave_interval: Option<u64>,
+    /// A directory shared with other machines to combine their tracked time. An empty path turns syncing off
+    #[arg(long, value_parser = parse_path)]
+    pub sync_dir: Option<PathBuf>,
+    /// How often to sync with the other machines in seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_SYNC_INTERVAL..))]
+    pub sync_interval: Option<u64>,
+    /// The name of this machine in the sync directory. An empty name uses the system host name
+    #[arg(long)]
+    pub host_name: Option<String>,
+    /// Allow another user or group to connect, e.g. uid:1001 or group:100:read-write. Replaces the allowed peers
+    /// of the config file
+    #[arg(long)]
+    pub allow: Vec<crate::structures::config::AllowedPeer>,
+    /// Where processes, the undo history and the audit log are kept
+    #[arg(long, value_parser = parse_path)]
+    pub data_dir: Option<PathBuf>,
+}
+
+impl Launch {
+    /// The settings that are set by the launch flags
+    pub fn settings(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut settings = serde_json::Map::new();
+
+        let mut insert = |name: &str, value: serde_json::Value| {
+            settings.insert(name.into(), value);
+        };
+
+        if let Some(poll_interval) = self.poll_interval {
+            insert("poll_interval", poll_interval.into());
+        }
+        if let Some(duration_update_interval) = self.duration_update_interval {
+            insert("duration_update_interval", duration_update_interval.into());
+        }
+        if let Some(autosave_interval) = self.autosave_interval {
+            insert("autosave_interval", autosave_interval.into());
+        }
+        if let Some(sync_interval) = self.sync_interval {
+            insert("sync_interval", sync_interval.into());
+        }
+
+        // Empty paths and names unset the setting, the same as with the option command
+        for (name, value) in [
+            (
+                "sync_dir",
+                self.sync_dir.as_ref().map(|dir| dir.display().to_string()),
+            ),
+            ("host_name", self.host_name.clone()),
+            (
+                "data_dir",
+                self.data_dir.as_ref().map(|dir| dir.display().to_string()),
+            ),
+        ] {
+            if let Some(value) = value {
+                insert(name, Some(value).filter(|value| !value.is_empty()).into());
+            }
+        }
+
+        if !self.allow.is_empty() {
+            insert(
+                "allowed_peers",
+                serde_json::to_value(&self.allow).expect("must serialize"),
+            );
+        }
+
+        settings
+    }
 }
 
 #[derive(Debug, Parser, Serialize, Deserialize)]
@@ -100,15 +438,57 @@ pub struct Config {
     /// How often to autosave in case the program quits unexpectedly in seconds
     #[arg(short, long, value_parser = clap::value_parser!(u64).range(crate::MIN_AUTOSAVE_INTERVAL..))]
     pub autosave_interval: Option<u64>,
+    /// A directory shared with other machines to combine their tracked time. An empty path turns syncing off
+    #[arg(long, value_parser = parse_path)]
+    pub sync_dir: Option<PathBuf>,
+    /// How often to sync with the other machines in seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(crate::MIN_SYNC_INTERVAL..))]
+    pub sync_interval: Option<u64>,
+    /// The name of this machine in the sync directory. An empty name uses the system host name
+    #[arg(long)]
+    pub host_name: Option<String>,
+    /// Allow another user or group to connect, e.g. uid:1001, group:100 or uid:1001:read-write.
+    /// Peers are read only unless read-write is given. Only the user running the server may change this
+    #[arg(long)]
+    pub allow: Vec<crate::structures::config::AllowedPeer>,
+    /// Stop allowing a user or group that was allowed before, e.g. uid:1001 or group:100
+    #[arg(long)]
+    pub revoke: Vec<crate::structures::config::PeerId>,
+    /// How the client shows durations by default. This is saved on the client side and not sent to the server
+    #[arg(short = 'f', long, value_enum)]
+    #[serde(skip)]
+    pub duration_format: Option<crate::DurationFormat>,
+}
+
+impl Config {
+    /// Whether any option that is handled by the server is set
+    pub fn has_server_options(&self) -> bool {
+        self.poll_interval.is_some()
+            || self.duration_update_interval.is_some()
+            || self.autosave_interval.is_some()
+            || self.sync_dir.is_some()
+            || self.sync_interval.is_some()
+            || self.host_name.is_some()
+            || !self.allow.is_empty()
+            || !self.revoke.is_empty()
+    }
 }
 
 #[derive(Debug, Subcommand, Serialize, Deserialize)]
 pub enum DurationCalculation {
-    /// Add seconds to a process's duration
-    Add { seconds: u64 },
+    /// Add time to a process's duration
+    Add {
+        /// Seconds or a duration such as 1h30m, 2:30 or PT1H30M
+        #[arg(value_parser = crate::string_to_duration)]
+        seconds: u64,
+    },
 
-    /// Subtract seconds from a process's duration
-    Subtract { seconds: u64 },
+    /// Subtract time from a process's duration
+    Subtract {
+        /// Seconds or a duration such as 1h30m, 2:30 or PT1H30M
+        #[arg(value_parser = crate::string_to_duration)]
+        seconds: u64,
+    },
 }
 
 #[derive(Debug, Subcommand, Serialize, Deserialize)]
@@ -122,10 +502,13 @@ pub enum MoveDirection {
 #[derive(Debug, Subcommand, Serialize, Deserialize)]
 pub enum Commands {
     /// Launch Simple process tracker and begin tracking selected processes
-    Launch,
+    Launch(Launch),
 
     /// List all processes running on the system with the names that Simple process tracker will use to check if they are active
-    Processes,
+    Processes(Processes),
+
+    /// List the profiles that have saved data and whether their servers are running
+    Profiles,
 
     /// Add a process to track. Optionally add it with specific options set in advance
     Add(Add),
@@ -137,7 +520,7 @@ pub enum Commands {
     /// Change some data about a specific process, such as its duration, notes, icon, etc.
     Change(Change),
 
-    /// Add or subtract seconds from a process's duration
+    /// Add or subtract time from a process's duration
     Duration(Duration),
 
     /// Remove a process given its ID
@@ -151,9 +534,28 @@ pub enum Commands {
     /// Example: export "./export.json" 0-3,5,7
     Export(Export),
 
-    /// Import processes from the given JSON file. Example: import "./export.json"
+    /// Import processes from the given file. Time from other trackers is added to processes with the same name.
+    /// Example: import "./export.json"
     Import(Import),
 
+    /// Create or restore a full backup of Simple process tracker, including its configuration and undo history.
+    /// Example: archive create "./backup.json"
+    Archive(Archive),
+
+    /// Revert the last add, change, duration, remove, move or import
+    Undo,
+
+    /// Apply the last undone operation again
+    Redo,
+
+    /// Show the history of Simple process tracker.
+    /// Example: history --ops
+    History(History),
+
+    /// Show who changed what. Every request that changes processes or options is recorded in the audit log.
+    /// Example: audit --process mpv
+    Audit(Audit),
+
     /// Set options for Simple process tracker
     Option(Config),
 
@@ -163,3 +565,51 @@ pub enum Commands {
     /// Save and close Simple process tracker
     Quit,
 }
+
+impl Commands {
+    /// Whether the command may change the processes, the options or the server itself
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Commands::Add(_)
+            | Commands::Change(_)
+            | Commands::Duration(_)
+            | Commands::Remove(_)
+            | Commands::Move(_)
+            | Commands::Option(_)
+            | Commands::Undo
+            | Commands::Redo
+            | Commands::Quit => true,
+            Commands::Import(import_cmd) => !import_cmd.dry_run,
+            Commands::Archive(archive_cmd) => {
+                matches!(archive_cmd.action, ArchiveAction::Restore { .. })
+            }
+            Commands::Launch(_)
+            | Commands::Processes(_)
+            | Commands::Profiles
+            | Commands::View(_)
+            | Commands::Export(_)
+            | Commands::History(_)
+            | Commands::Audit(_)
+            | Commands::Settings => false,
+        }
+    }
+
+    /// Whether the command is handled by the client itself and never sent to the server
+    pub fn is_client_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::Launch(_) | Commands::Processes(_) | Commands::Profiles
+        )
+    }
+
+    /// Whether the request is recorded in the audit log. Archives are also recorded when they are created, because
+    /// they are written with the privileges of the server
+    pub fn is_audited(&self) -> bool {
+        self.is_mutating() || matches!(self, Commands::Archive(_))
+    }
+}
+
+/// Clap does not accept empty paths, which are used to unset a path
+fn parse_path(input: &str) -> Result<PathBuf, std::convert::Infallible> {
+    Ok(PathBuf::from(input))
+}
@@ -0,0 +1,255 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+use crate::local_to_utc;
+
+pub const DURATION_FORMATS: &str =
+    "seconds (90), HH:MM:SS (01:30:00), H:MM (1:30), units (1h30m, 90m, 1d 2h, 1.5h) or ISO-8601 (PT1H30M, P1DT2H)";
+
+pub const DATETIME_FORMATS: &str =
+    "YYYY/MM/DD HH:MM:SS, YYYY/MM/DD, ISO-8601 (2023-05-20T18:00:00, 2023-05-20T18:00:00+02:00), \
+     now, today, yesterday 18:00 or a duration followed by ago (3 days ago, 1h30m ago)";
+
+/// Date times without an offset, which are interpreted in the local timezone
+const NAIVE_DATETIME_FORMATS: [&str; 8] = [
+    "%Y/%m/%d %H:%M:%S",
+    "%Y/%m/%d %H:%M",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y%m%dT%H%M%S",
+    "%Y%m%dT%H%M",
+];
+
+const NAIVE_DATE_FORMATS: [&str; 3] = ["%Y/%m/%d", "%Y-%m-%d", "%Y%m%d"];
+
+const OFFSET_DATETIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%dT%H:%M:%S%.f%z",
+    "%Y-%m-%d %H:%M:%S%.f%z",
+];
+
+const TIME_FORMATS: [&str; 2] = ["%H:%M:%S", "%H:%M"];
+
+/// Parse a duration written by a human into seconds. See [`DURATION_FORMATS`] for what is accepted
+pub fn string_to_duration(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+
+    let result = if trimmed.is_empty() {
+        Err("empty duration".to_string())
+    } else if trimmed.bytes().all(|byte| byte.is_ascii_digit()) {
+        trimmed.parse::<u64>().map_err(|e| e.to_string())
+    } else if trimmed.contains(':') {
+        parse_clock_duration(trimmed)
+    } else if trimmed.starts_with(['P', 'p']) {
+        parse_iso8601_duration(trimmed)
+    } else {
+        parse_unit_duration(trimmed)
+    };
+
+    result.map_err(|e| format!("invalid duration {input} -> {e}, expected {DURATION_FORMATS}"))
+}
+
+/// HH:MM:SS or H:MM
+fn parse_clock_duration(input: &str) -> Result<u64, String> {
+    let parts = input
+        .split(':')
+        .map(|part| {
+            part.trim()
+                .parse::<u64>()
+                .map_err(|e| format!("{part:?} -> {e}"))
+        })
+        .collect::<Result<Vec<u64>, String>>()?;
+
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes] => (hours, minutes, 0),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return Err("expected 2 or 3 parts separated by :".into()),
+    };
+
+    sum_seconds([(hours, 3600), (minutes, 60), (seconds, 1)])
+}
+
+/// PnWnDTnHnMnS. Years and months are rejected because their length in seconds is ambiguous
+fn parse_iso8601_duration(input: &str) -> Result<u64, String> {
+    let mut total = 0u64;
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut has_component = false;
+
+    for char in input[1..].chars() {
+        match char.to_ascii_uppercase() {
+            'T' if !in_time && number.is_empty() => in_time = true,
+            digit @ '0'..='9' => number.push(digit),
+            unit => {
+                let multiplier = match (unit, in_time) {
+                    ('W', false) => 7 * 86400,
+                    ('D', false) => 86400,
+                    ('H', true) => 3600,
+                    ('M', true) => 60,
+                    ('S', true) => 1,
+                    ('Y' | 'M', false) => {
+                        return Err("years and months have no fixed length".into())
+                    }
+                    _ => return Err(format!("unexpected {char:?}")),
+                };
+
+                let value = number
+                    .parse::<u64>()
+                    .map_err(|_| format!("missing number before {char:?}"))?;
+                number.clear();
+
+                total = sum_seconds([(total, 1), (value, multiplier)])?;
+                has_component = true;
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return Err(format!("missing unit after {number}"));
+    } else if !has_component {
+        return Err("no duration components".into());
+    }
+
+    Ok(total)
+}
+
+/// Numbers followed by units, e.g. 1h30m, 1d 2h, 1.5 hours or 3 days and 2 minutes
+fn parse_unit_duration(input: &str) -> Result<u64, String> {
+    let lowercase = input.to_lowercase();
+    let mut rest = lowercase.as_str();
+    let mut total = 0.0;
+
+    loop {
+        rest = rest.trim_start_matches(|char: char| char.is_whitespace() || char == ',');
+        rest = rest.strip_prefix("and ").unwrap_or(rest).trim_start();
+
+        if rest.is_empty() {
+            break;
+        }
+
+        let number_end = rest
+            .find(|char: char| !(char.is_ascii_digit() || char == '.'))
+            .unwrap_or(rest.len());
+        let (number, after_number) = rest.split_at(number_end);
+
+        let number = number
+            .parse::<f64>()
+            .map_err(|_| format!("expected a number at {rest:?}"))?;
+
+        let after_number = after_number.trim_start();
+        let unit_end = after_number
+            .find(|char: char| !char.is_ascii_alphabetic())
+            .unwrap_or(after_number.len());
+        let (unit, after_unit) = after_number.split_at(unit_end);
+
+        let multiplier = match unit {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            "d" | "day" | "days" => 86400.0,
+            "w" | "week" | "weeks" => 7.0 * 86400.0,
+            "" => return Err(format!("missing unit after {number}")),
+            _ => return Err(format!("unknown unit {unit:?}")),
+        };
+
+        total += number * multiplier;
+        rest = after_unit;
+    }
+
+    // Fractional amounts such as 1.5h may not add up to a whole second
+    if total.round() >= u64::MAX as f64 {
+        return Err("duration too large".into());
+    }
+
+    Ok(total.round() as u64)
+}
+
+fn sum_seconds<const N: usize>(parts: [(u64, u64); N]) -> Result<u64, String> {
+    parts
+        .into_iter()
+        .try_fold(0u64, |total, (value, multiplier)| {
+            value
+                .checked_mul(multiplier)
+                .and_then(|seconds| total.checked_add(seconds))
+        })
+        .ok_or_else(|| "duration too large".to_string())
+}
+
+/// Parse a date time written by a human. Anything without an explicit offset is interpreted in the local timezone
+/// and relative inputs such as "yesterday" or "3 days ago" are relative to `now`. See [`DATETIME_FORMATS`] for what
+/// is accepted
+pub fn parse_datetime(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    parse_relative_datetime(input.trim(), now)
+        .or_else(|| parse_absolute_datetime(input).ok())
+        .ok_or_else(|| format!("invalid date time {input} -> expected {DATETIME_FORMATS}"))
+}
+
+/// Parse a date time that does not depend on the current time
+pub fn parse_absolute_datetime(input: &str) -> Result<DateTime<Utc>, String> {
+    let trimmed = input.trim();
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(trimmed) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    for format in OFFSET_DATETIME_FORMATS {
+        if let Ok(date) = DateTime::parse_from_str(trimmed, format) {
+            return Ok(date.with_timezone(&Utc));
+        }
+    }
+
+    for format in NAIVE_DATETIME_FORMATS {
+        if let Ok(date) = NaiveDateTime::parse_from_str(trimmed, format) {
+            return Ok(local_to_utc(date));
+        }
+    }
+
+    for format in NAIVE_DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(trimmed, format) {
+            return Ok(local_to_utc(date.and_time(NaiveTime::MIN)));
+        }
+    }
+
+    Err(format!(
+        "invalid date time {input} -> expected {DATETIME_FORMATS}"
+    ))
+}
+
+fn parse_relative_datetime(input: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let lowercase = input.to_lowercase();
+
+    if lowercase == "now" {
+        return Some(now);
+    }
+
+    if let Some(duration) = lowercase.strip_suffix(" ago") {
+        let seconds = string_to_duration(duration).ok()?;
+        let duration = Duration::from_std(std::time::Duration::from_secs(seconds)).ok()?;
+        return now.checked_sub_signed(duration);
+    }
+
+    let (day, time) = lowercase
+        .split_once(char::is_whitespace)
+        .map_or((lowercase.as_str(), None), |(day, time)| {
+            (day, Some(time.trim()))
+        });
+
+    let days_back = match day {
+        "today" => 0,
+        "yesterday" => 1,
+        _ => return None,
+    };
+
+    let time = match time {
+        Some(time) => TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveTime::parse_from_str(time, format).ok())?,
+        None => NaiveTime::MIN,
+    };
+
+    let date = now.with_timezone(&Local).date_naive() - Duration::days(days_back);
+
+    Some(local_to_utc(date.and_time(time)))
+}
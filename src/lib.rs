@@ -1,50 +1,378 @@
-use std::path::PathBuf;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
 
+use chrono::{DateTime, FixedOffset, LocalResult, NaiveDateTime, TimeZone, Utc};
 use interprocess::local_socket::NameTypeSupport;
+use serde::{Deserialize, Serialize};
 
 pub mod client_utils;
+pub mod clock;
 pub mod commands;
+pub mod human_input;
 pub mod process_scanner;
 pub mod server_utils;
 pub mod structures;
 
+pub use human_input::{parse_datetime, string_to_duration};
+
 pub const ACTIVE_ICON: &str = "✅";
 pub const PAUSED_ICON: &str = "❌";
 pub const MIN_POLL_INTERVAL: u64 = 10;
+/// Single processes may be checked more often than all of them, e.g. short lived command line tools
+pub const MIN_PROCESS_POLL_INTERVAL: u64 = 1;
 pub const MIN_DURATION_UPDATE_INTERVAL: u64 = 1;
 pub const MIN_AUTOSAVE_INTERVAL: u64 = 60;
+pub const MIN_SYNC_INTERVAL: u64 = 60;
+
+/// The profile used when none is chosen, which keeps its data directly in the config dir
+pub const DEFAULT_PROFILE: &str = "default";
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Choose the profile for the rest of the program. Each profile has its own config, processes and socket,
+/// so several servers can run side by side. This can only be done once, before anything is read or saved
+pub fn set_profile(profile: &str) -> Result<(), String> {
+    let profile = parse_profile_name(profile)?;
+
+    PROFILE
+        .set(profile)
+        .map_err(|_| "profile is already set".to_string())
+}
+
+pub fn get_profile() -> &'static str {
+    PROFILE.get().map_or(DEFAULT_PROFILE, String::as_str)
+}
 
+/// Profile names become part of paths and socket names, so only letters, digits, - and _ are allowed
+pub fn parse_profile_name(input: &str) -> Result<String, String> {
+    if !input.is_empty()
+        && input
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_'))
+    {
+        Ok(input.into())
+    } else {
+        Err(format!(
+            "invalid profile {input} -> expected letters, digits, - and _"
+        ))
+    }
+}
+
+/// The environment variable that overrides where settings are kept
+pub const CONFIG_DIR_ENV_VAR: &str = "SPT_CONFIG_DIR";
+
+/// Files with tracking data, which used to be kept in the config dir next to the settings
+pub const DATA_FILES: [&str; 1] = ["processes.json"];
+
+/// Where the settings of the current profile are kept
 pub fn get_config_dir() -> Option<PathBuf> {
-    dirs::config_dir().map(|dir| dir.join("simple-process-tracker"))
+    get_profile_dir(get_profile())
+}
+
+pub fn get_profile_dir(profile: &str) -> Option<PathBuf> {
+    profile_dir_from(config_base_dir()?, profile)
+}
+
+/// Where the tracking data of the current profile is kept unless a data dir is set in the config, the environment or
+/// on launch, which are all read as settings of the config
+pub fn get_data_dir() -> Option<PathBuf> {
+    data_dir_from(
+        None,
+        None,
+        dirs::data_dir().map(|dir| dir.join("simple-process-tracker")),
+        get_profile(),
+    )
+}
+
+/// Pick the data dir of a profile. A directory set in the environment takes priority over the config, and the
+/// directory of the profile under the default data dir is used without either
+pub fn data_dir_from(
+    env_override: Option<PathBuf>,
+    config_override: Option<&Path>,
+    default_base_dir: Option<PathBuf>,
+    profile: &str,
+) -> Option<PathBuf> {
+    match (env_override, config_override) {
+        (Some(env_dir), _) => profile_dir_from(env_dir, profile),
+        (None, Some(config_dir)) => Some(config_dir.to_path_buf()),
+        (None, None) => profile_dir_from(default_base_dir?, profile),
+    }
+}
+
+fn profile_dir_from(base_dir: PathBuf, profile: &str) -> Option<PathBuf> {
+    if profile == DEFAULT_PROFILE {
+        Some(base_dir)
+    } else {
+        Some(base_dir.join("profiles").join(profile))
+    }
+}
+
+fn config_base_dir() -> Option<PathBuf> {
+    env_dir(CONFIG_DIR_ENV_VAR)
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("simple-process-tracker")))
+}
+
+/// A directory from the environment. Relative paths are ignored because they would change with the working dir
+fn env_dir(env_var: &str) -> Option<PathBuf> {
+    std::env::var_os(env_var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+/// Move the data files that are in one directory but not yet in another, e.g. from the config dir to the data dir.
+/// Returns the files that were moved
+pub fn migrate_data_files(from_dir: &Path, to_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut moved = vec![];
+
+    if from_dir == to_dir {
+        return Ok(moved);
+    }
+
+    for file_name in DATA_FILES {
+        let from = from_dir.join(file_name);
+        let to = to_dir.join(file_name);
+
+        if !from.is_file() || to.exists() {
+            continue;
+        }
+
+        std::fs::create_dir_all(to_dir)?;
+
+        // Renaming fails when the directories are on different file systems
+        if std::fs::rename(&from, &to).is_err() {
+            std::fs::copy(&from, &to)?;
+            std::fs::remove_file(&from)?;
+        }
+
+        moved.push(to);
+    }
+
+    Ok(moved)
+}
+
+/// Every profile that has saved data, starting with the default profile
+pub fn list_profiles() -> Vec<String> {
+    let mut profiles: Vec<String> = config_base_dir()
+        .and_then(|dir| std::fs::read_dir(dir.join("profiles")).ok())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|profile| profile != DEFAULT_PROFILE && parse_profile_name(profile).is_ok())
+        .collect();
+
+    profiles.sort();
+    profiles.insert(0, DEFAULT_PROFILE.into());
+
+    profiles
+}
+
+/// The name of this machine, used to tell apart the data of different machines when syncing
+pub fn get_host_name() -> String {
+    #[cfg(target_os = "linux")]
+    let host_name = std::fs::read_to_string("/proc/sys/kernel/hostname").ok();
+
+    #[cfg(not(target_os = "linux"))]
+    let host_name = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok();
+
+    host_name
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".into())
+}
+
+/// Write a file so that readers either see its old contents or all of its new contents, even if we crash midway.
+/// The data is first written to a temporary file next to the target, which then replaces the target
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name")
+    })?;
+
+    let mut temporary_name = std::ffi::OsString::from(".");
+    temporary_name.push(file_name);
+    temporary_name.push(format!(".{}.tmp", std::process::id()));
+    let temporary_path = path.with_file_name(temporary_name);
+
+    let result = (|| {
+        let mut file = std::fs::File::create(&temporary_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary_path, path)
+    })();
+
+    if result.is_err() {
+        _ = std::fs::remove_file(&temporary_path);
+    }
+
+    result
+}
+
+/// The environment variable that overrides where the server listens and where clients connect
+pub const SOCKET_ENV_VAR: &str = "SPT_SOCKET";
+
+/// Where clients connect. A name given on the command line takes priority over [`SOCKET_ENV_VAR`], and without either
+/// every user and profile gets their own socket, which is looked for in /tmp when the server was shared with others.
+/// Any user can create files in /tmp, so a socket there is only used when it belongs to the current user
+pub fn get_socket_name(socket_override: Option<&str>) -> Result<String, String> {
+    let private = get_server_socket_name(socket_override, false);
+    let shared = get_server_socket_name(socket_override, true);
+
+    if shared == private || Path::new(&private).exists() || !Path::new(&shared).exists() {
+        return Ok(private);
+    }
+
+    check_socket_owner(&shared)?;
+
+    Ok(shared)
 }
 
-pub fn get_socket_name() -> &'static str {
-    use NameTypeSupport::*;
+/// Make sure that a socket file was created by the current user rather than by another user pretending to be the
+/// server
+pub fn check_socket_owner(socket_name: &str) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::symlink_metadata(socket_name)
+            .map_err(|e| format!("cannot read socket {socket_name} -> {e}"))?;
+
+        if Some(metadata.uid()) != current_uid() {
+            return Err(format!(
+                "socket {socket_name} belongs to uid {} -> not connecting to a server of another user, pass --socket to connect anyway",
+                metadata.uid()
+            ));
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        _ = socket_name;
+    }
+
+    Ok(())
+}
+
+/// Where the server listens. The runtime dir can only be entered by its owner, so a server that other users may
+/// connect to listens in /tmp instead
+pub fn get_server_socket_name(socket_override: Option<&str>, shared: bool) -> String {
+    socket_name_from(
+        socket_override,
+        std::env::var(SOCKET_ENV_VAR).ok().as_deref(),
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|_| !shared),
+        NameTypeSupport::query(),
+        get_profile(),
+    )
+}
+
+/// See [`get_socket_name`]. Paths are preferred over namespaced names where both are supported, because only paths
+/// can be protected with file permissions
+pub fn socket_name_from(
+    socket_override: Option<&str>,
+    env_override: Option<&str>,
+    runtime_dir: Option<PathBuf>,
+    name_type_support: NameTypeSupport,
+    profile: &str,
+) -> String {
+    if let Some(name) = socket_override
+        .or(env_override)
+        .filter(|name| !name.is_empty())
+    {
+        return name.into();
+    }
+
+    // The default profile keeps the socket name it had before profiles existed
+    let profile_suffix = if profile == DEFAULT_PROFILE {
+        String::new()
+    } else {
+        format!("-{profile}")
+    };
 
-    match NameTypeSupport::query() {
-        OnlyPaths => "/tmp/simple-process-tracker.sock",
-        OnlyNamespaced | Both => "@simple-process-tracker.sock",
+    match name_type_support {
+        NameTypeSupport::OnlyNamespaced => {
+            format!(
+                "@simple-process-tracker-{}{profile_suffix}.sock",
+                user_suffix()
+            )
+        }
+        NameTypeSupport::OnlyPaths | NameTypeSupport::Both => {
+            match runtime_dir.filter(|dir| dir.is_absolute()) {
+                // The runtime dir belongs to the user alone, so the socket name does not have to be unique
+                Some(runtime_dir) => runtime_dir
+                    .join(format!("simple-process-tracker{profile_suffix}.sock"))
+                    .display()
+                    .to_string(),
+                None => format!(
+                    "/tmp/simple-process-tracker-{}{profile_suffix}.sock",
+                    user_suffix()
+                ),
+            }
+        }
     }
 }
 
-pub fn string_to_duration(input: &str) -> Result<u64, String> {
-    let parts: Vec<String> = input.split(':').map(|s| s.to_string()).collect();
+/// The effective user ID of the user running Simple process tracker, which owns the files and sockets it creates, on
+/// systems that have one
+pub fn current_uid() -> Option<u32> {
+    #[cfg(unix)]
+    // SAFETY: geteuid cannot fail and has no side effects
+    return Some(unsafe { libc::geteuid() });
+
+    #[cfg(not(unix))]
+    return None;
+}
+
+/// The name of the user with the given ID, if the system knows it
+pub fn user_name(uid: u32) -> Option<String> {
+    #[cfg(unix)]
+    {
+        // SAFETY: passwd is plain data, so all zeroes is a valid value
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; 4096];
 
-    if parts.len() != 3 {
-        return Err("invalid duration input".into());
+        // SAFETY: every pointer is valid for the given buffer length, and the name is only read when an entry was found
+        unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            );
+
+            if result.is_null() {
+                return None;
+            }
+
+            std::ffi::CStr::from_ptr(passwd.pw_name)
+                .to_str()
+                .ok()
+                .map(str::to_owned)
+        }
     }
 
-    let (hours, minutes, seconds) =
-        move || -> Result<(u64, u64, u64), Box<dyn std::error::Error>> {
-            Ok((
-                parts[0].parse::<u64>()?,
-                parts[1].parse::<u64>()?,
-                parts[2].parse::<u64>()?,
-            ))
-        }()
-        .map_err(|e| format!("invalid duration {input} -> {e}"))?;
+    #[cfg(not(unix))]
+    {
+        _ = uid;
+        None
+    }
+}
 
-    Ok(hours * 3600 + minutes * 60 + seconds)
+/// Something that tells apart the users of a machine, for use in shared names
+fn user_suffix() -> String {
+    match current_uid() {
+        Some(uid) => uid.to_string(),
+        None => std::env::var("USERNAME").unwrap_or_else(|_| "default".into()),
+    }
 }
 
 pub fn duration_to_string(input: u64) -> String {
@@ -55,6 +383,82 @@ pub fn duration_to_string(input: u64) -> String {
     format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
 }
 
+/// How durations are shown to the user
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DurationFormat {
+    /// 1409:32:00
+    #[default]
+    Clock,
+    /// 58d 17h 32m
+    Days,
+    /// 58 days, 17 hours
+    Human,
+    /// 1409.53h
+    Hours,
+    /// 5074320
+    Seconds,
+}
+
+pub fn format_duration(input: u64, format: DurationFormat) -> String {
+    match format {
+        DurationFormat::Clock => duration_to_string(input),
+        DurationFormat::Days => format!(
+            "{}d {:02}h {:02}m",
+            input / 86400,
+            input % 86400 / 3600,
+            input % 3600 / 60
+        ),
+        DurationFormat::Human => {
+            let units = [
+                (input / 86400, "day"),
+                (input % 86400 / 3600, "hour"),
+                (input % 3600 / 60, "minute"),
+                (input % 60, "second"),
+            ];
+
+            // Only the two most significant units are shown to keep long durations readable
+            let parts: Vec<String> = units
+                .iter()
+                .skip_while(|(amount, _)| *amount == 0)
+                .take(2)
+                .filter(|(amount, _)| *amount != 0)
+                .map(|(amount, unit)| {
+                    format!("{amount} {unit}{}", if *amount == 1 { "" } else { "s" })
+                })
+                .collect();
+
+            if parts.is_empty() {
+                "0 seconds".into()
+            } else {
+                parts.join(", ")
+            }
+        }
+        DurationFormat::Hours => format!("{:.2}h", input as f64 / 3600.0),
+        DurationFormat::Seconds => input.to_string(),
+    }
+}
+
+/// Show an amount of memory in the largest unit it has at least one of, e.g. 512 KiB, 80.5 MiB or 1.25 GiB
+pub fn format_kib(kib: u64) -> String {
+    match kib {
+        0..1024 => format!("{kib} KiB"),
+        1024..1_048_576 => format!("{:.1} MiB", kib as f64 / 1024.0),
+        _ => format!("{:.2} GiB", kib as f64 / 1_048_576.0),
+    }
+}
+
+/// Parse a CSV delimiter given as a single ASCII character or as "tab"
+pub fn parse_delimiter(input: &str) -> Result<u8, String> {
+    match input {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if input.len() == 1 && input.is_ascii() => Ok(input.as_bytes()[0]),
+        _ => Err(format!(
+            "invalid delimiter {input} -> expected a single ASCII character or tab"
+        )),
+    }
+}
+
 pub fn parse_range(input: &str) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
     let mut output = vec![];
 
@@ -81,7 +485,67 @@ pub fn parse_range(input: &str) -> Result<Vec<usize>, Box<dyn std::error::Error>
     Ok(output)
 }
 
-pub fn parse_datetime(input: &str) -> Result<chrono::NaiveDateTime, String> {
-    chrono::NaiveDateTime::parse_from_str(input, "%Y/%m/%d %H:%M:%S")
-        .map_err(|e| format!("invalid date time {input} -> {e}"))
+/// Convert a date time without timezone information to UTC by assuming it is in the system's local timezone.
+/// A local time that happens twice because clocks were turned back resolves to the first occurrence, while a
+/// local time that was skipped because clocks were turned forward is moved forward by an hour
+pub fn local_to_utc(input: NaiveDateTime) -> DateTime<Utc> {
+    match chrono::Local.from_local_datetime(&input) {
+        LocalResult::Single(date) | LocalResult::Ambiguous(date, _) => date.with_timezone(&Utc),
+        LocalResult::None => chrono::Local
+            .from_local_datetime(&(input + chrono::Duration::hours(1)))
+            .earliest()
+            .map(|date| date.with_timezone(&Utc))
+            .unwrap_or_else(|| Utc.from_utc_datetime(&input)),
+    }
+}
+
+/// The timezone in which dates are shown to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayTimezone {
+    Local,
+    Fixed(FixedOffset),
+    Named(chrono_tz::Tz),
+}
+
+impl FromStr for DisplayTimezone {
+    type Err = String;
+
+    /// Accepts "local", "UTC", offsets like "+02:00" or "-0530" and IANA names like "Europe/Sofia"
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.eq_ignore_ascii_case("local") {
+            return Ok(Self::Local);
+        }
+
+        if input.eq_ignore_ascii_case("utc") || input.eq_ignore_ascii_case("z") {
+            return Ok(Self::Fixed(
+                FixedOffset::east_opt(0).expect("0 is in range"),
+            ));
+        }
+
+        if input.starts_with(['+', '-']) {
+            // FixedOffset has no parser of its own, so we parse the offset together with a dummy date
+            return DateTime::parse_from_str(
+                &format!("2000-01-01 00:00 {input}"),
+                "%Y-%m-%d %H:%M %z",
+            )
+            .map(|date| Self::Fixed(*date.offset()))
+            .map_err(|e| format!("invalid timezone offset {input} -> {e}"));
+        }
+
+        input
+            .parse::<chrono_tz::Tz>()
+            .map(Self::Named)
+            .map_err(|e| format!("invalid timezone {input} -> {e}"))
+    }
+}
+
+pub fn format_datetime(input: &DateTime<Utc>, timezone: DisplayTimezone) -> String {
+    const FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+
+    match timezone {
+        DisplayTimezone::Local => input.with_timezone(&chrono::Local).format(FORMAT),
+        DisplayTimezone::Fixed(offset) => input.with_timezone(&offset).format(FORMAT),
+        DisplayTimezone::Named(tz) => input.with_timezone(&tz).format(FORMAT),
+    }
+    .to_string()
 }
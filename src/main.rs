@@ -1,9 +1,12 @@
+use std::collections::{HashMap, HashSet};
+
 use clap::Parser;
 use simple_process_tracker_rs::{
     client_utils::client,
     commands::{self, Commands},
-    process_scanner::get_running_processes,
+    process_scanner::{Cgroup, ProcessScanner, SystemScanner},
     server_utils::server,
+    set_profile, user_name,
 };
 
 #[derive(Parser, Debug)]
@@ -16,34 +19,116 @@ use simple_process_tracker_rs::{
 struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// The socket the server listens on and clients connect to. Names starting with @ are namespaced.
+    /// Defaults to $SPT_SOCKET or a socket of the current user
+    #[arg(long, global = true)]
+    socket: Option<String>,
+    /// Use a separate config, process list and socket, e.g. to track work and personal use apart
+    #[arg(long, global = true, value_parser = simple_process_tracker_rs::parse_profile_name)]
+    profile: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
+    if let Some(profile) = &args.profile {
+        set_profile(profile).expect("profile is only set once");
+    }
+
     match args.command.unwrap_or(Commands::View(commands::View {
         ids: None,
         debug: false,
+        tz: None,
+        duration_format: None,
+        host: None,
+        columns: vec![],
+        rank: None,
     })) {
-        Commands::Launch => server::launch().await,
-        Commands::Processes => show_processes().await,
-        cmd => client::handle_user_command(cmd).await,
+        Commands::Launch(launch_cmd) => server::launch(launch_cmd, args.socket.as_deref()).await,
+        Commands::Processes(processes_cmd) => show_processes(processes_cmd),
+        Commands::Profiles => client::show_profiles().await,
+        cmd => client::handle_user_command(cmd, args.socket.as_deref()).await,
     }
 }
 
-async fn show_processes() {
-    match get_running_processes().await {
+fn show_processes(processes_cmd: commands::Processes) {
+    match SystemScanner::default().scan() {
         Ok(process_list) => {
-            let mut sorted_process_list: Vec<&str> =
-                process_list.iter().map(|name| name.as_str()).collect();
+            let mut user_names = HashMap::new();
+
+            // Many processes can run under the same name, but each name only needs to be shown once, or once per user
+            // and cgroup
+            let process_rows: HashSet<(&str, String, String)> = process_list
+                .iter()
+                .map(|process| {
+                    let user = match process.uid.filter(|_| processes_cmd.users) {
+                        Some(uid) => user_names
+                            .entry(uid)
+                            .or_insert_with(|| user_name(uid).unwrap_or_else(|| uid.to_string()))
+                            .clone(),
+                        None if processes_cmd.users => "?".into(),
+                        None => String::new(),
+                    };
+
+                    let cgroup = match process.cgroup.as_ref().filter(|_| processes_cmd.cgroups) {
+                        Some(cgroup) => cgroup_attributes(cgroup),
+                        None => String::new(),
+                    };
+
+                    (process.name.as_str(), user, cgroup)
+                })
+                .collect();
+
+            let mut sorted_process_list: Vec<(&str, String, String)> =
+                process_rows.into_iter().collect();
 
-            sorted_process_list.sort_by_key(|name| name.to_lowercase());
+            sorted_process_list.sort_by_key(|(name, user, cgroup)| {
+                (name.to_lowercase(), user.clone(), cgroup.clone())
+            });
 
-            for process in sorted_process_list {
-                println!("{process}");
+            let name_width = sorted_process_list
+                .iter()
+                .map(|(name, _, _)| name.len())
+                .max()
+                .unwrap_or_default();
+            let user_width = sorted_process_list
+                .iter()
+                .map(|(_, user, _)| user.len())
+                .max()
+                .unwrap_or_default();
+
+            for (process, user, cgroup) in sorted_process_list {
+                let mut line = format!("{process:<name_width$}");
+
+                if processes_cmd.users {
+                    line += &format!(" {user:<user_width$}");
+                }
+
+                if processes_cmd.cgroups {
+                    line += &format!(" {cgroup}");
+                }
+
+                println!("{}", line.trim_end());
             }
         }
         Err(e) => eprintln!("{e}"),
     }
 }
+
+/// The attributes of a cgroup that processes can be matched by, e.g. unit=app-firefox.scope flatpak=org.mozilla.firefox
+fn cgroup_attributes(cgroup: &Cgroup) -> String {
+    [
+        ("unit", &cgroup.unit),
+        ("flatpak", &cgroup.flatpak_app),
+        // Container IDs are shortened the same way Docker and Podman show them
+        (
+            "container",
+            &cgroup.container_id.as_ref().map(|id| id[..12].to_owned()),
+        ),
+    ]
+    .iter()
+    .filter_map(|(key, value)| Some(format!("{key}={}", value.as_ref()?)))
+    .collect::<Vec<String>>()
+    .join(" ")
+}
@@ -0,0 +1,132 @@
+/// Where a process runs according to its cgroup, which tells apart apps that are started by the same generic launcher
+/// such as bwrap or containerd-shim
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cgroup {
+    /// The path of the cgroup, e.g. /user.slice/user-1000.slice/user@1000.service/app.slice/app-firefox.scope
+    pub path: String,
+    /// The systemd service or scope the process runs in
+    pub unit: Option<String>,
+    /// The ID of the Flatpak app the process belongs to, e.g. org.mozilla.firefox
+    pub flatpak_app: Option<String>,
+    /// The ID of the Docker, Podman or Kubernetes container the process runs in
+    pub container_id: Option<String>,
+}
+
+impl Cgroup {
+    pub fn from_path(path: &str) -> Self {
+        let components = || path.split('/').filter(|component| !component.is_empty());
+
+        let unit = components()
+            .rev()
+            .find(|component| component.ends_with(".service") || component.ends_with(".scope"))
+            .map(str::to_owned);
+
+        // Flatpak starts every app in a scope such as app-flatpak-org.mozilla.firefox-12345.scope
+        let flatpak_app = components().rev().find_map(|component| {
+            let app = component
+                .strip_prefix("app-flatpak-")?
+                .strip_suffix(".scope")?;
+            let app = app
+                .rsplit_once('-')
+                .filter(|(_, instance)| instance.bytes().all(|byte| byte.is_ascii_digit()))
+                .map_or(app, |(app, _)| app);
+            Some(app.to_owned())
+        });
+
+        // The systemd driver puts containers in scopes such as docker-<ID>.scope, and the cgroupfs driver in a cgroup
+        // named after the bare ID
+        let container_id = components().rev().find_map(|component| {
+            let id = component.strip_suffix(".scope").unwrap_or(component);
+            let id = ["docker-", "libpod-", "cri-containerd-", "crio-"]
+                .iter()
+                .find_map(|prefix| id.strip_prefix(prefix))
+                .unwrap_or(id);
+
+            (id.len() == 64 && id.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .then(|| id.to_owned())
+        });
+
+        Self {
+            path: path.to_owned(),
+            unit,
+            flatpak_app,
+            container_id,
+        }
+    }
+
+    /// Read the contents of a /proc/<pid>/cgroup file. The systemd hierarchy is used on systems that still mount
+    /// cgroup v1, and the unified one otherwise
+    pub fn from_proc_file(contents: &str) -> Option<Self> {
+        let paths: Vec<(&str, &str)> = contents
+            .lines()
+            .filter_map(|line| {
+                let (_, rest) = line.split_once(':')?;
+                rest.split_once(':')
+            })
+            .collect();
+
+        let (_, path) = paths
+            .iter()
+            .find(|(controllers, _)| *controllers == "name=systemd")
+            .or_else(|| paths.iter().find(|(controllers, _)| controllers.is_empty()))
+            .or_else(|| paths.first())?;
+
+        Some(Self::from_path(path))
+    }
+
+    /// Whether the path, the unit, the Flatpak app ID or the container ID matches a glob pattern
+    pub fn matches(&self, pattern: &str) -> bool {
+        [
+            Some(&self.path),
+            self.unit.as_ref(),
+            self.flatpak_app.as_ref(),
+            self.container_id.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|text| glob_matches(pattern, text))
+    }
+}
+
+/// Match a pattern where * stands for any number of characters, including none, and ? for exactly one
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    // Matching bytes keeps this free of allocations, as it runs for every process on every scan. Literal characters
+    // match byte by byte, but ? and backtracking have to step over whole characters
+    let pattern_bytes = pattern.as_bytes();
+    let text_bytes = text.as_bytes();
+    let char_len = |index: usize| text[index..].chars().next().map_or(1, char::len_utf8);
+
+    let (mut pattern_index, mut text_index) = (0, 0);
+    // Where the last * was and the text position it matches up to, to backtrack to when the rest does not match
+    let mut backtrack = None;
+
+    while text_index < text_bytes.len() {
+        match pattern_bytes.get(pattern_index) {
+            Some(b'*') => {
+                backtrack = Some((pattern_index, text_index));
+                pattern_index += 1;
+            }
+            Some(b'?') => {
+                pattern_index += 1;
+                text_index += char_len(text_index);
+            }
+            Some(&byte) if byte == text_bytes[text_index] => {
+                pattern_index += 1;
+                text_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_index, star_text_index)) => {
+                    let next_text_index = star_text_index + char_len(star_text_index);
+                    pattern_index = star_index + 1;
+                    text_index = next_text_index;
+                    backtrack = Some((star_index, next_text_index));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern_bytes[pattern_index..]
+        .iter()
+        .all(|byte| *byte == b'*')
+}
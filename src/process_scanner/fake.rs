@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{Cgroup, ProcessEvent, ProcessScanner, ProcessSnapshot, ResourceUsage};
+
+/// Which processes the fake system reports as they start or exit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FakeEvents {
+    /// Nothing is reported, so processes are only found by scanning
+    #[default]
+    None,
+    /// Only the exits of processes that are watched one by one are reported
+    Exits,
+    /// Every process that starts or exits is reported
+    All,
+}
+
+#[derive(Debug, Default)]
+struct FakeSystem {
+    processes: Vec<ProcessSnapshot>,
+    next_pid: u32,
+    queued_errors: VecDeque<String>,
+    scan_count: usize,
+    events: FakeEvents,
+    all_watcher: Option<UnboundedSender<ProcessEvent>>,
+    exit_watchers: Vec<(u32, UnboundedSender<ProcessEvent>)>,
+    names: Option<HashSet<String>>,
+    cgroup_patterns: Vec<String>,
+    usage: HashMap<u32, ResourceUsage>,
+}
+
+impl FakeSystem {
+    fn report_exit(&mut self, pid: u32) {
+        if let Some(all_watcher) = &self.all_watcher {
+            _ = all_watcher.send(ProcessEvent::Exited { pid });
+        }
+
+        self.exit_watchers.retain(|(watched_pid, exit_watcher)| {
+            if *watched_pid == pid {
+                _ = exit_watcher.send(ProcessEvent::Exited { pid });
+            }
+            *watched_pid != pid
+        });
+    }
+}
+
+/// An in-memory scanner whose running processes are controlled by the caller. Clones share the same
+/// fake system, so a test can keep one handle while another is moved into a background task
+#[derive(Debug, Clone, Default)]
+pub struct FakeScanner {
+    system: Arc<Mutex<FakeSystem>>,
+}
+
+impl FakeScanner {
+    /// Start a new instance of a process as the current user and return its PID
+    pub fn spawn(&self, name: &str) -> u32 {
+        self.spawn_as(name, crate::current_uid())
+    }
+
+    /// Start a new instance of a process owned by the user with the given ID and return its PID
+    pub fn spawn_as(&self, name: &str, uid: Option<u32>) -> u32 {
+        self.spawn_snapshot(name, uid, None)
+    }
+
+    /// Start a new instance of a process as the current user in the cgroup with the given path and return its PID
+    pub fn spawn_in_cgroup(&self, name: &str, cgroup_path: &str) -> u32 {
+        self.spawn_snapshot(
+            name,
+            crate::current_uid(),
+            Some(Cgroup::from_path(cgroup_path)),
+        )
+    }
+
+    fn spawn_snapshot(&self, name: &str, uid: Option<u32>, cgroup: Option<Cgroup>) -> u32 {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+
+        system.next_pid += 1;
+        let pid = system.next_pid;
+
+        let process = ProcessSnapshot {
+            pid,
+            name: name.to_owned(),
+            uid,
+            cgroup,
+        };
+
+        if let Some(all_watcher) = &system.all_watcher {
+            _ = all_watcher.send(ProcessEvent::Executed(process.clone()));
+        }
+
+        system.processes.push(process);
+
+        pid
+    }
+
+    /// Stop the process with the given PID
+    pub fn kill(&self, pid: u32) {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+        system.processes.retain(|process| process.pid != pid);
+        system.report_exit(pid);
+    }
+
+    /// Stop every instance of the process with the given name
+    pub fn kill_all(&self, name: &str) {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+
+        let pids: Vec<u32> = system
+            .processes
+            .iter()
+            .filter(|process| process.name == name)
+            .map(|process| process.pid)
+            .collect();
+
+        system.processes.retain(|process| process.name != name);
+
+        for pid in pids {
+            system.report_exit(pid);
+        }
+    }
+
+    /// Make the next scan fail with the given message instead of returning the running processes
+    pub fn fail_next_scan(&self, message: &str) {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+        system.queued_errors.push_back(message.to_owned());
+    }
+
+    /// How many times the fake system was scanned, including failed scans
+    pub fn scan_count(&self) -> usize {
+        self.system
+            .lock()
+            .expect("fake system lock poisoned")
+            .scan_count
+    }
+
+    /// Set how much CPU time and memory the process with the given PID has used so far
+    pub fn set_usage(&self, pid: u32, usage: ResourceUsage) {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+        system.usage.insert(pid, usage);
+    }
+
+    /// Stop reporting the processes that start or exit, like when the system's event stream fails
+    pub fn stop_events(&self) {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+
+        if let Some(all_watcher) = system.all_watcher.take() {
+            _ = all_watcher.send(ProcessEvent::Stopped);
+        }
+    }
+
+    /// Choose which processes are reported as they start or exit. Nothing is reported by default
+    pub fn report_events(&self, events: FakeEvents) {
+        self.system
+            .lock()
+            .expect("fake system lock poisoned")
+            .events = events;
+    }
+}
+
+impl ProcessScanner for FakeScanner {
+    fn scan(&mut self) -> Result<Vec<ProcessSnapshot>, Box<dyn Error + Send + Sync>> {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+        system.scan_count += 1;
+
+        if let Some(message) = system.queued_errors.pop_front() {
+            return Err(message.into());
+        }
+
+        Ok(system
+            .processes
+            .iter()
+            .filter(|process| {
+                system
+                    .names
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&process.name))
+                    || process.cgroup.as_ref().is_some_and(|cgroup| {
+                        system
+                            .cgroup_patterns
+                            .iter()
+                            .any(|pattern| cgroup.matches(pattern))
+                    })
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn filter_names(&mut self, names: HashSet<String>) {
+        self.system.lock().expect("fake system lock poisoned").names = Some(names);
+    }
+
+    fn filter_cgroups(&mut self, patterns: Vec<String>) {
+        self.system
+            .lock()
+            .expect("fake system lock poisoned")
+            .cgroup_patterns = patterns;
+    }
+
+    fn usage(&mut self, pid: u32) -> Option<ResourceUsage> {
+        let system = self.system.lock().expect("fake system lock poisoned");
+
+        system
+            .processes
+            .iter()
+            .any(|process| process.pid == pid)
+            .then(|| system.usage.get(&pid).copied().unwrap_or_default())
+    }
+
+    fn watch_all(
+        &mut self,
+        events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+
+        if system.events != FakeEvents::All {
+            return Err("permission denied".into());
+        }
+
+        system.all_watcher = Some(events);
+        Ok(())
+    }
+
+    fn watch_exit(
+        &mut self,
+        pid: u32,
+        events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut system = self.system.lock().expect("fake system lock poisoned");
+
+        if system.events == FakeEvents::None {
+            return Err("permission denied".into());
+        }
+
+        // Like a pidfd, watching a process that already exited reports its exit right away
+        if system.processes.iter().any(|process| process.pid == pid) {
+            system.exit_watchers.push((pid, events));
+        } else {
+            _ = events.send(ProcessEvent::Exited { pid });
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    ffi::{CStr, CString, OsStr},
+    fs::File,
+    io::{self, Read},
+    os::{
+        fd::{FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use tokio::{
+    io::{unix::AsyncFd, Interest},
+    sync::mpsc::UnboundedSender,
+};
+
+use super::{proc_connector, Cgroup, ProcessEvent, ProcessScanner, ProcessSnapshot, ResourceUsage};
+
+/// Scans the processes running on the system through procfs. Buffers are kept between scans, and processes whose names
+/// are not wanted are skipped without copying anything
+#[derive(Debug)]
+pub struct SystemScanner {
+    /// Where procfs is mounted, which is only changed to scan a copy of it
+    proc_dir: PathBuf,
+    /// The path of the comm, status or cgroup file being read. The part before the PID is the same for every process
+    comm_path: Vec<u8>,
+    /// Shared with the thread that reads the processes the system reports starting
+    filter: Arc<RwLock<ProcessFilter>>,
+    /// How many processes the last scan returned, to make room for as many in the next one
+    last_len: usize,
+    /// Holds the stat and status files of a process while they are read
+    file_buffer: Vec<u8>,
+}
+
+/// Which processes are wanted, by name or by cgroup
+#[derive(Debug, Default)]
+pub(super) struct ProcessFilter {
+    names: Option<HashSet<String>>,
+    cgroup_patterns: Vec<String>,
+}
+
+impl ProcessFilter {
+    pub(super) fn wants_name(&self, name: &str) -> bool {
+        self.names.as_ref().is_none_or(|names| names.contains(name))
+    }
+
+    /// Whether processes may be wanted for their cgroup, which then has to be read for every process
+    pub(super) fn has_cgroup_patterns(&self) -> bool {
+        !self.cgroup_patterns.is_empty()
+    }
+
+    pub(super) fn wants_cgroup(&self, cgroup: &Cgroup) -> bool {
+        self.cgroup_patterns
+            .iter()
+            .any(|pattern| cgroup.matches(pattern))
+    }
+}
+
+impl Default for SystemScanner {
+    fn default() -> Self {
+        Self::with_proc_dir("/proc")
+    }
+}
+
+impl SystemScanner {
+    /// Scan a procfs tree mounted somewhere other than /proc
+    pub fn with_proc_dir(proc_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            proc_dir: proc_dir.into(),
+            comm_path: Vec::with_capacity(64),
+            filter: Default::default(),
+            last_len: 300,
+            file_buffer: Vec::with_capacity(4096),
+        }
+    }
+
+    /// Read a file in the process dir at the start of the path buffer into the file buffer
+    fn read_file_in(&mut self, pid_dir_len: usize, file_name: &[u8]) -> Option<&[u8]> {
+        self.comm_path.truncate(pid_dir_len);
+        self.comm_path.extend_from_slice(file_name);
+
+        self.file_buffer.clear();
+        File::open(OsStr::from_bytes(&self.comm_path))
+            .ok()?
+            .read_to_end(&mut self.file_buffer)
+            .ok()?;
+
+        Some(&self.file_buffer)
+    }
+
+    /// Read a file of the process with the given PID into the file buffer
+    fn read_process_file(&mut self, pid: u32, file_name: &str) -> Option<&[u8]> {
+        let path = self.proc_dir.join(pid.to_string()).join(file_name);
+
+        self.file_buffer.clear();
+        File::open(path)
+            .ok()?
+            .read_to_end(&mut self.file_buffer)
+            .ok()?;
+
+        Some(&self.file_buffer)
+    }
+}
+
+impl ProcessScanner for SystemScanner {
+    fn scan(&mut self) -> Result<Vec<ProcessSnapshot>, Box<dyn Error + Send + Sync>> {
+        let mut dir = Dir::open(&self.proc_dir)
+            .map_err(|e| format!("error reading {} -> {e}", self.proc_dir.display()))?;
+
+        self.comm_path.clear();
+        self.comm_path
+            .extend_from_slice(self.proc_dir.as_os_str().as_bytes());
+        self.comm_path.push(b'/');
+        let prefix_len = self.comm_path.len();
+
+        let filter = Arc::clone(&self.filter);
+        let filter = filter.read().unwrap_or_else(PoisonError::into_inner);
+
+        let mut process_list = Vec::with_capacity(self.last_len);
+        // Names are at most 15 bytes long, followed by a newline
+        let mut comm = [0u8; 64];
+
+        while let Some(entry) = dir.next_name() {
+            // Skip entries such as /proc/self or /proc/sys that do not belong to a process
+            let Some(pid) = parse_pid(entry) else {
+                continue;
+            };
+
+            self.comm_path.truncate(prefix_len);
+            self.comm_path.extend_from_slice(entry);
+            let pid_dir_len = self.comm_path.len();
+            self.comm_path.extend_from_slice(b"/comm");
+
+            // The process may have exited since we read /proc, in which case we simply skip it
+            let Some(name) = read_comm(OsStr::from_bytes(&self.comm_path), &mut comm) else {
+                continue;
+            };
+
+            let is_wanted_name = filter.wants_name(name);
+
+            if !is_wanted_name && !filter.has_cgroup_patterns() {
+                continue;
+            }
+
+            // The cgroup and the owner are only read for the processes that may be wanted
+            let cgroup = self
+                .read_file_in(pid_dir_len, b"/cgroup")
+                .and_then(|contents| std::str::from_utf8(contents).ok())
+                .and_then(Cgroup::from_proc_file);
+
+            if !is_wanted_name
+                && !cgroup
+                    .as_ref()
+                    .is_some_and(|cgroup| filter.wants_cgroup(cgroup))
+            {
+                continue;
+            }
+
+            let uid = self
+                .read_file_in(pid_dir_len, b"/status")
+                .and_then(parse_uid);
+
+            process_list.push(ProcessSnapshot {
+                pid,
+                name: name.to_owned(),
+                uid,
+                cgroup,
+            });
+        }
+
+        self.last_len = process_list.len();
+
+        Ok(process_list)
+    }
+
+    fn filter_names(&mut self, names: HashSet<String>) {
+        self.filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .names = Some(names);
+    }
+
+    fn filter_cgroups(&mut self, patterns: Vec<String>) {
+        self.filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .cgroup_patterns = patterns;
+    }
+
+    fn usage(&mut self, pid: u32) -> Option<ResourceUsage> {
+        let cpu_ticks = parse_cpu_ticks(self.read_process_file(pid, "stat")?)?;
+        // Kernel threads have no memory of their own, so their status has no VmRSS
+        let rss_kib = parse_rss_kib(self.read_process_file(pid, "status")?).unwrap_or(0);
+
+        Some(ResourceUsage {
+            cpu_millis: cpu_ticks.saturating_mul(1000) / clock_ticks_per_second(),
+            rss_kib,
+        })
+    }
+
+    fn watch_all(
+        &mut self,
+        events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        proc_connector::subscribe(events, self.proc_dir.clone(), Arc::clone(&self.filter))
+            .map_err(|e| format!("cannot subscribe to the proc connector -> {e}").into())
+    }
+
+    fn watch_exit(
+        &mut self,
+        pid: u32,
+        events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // SAFETY: pidfd_open has no memory safety requirements, and the returned descriptor is checked before it is owned
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+
+        if fd < 0 {
+            let error = io::Error::last_os_error();
+
+            // The process exited since it was scanned
+            if error.raw_os_error() == Some(libc::ESRCH) {
+                _ = events.send(ProcessEvent::Exited { pid });
+                return Ok(());
+            }
+
+            return Err(format!("cannot open a pidfd for {pid} -> {error}").into());
+        }
+
+        // SAFETY: fd is a valid descriptor that nothing else owns
+        let pidfd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+        let pidfd = AsyncFd::with_interest(pidfd, Interest::READABLE)?;
+
+        // A pidfd becomes readable once its process exits
+        tokio::spawn(async move {
+            if pidfd.readable().await.is_ok() {
+                _ = events.send(ProcessEvent::Exited { pid });
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// A directory read with readdir, which lends out the name of each entry instead of copying it like
+/// [`std::fs::read_dir`]
+struct Dir(NonNull<libc::DIR>);
+
+impl Dir {
+    fn open(path: &Path) -> io::Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+
+        // SAFETY: path is a valid nul terminated string
+        let dir = unsafe { libc::opendir(path.as_ptr()) };
+
+        NonNull::new(dir)
+            .map(Self)
+            .ok_or_else(io::Error::last_os_error)
+    }
+
+    fn next_name(&mut self) -> Option<&[u8]> {
+        // SAFETY: the directory stays open as long as self
+        let entry = unsafe { libc::readdir(self.0.as_ptr()) };
+
+        if entry.is_null() {
+            return None;
+        }
+
+        // SAFETY: d_name is a nul terminated string in the entry, which stays valid until the next readdir call, which
+        // needs the borrow of self to end first
+        Some(unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes())
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        // SAFETY: the directory was opened by opendir and is not used again
+        unsafe { libc::closedir(self.0.as_ptr()) };
+    }
+}
+
+fn parse_pid(name: &[u8]) -> Option<u32> {
+    if name.is_empty() || !name.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(name).ok()?.parse().ok()
+}
+
+/// Read a comm file into the buffer and return the name in it
+fn read_comm<'a>(path: &OsStr, buffer: &'a mut [u8]) -> Option<&'a str> {
+    let len = File::open(path).ok()?.read(buffer).ok()?;
+
+    std::str::from_utf8(&buffer[..len]).ok().map(str::trim)
+}
+
+/// The user and system CPU time in a stat file, in clock ticks. These are the 14th and 15th fields, counted after the
+/// name in parentheses since the name may contain spaces
+fn parse_cpu_ticks(stat: &[u8]) -> Option<u64> {
+    let after_name = &stat[stat.iter().rposition(|byte| *byte == b')')? + 1..];
+    let mut fields = std::str::from_utf8(after_name)
+        .ok()?
+        .split_ascii_whitespace();
+
+    let user_ticks: u64 = fields.nth(11)?.parse().ok()?;
+    let system_ticks: u64 = fields.next()?.parse().ok()?;
+
+    Some(user_ticks + system_ticks)
+}
+
+/// The real user ID on the Uid line of a status file, which is followed by the effective, saved and filesystem ones
+pub(super) fn parse_uid(status: &[u8]) -> Option<u32> {
+    std::str::from_utf8(status)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_ascii_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// The VmRSS line of a status file, which is given in kB
+fn parse_rss_kib(status: &[u8]) -> Option<u64> {
+    std::str::from_utf8(status)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn clock_ticks_per_second() -> u64 {
+    // SAFETY: sysconf has no memory safety requirements
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+
+    if ticks > 0 {
+        ticks as u64
+    } else {
+        100
+    }
+}
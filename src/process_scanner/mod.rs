@@ -0,0 +1,104 @@
+use std::{collections::HashSet, error::Error};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+mod cgroup;
+#[cfg(any(test, feature = "test-util"))]
+mod fake;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+mod proc_connector;
+mod scan_thread;
+#[cfg(target_os = "windows")]
+mod windows;
+
+pub use cgroup::{glob_matches, Cgroup};
+#[cfg(any(test, feature = "test-util"))]
+pub use fake::{FakeEvents, FakeScanner};
+#[cfg(target_os = "linux")]
+pub use linux::SystemScanner;
+pub use scan_thread::ScanThread;
+#[cfg(target_os = "windows")]
+pub use windows::SystemScanner;
+
+#[cfg(target_os = "macos")]
+compile_error!("macos not yet implemented");
+
+/// A single process seen during a scan of the system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessSnapshot {
+    pub pid: u32,
+    /// The name Simple process tracker uses to check if a tracked process is running
+    pub name: String,
+    /// The real user ID of the owner, if the system has one
+    pub uid: Option<u32>,
+    /// The cgroup the process runs in, if the system has cgroups
+    pub cgroup: Option<Cgroup>,
+}
+
+/// What a single running process has used of the system's resources
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// CPU time used since the process started, in milliseconds
+    pub cpu_millis: u64,
+    /// Resident memory in KiB
+    pub rss_kib: u64,
+}
+
+/// A process starting or exiting, reported by the system as it happens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessEvent {
+    /// A process started a copy of itself, which has the same name until it runs another program
+    Forked {
+        parent: u32,
+        child: u32,
+    },
+    /// A process started running a program, so it may have a new name
+    Executed(ProcessSnapshot),
+    Exited {
+        pid: u32,
+    },
+    /// The system reported more events than could be read, so some were dropped and a full scan is needed
+    Lost,
+    /// The system stopped reporting events, so processes have to be found by scanning again
+    Stopped,
+}
+
+pub trait ProcessScanner: Send {
+    /// Return a snapshot of every process currently running on the system
+    fn scan(&mut self) -> Result<Vec<ProcessSnapshot>, Box<dyn Error + Send + Sync>>;
+
+    /// Only return the processes with one of the given names from now on. Every process is returned until this is
+    /// called
+    fn filter_names(&mut self, names: HashSet<String>);
+
+    /// Also report the processes whose cgroup matches one of these glob patterns, whatever their name. Systems without
+    /// cgroups never report any
+    fn filter_cgroups(&mut self, _patterns: Vec<String>) {}
+
+    /// Read how much CPU time and memory the running process with the given PID has used. Returns None when the process
+    /// exited or the scanner cannot tell
+    fn usage(&mut self, _pid: u32) -> Option<ResourceUsage> {
+        None
+    }
+
+    /// Start sending every process that starts or exits to the given channel as it happens. Scanners that cannot do
+    /// this return an error, in which case new processes are only found by scanning
+    fn watch_all(
+        &mut self,
+        _events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("not supported on this system".into())
+    }
+
+    /// Send the exit of the process with the given PID to the given channel as it happens, for when
+    /// [`ProcessScanner::watch_all`] is not available
+    fn watch_exit(
+        &mut self,
+        _pid: u32,
+        _events: UnboundedSender<ProcessEvent>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        Err("not supported on this system".into())
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    linux::{self, ProcessFilter},
+    Cgroup, ProcessEvent, ProcessSnapshot,
+};
+
+// From linux/connector.h and linux/cn_proc.h, which the libc crate does not include
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+const PROC_EVENT_NONE: u32 = 0;
+const PROC_EVENT_FORK: u32 = 0x1;
+const PROC_EVENT_EXEC: u32 = 0x2;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+const NLMSG_HEADER_LEN: usize = 16;
+const CN_MSG_HEADER_LEN: usize = 20;
+/// Where the event data starts, after the netlink header, the connector header and the event's type, CPU and timestamp
+const EVENT_DATA_OFFSET: usize = NLMSG_HEADER_LEN + CN_MSG_HEADER_LEN + 16;
+
+/// How long to wait for the kernel to confirm the subscription
+const SUBSCRIBE_TIMEOUT_MS: i32 = 1000;
+/// How often the events thread checks whether the channel was closed while no events arrive
+const CLOSED_CHECK_INTERVAL_MS: i32 = 1000;
+
+/// Subscribe to the kernel's process events through the netlink proc connector and send them to the given channel
+/// until it is closed. Subscribing needs the CAP_NET_ADMIN capability.
+///
+/// Started processes are read from the given proc directory, so events are received on their own thread rather than
+/// blocking the runtime for every process started on the system
+pub fn subscribe(
+    events: UnboundedSender<ProcessEvent>,
+    proc_dir: PathBuf,
+    filter: Arc<RwLock<ProcessFilter>>,
+) -> io::Result<()> {
+    let socket = open_socket()?;
+
+    send_listen(socket.as_raw_fd())?;
+    wait_for_ack(socket.as_raw_fd())?;
+
+    std::thread::Builder::new()
+        .name("process-events".to_owned())
+        .spawn(move || {
+            let mut buffer = [0u8; 4096];
+
+            while !events.is_closed() {
+                match poll_readable(socket.as_raw_fd(), CLOSED_CHECK_INTERVAL_MS) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        eprintln!("error waiting for process events -> {e}");
+                        _ = events.send(ProcessEvent::Stopped);
+                        return;
+                    }
+                }
+
+                let event = match receive(socket.as_raw_fd(), &mut buffer) {
+                    Ok(len) => parse_event(&buffer[..len], &proc_dir, &filter),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    // The kernel drops events when they are not read fast enough
+                    Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => Some(ProcessEvent::Lost),
+                    Err(e) => {
+                        eprintln!("error reading process events -> {e}");
+                        _ = events.send(ProcessEvent::Stopped);
+                        return;
+                    }
+                };
+
+                if let Some(event) = event {
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn open_socket() -> io::Result<OwnedFd> {
+    // SAFETY: socket has no memory safety requirements, and the returned descriptor is checked before it is owned
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_CONNECTOR,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a valid descriptor that nothing else owns
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: sockaddr_nl is plain data, so all zeroes is a valid value
+    let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = CN_IDX_PROC;
+
+    // SAFETY: address is a valid sockaddr_nl and the given length is its size
+    let result = unsafe {
+        libc::bind(
+            fd,
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+fn send_listen(fd: RawFd) -> io::Result<()> {
+    let mut message = Vec::with_capacity(NLMSG_HEADER_LEN + CN_MSG_HEADER_LEN + 4);
+
+    // nlmsghdr: length, type, flags, sequence number and port ID
+    message.extend(((NLMSG_HEADER_LEN + CN_MSG_HEADER_LEN + 4) as u32).to_ne_bytes());
+    message.extend((libc::NLMSG_DONE as u16).to_ne_bytes());
+    message.extend(0u16.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes());
+    // cn_msg: index, value, sequence number, acknowledgement, data length and flags
+    message.extend(CN_IDX_PROC.to_ne_bytes());
+    message.extend(CN_VAL_PROC.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes());
+    message.extend(4u16.to_ne_bytes());
+    message.extend(0u16.to_ne_bytes());
+    message.extend(PROC_CN_MCAST_LISTEN.to_ne_bytes());
+
+    // SAFETY: the pointer and length describe the message buffer
+    let sent = unsafe { libc::send(fd, message.as_ptr().cast(), message.len(), 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// The kernel answers the subscription with an empty event holding an error code, which is how a missing capability is
+/// reported
+fn wait_for_ack(fd: RawFd) -> io::Result<()> {
+    let mut buffer = [0u8; 4096];
+
+    loop {
+        if !poll_readable(fd, SUBSCRIBE_TIMEOUT_MS)? {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no answer from the proc connector",
+            ));
+        }
+
+        let len = receive(fd, &mut buffer)?;
+
+        if read_u32(&buffer[..len], NLMSG_HEADER_LEN + CN_MSG_HEADER_LEN) != Some(PROC_EVENT_NONE) {
+            // Other listeners may already have made the kernel send events
+            continue;
+        }
+
+        return match read_u32(&buffer[..len], EVENT_DATA_OFFSET) {
+            Some(0) => Ok(()),
+            Some(error) => Err(io::Error::from_raw_os_error(error as i32)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid answer from the proc connector",
+            )),
+        };
+    }
+}
+
+/// Wait until the socket can be read, returning false when the timeout passed first
+fn poll_readable(fd: RawFd, timeout_ms: i32) -> io::Result<bool> {
+    let mut poll_fd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    // SAFETY: poll_fd is a single valid pollfd
+    match unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } {
+        0 => Ok(false),
+        result if result < 0 => {
+            let error = io::Error::last_os_error();
+            match error.kind() {
+                io::ErrorKind::Interrupted => Ok(false),
+                _ => Err(error),
+            }
+        }
+        _ => Ok(true),
+    }
+}
+
+fn receive(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+    // SAFETY: the pointer and length describe the buffer
+    let len = unsafe { libc::recv(fd, buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(len as usize)
+}
+
+/// Turn a message from the proc connector into an event. Events about threads and other kinds of events are skipped
+fn parse_event(
+    message: &[u8],
+    proc_dir: &Path,
+    filter: &RwLock<ProcessFilter>,
+) -> Option<ProcessEvent> {
+    let data = |index: usize| read_u32(message, EVENT_DATA_OFFSET + index * 4);
+
+    match read_u32(message, NLMSG_HEADER_LEN + CN_MSG_HEADER_LEN)? {
+        PROC_EVENT_FORK => {
+            let (parent, child, child_tgid) = (data(1)?, data(2)?, data(3)?);
+            (child == child_tgid).then_some(ProcessEvent::Forked { parent, child })
+        }
+        PROC_EVENT_EXEC => {
+            let (pid, tgid) = (data(0)?, data(1)?);
+            if pid != tgid {
+                return None;
+            }
+
+            let filter = filter.read().unwrap_or_else(PoisonError::into_inner);
+            read_snapshot(proc_dir, pid, &filter).map(ProcessEvent::Executed)
+        }
+        PROC_EVENT_EXIT => {
+            let (pid, tgid) = (data(0)?, data(1)?);
+            (pid == tgid).then_some(ProcessEvent::Exited { pid })
+        }
+        _ => None,
+    }
+}
+
+/// Read a process that was just started. Its status and cgroup are only read when the filter may want it, but it is
+/// still reported otherwise so that a tracked process that started another program is no longer counted
+fn read_snapshot(proc_dir: &Path, pid: u32, filter: &ProcessFilter) -> Option<ProcessSnapshot> {
+    let process_dir = proc_dir.join(pid.to_string());
+
+    // A process that already exited again is simply missed, like it would be by a scan
+    let name = std::fs::read_to_string(process_dir.join("comm")).ok()?;
+    let name = name.trim().to_owned();
+    let is_wanted_name = filter.wants_name(&name);
+
+    let cgroup = (is_wanted_name || filter.has_cgroup_patterns())
+        .then(|| std::fs::read_to_string(process_dir.join("cgroup")).ok())
+        .flatten()
+        .and_then(|contents| Cgroup::from_proc_file(&contents));
+
+    let is_wanted = is_wanted_name
+        || cgroup
+            .as_ref()
+            .is_some_and(|cgroup| filter.wants_cgroup(cgroup));
+
+    let uid = is_wanted
+        .then(|| std::fs::read(process_dir.join("status")).ok())
+        .flatten()
+        .and_then(|status| linux::parse_uid(&status));
+
+    Some(ProcessSnapshot {
+        pid,
+        name,
+        uid,
+        cgroup,
+    })
+}
+
+fn read_u32(message: &[u8], offset: usize) -> Option<u32> {
+    let bytes = message.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(
+        bytes.try_into().expect("slice has 4 bytes"),
+    ))
+}
@@ -0,0 +1,52 @@
+use std::sync::mpsc;
+
+use tokio::{runtime::Handle, sync::oneshot};
+
+use super::ProcessScanner;
+
+type Job<S> = Box<dyn FnOnce(&mut S) + Send>;
+
+/// Runs a scanner on a thread of its own, so that scans neither wait for nor take up the blocking thread pool and the
+/// scanner's buffers are reused from one scan to the next. The thread stops when this is dropped
+#[derive(Debug)]
+pub struct ScanThread<S> {
+    jobs: mpsc::Sender<Job<S>>,
+}
+
+impl<S: ProcessScanner + 'static> ScanThread<S> {
+    /// Move the scanner to a new thread. This must be called from within the tokio runtime, which the scanner can then
+    /// use to watch processes
+    pub fn spawn(mut scanner: S) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job<S>>();
+        let runtime = Handle::current();
+
+        std::thread::Builder::new()
+            .name("process-scanner".into())
+            .spawn(move || {
+                let _runtime = runtime.enter();
+
+                for job in job_receiver {
+                    job(&mut scanner);
+                }
+            })
+            .expect("cannot start the process scanner thread");
+
+        Self { jobs }
+    }
+
+    /// Run the given function with the scanner on its thread and return what it returns
+    pub async fn run<R: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut S) -> R + Send + 'static,
+    ) -> R {
+        let (result_sender, result) = oneshot::channel();
+
+        self.jobs
+            .send(Box::new(move |scanner| {
+                _ = result_sender.send(job(scanner));
+            }))
+            .expect("process scanner thread stopped");
+
+        result.await.expect("process scanner panicked")
+    }
+}
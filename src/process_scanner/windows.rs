@@ -0,0 +1,82 @@
+use std::{collections::HashSet, error::Error};
+
+use super::{ProcessScanner, ProcessSnapshot};
+
+/// Scans the processes running on the system through a toolhelp snapshot
+#[derive(Debug, Default)]
+pub struct SystemScanner {
+    names: Option<HashSet<String>>,
+}
+
+fn from_utf16_until_nul_byte(input: &[u16]) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut split = input.split(|char| *char == 0);
+    let bytes = split
+        .next()
+        .ok_or_else(|| "no nul byte found".to_string())?;
+    Ok(String::from_utf16(bytes)?)
+}
+
+impl ProcessScanner for SystemScanner {
+    fn scan(&mut self) -> Result<Vec<ProcessSnapshot>, Box<dyn Error + Send + Sync>> {
+        use windows_sys::Win32::Foundation::CloseHandle;
+        use windows_sys::Win32::System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
+        };
+        use windows_sys::Win32::System::Kernel::NULL64;
+
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            cntUsage: 0,
+            th32ProcessID: 0,
+            th32DefaultHeapID: 0,
+            th32ModuleID: 0,
+            cntThreads: 0,
+            th32ParentProcessID: 0,
+            pcPriClassBase: 0,
+            dwFlags: 0,
+            szExeFile: [0; 260],
+        };
+
+        let mut process_list = Vec::with_capacity(300);
+
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, NULL64);
+
+            if Process32FirstW(snapshot, &mut entry) == 0 {
+                CloseHandle(snapshot);
+                return Err("Process32First failed".into());
+            }
+
+            loop {
+                let name = from_utf16_until_nul_byte(&entry.szExeFile)?;
+
+                if self
+                    .names
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&name))
+                {
+                    process_list.push(ProcessSnapshot {
+                        pid: entry.th32ProcessID,
+                        name,
+                        // Windows owners are security identifiers, not user IDs
+                        uid: None,
+                        cgroup: None,
+                    });
+                }
+
+                if Process32NextW(snapshot, &mut entry) == 0 {
+                    break;
+                }
+            }
+
+            CloseHandle(snapshot);
+        }
+
+        Ok(process_list)
+    }
+
+    fn filter_names(&mut self, names: HashSet<String>) {
+        self.names = Some(names);
+    }
+}
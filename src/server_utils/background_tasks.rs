@@ -1,45 +1,53 @@
-use std::time::Duration;
+use std::{collections::HashSet, path::Path, time::Duration};
 
-use tokio::sync::RwLock;
+use notify::{RecursiveMode, Watcher};
+use serde_json::{Map, Value};
+use tokio::sync::{mpsc, RwLock};
 
 use crate::{
-    get_config_dir,
-    process_scanner::get_running_processes,
-    structures::{config::Config, process::Processes},
+    clock::Clock,
+    process_scanner::{ProcessEvent, ProcessScanner, ScanThread},
+    server_utils::{
+        poll_schedule::PollSchedule, running_pids::RunningPids, usage_sampler::UsageSampler,
+    },
+    structures::{
+        config::{
+            read_config_file, Config, ConfigLayers, CONFIG_FILE_NAME, RUNTIME_SETTINGS_FILE_NAME,
+        },
+        host_log::{read_host_logs, replace_written_host_log, HostLog, SyncedHosts},
+        journal::Journal,
+        process::Processes,
+    },
+    write_atomically,
 };
 
+/// Save the tracking data and the settings changed at runtime to the data dir. The config file is never written
 pub async fn save_data(
     config: &RwLock<Config>,
+    config_layers: &RwLock<ConfigLayers>,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    data_dir: &Path,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let config_dir = get_config_dir().ok_or_else(|| "cannot find config dir".to_string())?;
-
-    if !config_dir.exists() {
-        tokio::fs::create_dir_all(&config_dir).await?;
+    if !data_dir.exists() {
+        tokio::fs::create_dir_all(data_dir).await?;
     }
 
-    let config_path = config_dir.join("config.json");
-    let config_lock = config_path.with_extension("lock");
-
-    let processes_path = config_dir.join("processes.json");
+    let processes_path = data_dir.join("processes.json");
     let processes_lock = processes_path.with_extension("lock");
 
     let mut builder = std::fs::OpenOptions::new();
     builder.create(true).write(true).truncate(true);
 
+    // The config is locked before the layers everywhere, so it is copied first
+    let config = config.read().await.clone();
+    let runtime_settings = config_layers.read().await.runtime_settings(&config);
+    let serialized = serde_json::to_vec_pretty(&runtime_settings).expect("must serialize");
+    write_atomically(&data_dir.join(RUNTIME_SETTINGS_FILE_NAME), &serialized)
+        .map_err(|e| format!("cannot write runtime settings -> {e}"))?;
+
     // We use lock files to prevent a conflict in case this function is called twice simultaneously:
     // once in the autosave thread and once in the handle_user_command thread during server close
-    while config_lock.exists() {
-        tokio::time::sleep(Duration::from_secs(1)).await;
-    }
-
-    _ = builder.open(&config_lock);
-    let config_file = builder
-        .open(config_path)
-        .map_err(|e| format!("cannot open config path -> {e}"))?;
-    serde_json::to_writer_pretty(config_file, &*config.read().await)?;
-    _ = std::fs::remove_file(config_lock);
-
     while processes_lock.exists() {
         tokio::time::sleep(Duration::from_secs(1)).await;
     }
@@ -51,56 +59,362 @@ pub async fn save_data(
     serde_json::to_writer_pretty(processes_file, &processes.read().await.0)?;
     _ = std::fs::remove_file(processes_lock);
 
+    let journal_path = data_dir.join("journal.json");
+    let serialized = serde_json::to_vec(&*journal.read().await).expect("must serialize");
+    write_atomically(&journal_path, &serialized)
+        .map_err(|e| format!("cannot write journal -> {e}"))?;
+
     Ok(())
 }
 
-pub async fn autosave_data(config: &RwLock<Config>, processes: &RwLock<Processes>) {
+pub async fn autosave_data(
+    config: &RwLock<Config>,
+    config_layers: &RwLock<ConfigLayers>,
+    processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    data_dir: &Path,
+    clock: &dyn Clock,
+) {
     loop {
         let sleep_seconds = config.read().await.autosave_interval;
 
-        tokio::time::sleep(Duration::from_secs(sleep_seconds)).await;
+        clock.sleep(Duration::from_secs(sleep_seconds)).await;
 
-        if let Err(e) = save_data(config, processes).await {
+        if let Err(e) = save_data(config, config_layers, processes, journal, data_dir).await {
             eprintln!("{e}");
         }
     }
 }
 
-pub async fn update_duration(config: &RwLock<Config>, processes: &RwLock<Processes>) {
+pub async fn update_duration(
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+    clock: &dyn Clock,
+) {
     loop {
         let sleep_seconds = config.read().await.duration_update_interval;
 
-        tokio::time::sleep(Duration::from_secs(sleep_seconds)).await;
+        clock.sleep(Duration::from_secs(sleep_seconds)).await;
 
-        for process in processes.write().await.0.iter_mut() {
-            if process.is_running && process.is_tracked {
-                process.duration += sleep_seconds;
-            }
-        }
+        processes.write().await.accrue_duration(clock.now());
     }
 }
 
-pub async fn check_running_processes(config: &RwLock<Config>, processes: &RwLock<Processes>) {
+pub async fn check_running_processes<S: ProcessScanner + 'static>(
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+    scanner: S,
+    clock: &dyn Clock,
+) {
+    let scanner = ScanThread::spawn(scanner);
+    let mut schedule = PollSchedule::default();
+    let mut running_pids = RunningPids::default();
+    let mut tracked_names: Option<HashSet<String>> = None;
+    let mut tracked_cgroups: Option<Vec<String>> = None;
+    let mut usage_sampler = UsageSampler::default();
+    let mut next_usage_sample = clock.now();
+
+    // Processes that start or exit are handled as it happens when the system reports them. Otherwise new processes are
+    // found by scanning, and only the exits of the processes found are reported
+    let (event_sender, mut events) = mpsc::unbounded_channel();
+    let mut watching_all = {
+        let event_sender = event_sender.clone();
+        scanner
+            .run(move |scanner| scanner.watch_all(event_sender))
+            .await
+    }
+    .map_err(|e| eprintln!("{e} -> checking for new processes every poll interval instead"))
+    .is_ok();
+    let mut watching_exits = !watching_all;
+
+    if watching_all {
+        schedule.check_once();
+    }
+
     loop {
+        let now = clock.now();
+        let poll_interval = config.read().await.poll_interval;
+
         // In case a process was running when the server closed, its is_running would be saved as true in the json file.
         // As a result, when the server starts it might incorrectly update that process's duration before checking
         // if it was running. Therefore, we check which processes are running first before sleeping
-        match get_running_processes().await {
-            Ok(process_list) => {
-                for process in processes.write().await.0.iter_mut() {
-                    if process.is_tracked && process_list.contains(&process.name) {
-                        process.is_running = true;
-                        process.last_seen_date = chrono::prelude::Local::now().naive_local();
-                    } else {
-                        process.is_running = false;
+        let due_filter = {
+            let processes = processes.read().await;
+
+            // The scanner only needs to be told again which names and cgroups to look for when they changed
+            schedule.any_due(&processes, now).then(|| {
+                let changed_names = tracked_names
+                    .as_ref()
+                    .is_none_or(|names| !processes.tracks_exactly(names))
+                    .then(|| processes.tracked_names());
+
+                let cgroups = processes.tracked_cgroups();
+                let changed_cgroups =
+                    (tracked_cgroups.as_ref() != Some(&cgroups)).then_some(cgroups);
+
+                (changed_names, changed_cgroups)
+            })
+        };
+
+        let process_list = if let Some((changed_names, changed_cgroups)) = due_filter {
+            if let Some(names) = &changed_names {
+                tracked_names = Some(names.clone());
+            }
+            if let Some(cgroups) = &changed_cgroups {
+                tracked_cgroups = Some(cgroups.clone());
+            }
+
+            // Scanning reads the whole system process list, so it is done on the scanner's own thread
+            let scan_result = scanner
+                .run(move |scanner| {
+                    if let Some(names) = changed_names {
+                        scanner.filter_names(names);
+                    }
+                    if let Some(cgroups) = changed_cgroups {
+                        scanner.filter_cgroups(cgroups);
+                    }
+                    scanner.scan()
+                })
+                .await;
+
+            scan_result.map_err(|e| eprintln!("{e}")).ok()
+        } else {
+            None
+        };
+
+        let mut new_pids = vec![];
+
+        {
+            let mut processes = processes.write().await;
+
+            match &process_list {
+                Some(process_list) => {
+                    new_pids = running_pids.replace(&processes, process_list);
+
+                    processes.update_running_of(process_list, now, |process| {
+                        schedule.is_due(process, now)
+                    })
+                }
+                // The instances found before are still running unless their exit was reported
+                None if watching_all || watching_exits => {
+                    processes.update_running_of(&running_pids.snapshots(), now, |process| {
+                        !schedule.is_due(process, now)
+                    })
+                }
+                // Processes that were untracked since the last scan stop running right away
+                None => processes.update_running_of(&[], now, |_| false),
+            }
+
+            processes.update_instances(running_pids.names());
+
+            // A failed scan is retried when the processes are due again instead of right away
+            schedule.mark_checked(&processes, now, poll_interval);
+        }
+
+        if watching_exits && !new_pids.is_empty() {
+            let event_sender = event_sender.clone();
+            let watch_result = scanner
+                .run(move |scanner| {
+                    new_pids
+                        .into_iter()
+                        .try_for_each(|pid| scanner.watch_exit(pid, event_sender.clone()))
+                })
+                .await;
+
+            if let Err(e) = watch_result {
+                eprintln!("{e} -> checking for exited processes every poll interval instead");
+                watching_exits = false;
+            }
+        }
+
+        // Usage is sampled every poll interval, even when processes are only checked once
+        if now >= next_usage_sample {
+            next_usage_sample = now + chrono::Duration::seconds(poll_interval as i64);
+
+            let running = running_pids.pids();
+            let samples = scanner
+                .run(move |scanner| {
+                    running
+                        .into_iter()
+                        .filter_map(|(pid, name)| Some((pid, name, scanner.usage(pid)?)))
+                        .collect()
+                })
+                .await;
+
+            usage_sampler.record(&mut *processes.write().await, samples);
+        }
+
+        tokio::select! {
+            _ = clock.sleep(schedule.time_until_next_check(now, poll_interval)) => {}
+            Some(event) = events.recv() => {
+                let now = clock.now();
+                let mut processes = processes.write().await;
+
+                match event {
+                    ProcessEvent::Lost => {
+                        eprintln!("missed some process events -> checking every process again");
+                        schedule.check_all_now();
+                    }
+                    ProcessEvent::Stopped => {
+                        eprintln!("stopped receiving process events -> checking every poll interval instead");
+                        watching_all = false;
+                        schedule.check_every_interval();
+                        schedule.check_all_now();
                     }
+                    _ => {}
+                }
+
+                for name in running_pids.apply(&processes, &event) {
+                    let is_running = running_pids.is_running(&name);
+                    processes.update_running_from_event(&name, is_running, now);
                 }
+
+                processes.update_instances(running_pids.names());
             }
-            Err(e) => eprintln!("{e}"),
         }
+    }
+}
+
+/// Write this host's log to the sync directory and read the logs of the other hosts from it
+pub async fn sync_hosts_once(
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+    synced_hosts: &RwLock<SyncedHosts>,
+    clock: &dyn Clock,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (sync_dir, local_host, data_dir) = {
+        let config = config.read().await;
+        (
+            config.sync_dir.clone(),
+            config.host_name(),
+            config.data_dir(),
+        )
+    };
+
+    // Syncing may have been turned off or the host renamed since the last sync
+    let Some(sync_dir) = sync_dir else {
+        *synced_hosts.write().await = SyncedHosts {
+            local_host,
+            ..Default::default()
+        };
+        return Ok(());
+    };
+
+    if !sync_dir.exists() {
+        tokio::fs::create_dir_all(&sync_dir)
+            .await
+            .map_err(|e| format!("cannot create sync dir {} -> {e}", sync_dir.display()))?;
+    }
+
+    let host_log = HostLog {
+        host: local_host.clone(),
+        updated_date: clock.now(),
+        processes: processes.read().await.0.clone(),
+    };
+
+    let written = host_log.write(&sync_dir)?;
+
+    // The log written under an earlier host name is removed before the other logs are read, so it is not counted
+    let replace_error = data_dir
+        .as_deref()
+        .and_then(|data_dir| replace_written_host_log(data_dir, &written).err());
 
-        let sleep_seconds = config.read().await.poll_interval;
+    let (others, mut errors) = read_host_logs(&sync_dir, &local_host)?;
+    errors.extend(replace_error);
 
-        tokio::time::sleep(Duration::from_secs(sleep_seconds)).await;
+    *synced_hosts.write().await = SyncedHosts { local_host, others };
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n").into())
+    }
+}
+
+pub async fn sync_hosts(
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+    synced_hosts: &RwLock<SyncedHosts>,
+    clock: &dyn Clock,
+) {
+    loop {
+        if let Err(e) = sync_hosts_once(config, processes, synced_hosts, clock).await {
+            eprintln!("{e}");
+        }
+
+        let sleep_seconds = config.read().await.sync_interval;
+
+        clock.sleep(Duration::from_secs(sleep_seconds)).await;
+    }
+}
+
+/// Apply a new config file while keeping the settings of the environment, the launch flags and the settings changed
+/// at runtime. Nothing changes if the result is invalid
+pub async fn apply_config_file(
+    config: &RwLock<Config>,
+    config_layers: &RwLock<ConfigLayers>,
+    new_config_file: Map<String, Value>,
+) -> Result<(), String> {
+    let mut config = config.write().await;
+    let mut config_layers = config_layers.write().await;
+
+    let new_config_layers = config_layers.with_file(new_config_file)?;
+
+    let runtime_settings = config.overrides_from(&config_layers.base);
+    let new_config = new_config_layers.base.with_overrides(&runtime_settings)?;
+
+    *config = new_config;
+    *config_layers = new_config_layers;
+
+    Ok(())
+}
+
+/// Reload the config file whenever it changes
+pub async fn watch_config_file(
+    config: &RwLock<Config>,
+    config_layers: &RwLock<ConfigLayers>,
+    config_dir: &Path,
+) {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            _ = sender.send(event);
+        }
+    });
+
+    // The directory is watched instead of the file because editors often replace the file when saving it
+    let _watcher = match watcher.and_then(|mut watcher| {
+        watcher
+            .watch(config_dir, RecursiveMode::NonRecursive)
+            .map(|_| watcher)
+    }) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("cannot watch config dir {} -> {e}", config_dir.display());
+            return;
+        }
+    };
+
+    let config_path = config_dir.join(CONFIG_FILE_NAME);
+
+    while let Some(event) = receiver.recv().await {
+        if event.kind.is_access() || !event.paths.contains(&config_path) {
+            continue;
+        }
+
+        // Wait for the editor to finish saving before reading the file once for all of its events
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while receiver.try_recv().is_ok() {}
+
+        let result = match read_config_file(&config_path) {
+            Ok(new_config_file) => apply_config_file(config, config_layers, new_config_file).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => println!("reloaded {}", config_path.display()),
+            Err(e) => eprintln!("{e} -> keeping the last valid config"),
+        }
     }
 }
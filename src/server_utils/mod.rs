@@ -1,3 +1,7 @@
 pub mod background_tasks;
+pub mod peer_credentials;
+pub mod poll_schedule;
+pub mod running_pids;
 pub mod server;
+pub mod usage_sampler;
 pub mod user_commands;
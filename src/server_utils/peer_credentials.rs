@@ -0,0 +1,176 @@
+use interprocess::local_socket::tokio::LocalSocketStream;
+
+use crate::{
+    commands::Commands,
+    structures::{
+        audit_log::PeerCredentials,
+        config::{AccessLevel, AllowedPeer, PeerId},
+    },
+};
+
+/// Ask the operating system who is on the other end of the socket. Anything it cannot tell is left empty
+#[cfg(target_os = "linux")]
+pub fn peer_credentials(conn: &LocalSocketStream) -> PeerCredentials {
+    use std::os::unix::io::AsRawFd;
+
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    // SAFETY: the file descriptor belongs to the open connection and ucred is large enough for SO_PEERCRED
+    let result = unsafe {
+        libc::getsockopt(
+            conn.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut ucred as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+
+    if result != 0 {
+        return PeerCredentials::default();
+    }
+
+    PeerCredentials {
+        uid: Some(ucred.uid),
+        pid: Some(ucred.pid as u32),
+        gid: Some(ucred.gid),
+        groups: supplementary_groups(conn.as_raw_fd()),
+    }
+}
+
+/// SO_PEERCRED only holds the primary group, the others are read with SO_PEERGROUPS. Like SO_PEERCRED, they are the
+/// groups the peer had when it connected, so they cannot come from another process that reused its PID. Kernels
+/// without SO_PEERGROUPS give no supplementary groups, which only leaves the primary group to be matched
+#[cfg(target_os = "linux")]
+fn supplementary_groups(fd: std::os::unix::io::RawFd) -> Vec<u32> {
+    let gid_size = std::mem::size_of::<libc::gid_t>();
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+
+    loop {
+        let mut length = (groups.len() * gid_size) as libc::socklen_t;
+
+        // SAFETY: the file descriptor belongs to the open connection and the length is the size of the groups buffer
+        let result = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut length,
+            )
+        };
+
+        if result == 0 {
+            groups.truncate(length as usize / gid_size);
+            return groups;
+        }
+
+        // The kernel asks for a larger buffer by setting the length it needs
+        let needed = length as usize / gid_size;
+        if std::io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE)
+            && needed > groups.len()
+        {
+            groups.resize(needed, 0);
+            continue;
+        }
+
+        return vec![];
+    }
+}
+
+/// Ask the operating system who is on the other end of the socket. Anything it cannot tell is left empty
+#[cfg(not(target_os = "linux"))]
+pub fn peer_credentials(conn: &LocalSocketStream) -> PeerCredentials {
+    PeerCredentials {
+        pid: conn.peer_pid().ok(),
+        ..Default::default()
+    }
+}
+
+/// The user the server runs as, or None where users cannot be told apart
+pub fn server_uid() -> Option<u32> {
+    crate::current_uid()
+}
+
+/// What a peer may do, from least to most
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    ReadOnly,
+    ReadWrite,
+    /// The user running the server, who alone may decide who else is allowed
+    Owner,
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Permission::ReadOnly => "read-only",
+            Permission::ReadWrite => "read-write",
+            Permission::Owner => "owner",
+        })
+    }
+}
+
+impl From<AccessLevel> for Permission {
+    fn from(access: AccessLevel) -> Self {
+        match access {
+            AccessLevel::ReadOnly => Permission::ReadOnly,
+            AccessLevel::ReadWrite => Permission::ReadWrite,
+        }
+    }
+}
+
+/// Decide what a peer may do. Only the user running the server is allowed by default. Other users must be
+/// allowed by their uid or by one of their groups, and get the highest access level of all that match them
+pub fn authorize(
+    peer: &PeerCredentials,
+    server_uid: Option<u32>,
+    allowed_peers: &[AllowedPeer],
+) -> Option<Permission> {
+    // Without users there is nobody to keep out
+    let Some(server_uid) = server_uid else {
+        return Some(Permission::Owner);
+    };
+
+    let uid = peer.uid?;
+
+    if uid == server_uid {
+        return Some(Permission::Owner);
+    }
+
+    allowed_peers
+        .iter()
+        .filter(|allowed_peer| match allowed_peer.peer {
+            PeerId::Uid(allowed_uid) => allowed_uid == uid,
+            PeerId::Group(allowed_gid) => {
+                peer.gid == Some(allowed_gid) || peer.groups.contains(&allowed_gid)
+            }
+        })
+        .map(|allowed_peer| Permission::from(allowed_peer.access))
+        .max()
+}
+
+/// The least permission needed to run a command
+pub fn required_permission(command: &Commands) -> Permission {
+    match command {
+        Commands::Option(config_cmd)
+            if !config_cmd.allow.is_empty() || !config_cmd.revoke.is_empty() =>
+        {
+            Permission::Owner
+        }
+        // Restoring an archive replaces the config, including the allowed peers, and creating one writes a file anywhere
+        // the server's user can
+        Commands::Archive(_) => Permission::Owner,
+        // Even a dry run reads a file with the privileges of the server, and rejected rows send parts of it back
+        Commands::Import(_) => Permission::Owner,
+        // The server reads and writes host logs in the sync dir with its own privileges
+        Commands::Option(config_cmd) if config_cmd.sync_dir.is_some() => Permission::Owner,
+        command if command.is_mutating() => Permission::ReadWrite,
+        _ => Permission::ReadOnly,
+    }
+}
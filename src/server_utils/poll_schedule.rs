@@ -0,0 +1,78 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::structures::process::{Process, Processes};
+
+/// When each tracked process is due to be checked again. Processes are checked every poll interval of their own, or
+/// every poll interval of the config, and the system process list is only scanned when at least one of them is due
+#[derive(Debug, Default)]
+pub struct PollSchedule {
+    next_checks: HashMap<String, DateTime<Utc>>,
+    check_once: bool,
+}
+
+impl PollSchedule {
+    /// Whether a process is due to be checked. Processes that have never been checked are always due
+    pub fn is_due(&self, process: &Process, now: DateTime<Utc>) -> bool {
+        process.is_tracked
+            && self
+                .next_checks
+                .get(&process.name)
+                .is_none_or(|next_check| *next_check <= now)
+    }
+
+    pub fn any_due(&self, processes: &Processes, now: DateTime<Utc>) -> bool {
+        processes.0.iter().any(|process| self.is_due(process, now))
+    }
+
+    /// Only check each process once after it becomes tracked, for when every process that starts or exits is reported
+    /// as it happens
+    pub fn check_once(&mut self) {
+        self.check_once = true;
+    }
+
+    /// Check each process every poll interval again, for when processes are no longer reported as they start or exit
+    pub fn check_every_interval(&mut self) {
+        self.check_once = false;
+    }
+
+    /// Make every process due right away
+    pub fn check_all_now(&mut self) {
+        self.next_checks.clear();
+    }
+
+    /// Remember when the processes that were due at the given time are due again. Processes that are no longer
+    /// tracked are forgotten
+    pub fn mark_checked(&mut self, processes: &Processes, now: DateTime<Utc>, poll_interval: u64) {
+        let mut next_checks = HashMap::with_capacity(processes.0.len());
+
+        for process in processes.0.iter().filter(|process| process.is_tracked) {
+            let next_check = match self.next_checks.get(&process.name) {
+                Some(next_check) if *next_check > now => *next_check,
+                _ if self.check_once => DateTime::<Utc>::MAX_UTC,
+                _ => now + interval(process, poll_interval),
+            };
+
+            next_checks.insert(process.name.clone(), next_check);
+        }
+
+        self.next_checks = next_checks;
+    }
+
+    /// How long to wait until the next process is due. This is never longer than the poll interval of the config, so
+    /// that newly added processes do not wait for processes that are checked rarely
+    pub fn time_until_next_check(&self, now: DateTime<Utc>, poll_interval: u64) -> Duration {
+        let poll_interval = Duration::from_secs(poll_interval);
+
+        self.next_checks
+            .values()
+            .map(|next_check| (*next_check - now).to_std().unwrap_or_default())
+            .min()
+            .map_or(poll_interval, |until_next| until_next.min(poll_interval))
+    }
+}
+
+fn interval(process: &Process, poll_interval: u64) -> chrono::Duration {
+    chrono::Duration::seconds(process.poll_interval.unwrap_or(poll_interval) as i64)
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use crate::{
+    process_scanner::{ProcessEvent, ProcessSnapshot},
+    structures::process::Processes,
+};
+
+/// The PIDs of the running instances of tracked processes, kept up to date by scans and by the processes the system
+/// reports as they start or exit
+#[derive(Debug, Default)]
+pub struct RunningPids {
+    instances: HashMap<u32, Instance>,
+}
+
+#[derive(Debug, Clone)]
+struct Instance {
+    snapshot: ProcessSnapshot,
+    /// The tracked processes this is an instance of, which are not named the same when they are matched by cgroup
+    process_names: Vec<String>,
+}
+
+impl RunningPids {
+    /// Replace the known instances with the tracked processes in a scan, and return the PIDs that were not known before
+    pub fn replace(&mut self, processes: &Processes, process_list: &[ProcessSnapshot]) -> Vec<u32> {
+        let instances: HashMap<u32, Instance> = process_list
+            .iter()
+            .filter_map(|process| Some((process.pid, instance_of(processes, process)?)))
+            .collect();
+
+        let new_pids = instances
+            .keys()
+            .filter(|pid| !self.instances.contains_key(pid))
+            .copied()
+            .collect();
+
+        self.instances = instances;
+        new_pids
+    }
+
+    /// Apply a process starting or exiting and return the names of the tracked processes that gained or lost an
+    /// instance
+    pub fn apply(&mut self, processes: &Processes, event: &ProcessEvent) -> Vec<String> {
+        match event {
+            // A forked instance has the same owner and cgroup as its parent
+            ProcessEvent::Forked { parent, child } => match self.instances.get(parent).cloned() {
+                Some(mut instance) => {
+                    instance.snapshot.pid = *child;
+                    let changed = instance.process_names.clone();
+                    self.instances.insert(*child, instance);
+                    changed
+                }
+                None => vec![],
+            },
+            ProcessEvent::Executed(process) => {
+                // The instance may have been running a tracked program before
+                let mut changed: Vec<String> = self
+                    .instances
+                    .remove(&process.pid)
+                    .map(|instance| instance.process_names)
+                    .unwrap_or_default();
+
+                if let Some(instance) = instance_of(processes, process) {
+                    changed.extend(instance.process_names.iter().cloned());
+                    self.instances.insert(process.pid, instance);
+                }
+
+                changed.sort();
+                changed.dedup();
+                changed
+            }
+            ProcessEvent::Exited { pid } => self
+                .instances
+                .remove(pid)
+                .map(|instance| instance.process_names)
+                .unwrap_or_default(),
+            ProcessEvent::Lost | ProcessEvent::Stopped => vec![],
+        }
+    }
+
+    pub fn is_running(&self, name: &str) -> bool {
+        self.names().any(|running_name| running_name == name)
+    }
+
+    /// The name of the tracked process of every running instance
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.instances
+            .values()
+            .flat_map(|instance| instance.process_names.iter().map(String::as_str))
+    }
+
+    /// The PID of every running instance with the name of its tracked process
+    pub fn pids(&self) -> Vec<(u32, String)> {
+        self.instances
+            .iter()
+            .flat_map(|(pid, instance)| {
+                instance
+                    .process_names
+                    .iter()
+                    .map(|process_name| (*pid, process_name.clone()))
+            })
+            .collect()
+    }
+
+    pub fn snapshots(&self) -> Vec<ProcessSnapshot> {
+        self.instances
+            .values()
+            .map(|instance| instance.snapshot.clone())
+            .collect()
+    }
+}
+
+fn instance_of(processes: &Processes, snapshot: &ProcessSnapshot) -> Option<Instance> {
+    let process_names: Vec<String> = processes
+        .0
+        .iter()
+        .filter(|process| process.is_tracked && process.matches(snapshot))
+        .map(|process| process.name.clone())
+        .collect();
+
+    (!process_names.is_empty()).then(|| Instance {
+        snapshot: snapshot.clone(),
+        process_names,
+    })
+}
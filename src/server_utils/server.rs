@@ -1,4 +1,7 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use futures_lite::{io::BufReader, AsyncBufReadExt, AsyncWriteExt};
 use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
@@ -6,64 +9,227 @@ use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
 use tokio::sync::RwLock;
 
 use crate::{
-    commands::Commands,
-    get_socket_name,
-    server_utils::background_tasks::{autosave_data, check_running_processes, update_duration},
-    structures::{config::Config, process::Processes},
+    clock::{Clock, SystemClock},
+    commands::{Commands, Launch},
+    get_config_dir, get_server_socket_name, migrate_data_files,
+    process_scanner::SystemScanner,
+    server_utils::background_tasks::{
+        autosave_data, check_running_processes, sync_hosts, update_duration, watch_config_file,
+    },
+    structures::{
+        audit_log::{diff_state, AuditEntry, AuditLog},
+        config::{
+            parse_env_settings, read_or_create_config_file, read_runtime_settings, Config,
+            ConfigLayers,
+        },
+        host_log::SyncedHosts,
+        journal::Journal,
+        process::Processes,
+    },
 };
 
 use super::{
     background_tasks::save_data,
+    peer_credentials::{authorize, peer_credentials, required_permission, server_uid},
     user_commands::{
-        add_new_process, change_config, change_duration, change_process, get_processes,
-        get_settings, import_processes, move_process, remove_processes, set_exit_flag,
+        add_new_process, change_config, change_duration, change_process, get_audit_entries,
+        get_history, get_processes, get_settings, handle_archive, import_processes, move_process,
+        redo_operation, remove_processes, set_exit_flag, undo_operation,
     },
 };
 
-pub async fn launch() {
-    let socket_name = get_socket_name();
-
-    if let Err(e) = LocalSocketListener::bind(socket_name) {
-        eprintln!("cannot start server on socket {socket_name} -> {e}");
+pub async fn launch(launch_cmd: Launch, socket_override: Option<&str>) {
+    let Some(config_dir) = get_config_dir() else {
+        eprintln!("cannot find config dir");
         std::process::exit(1);
+    };
+    let config_dir = &*Box::leak(config_dir.into_boxed_path());
+
+    // A mistake in the config file should not stop tracking, so the defaults are used until it is fixed
+    let file_settings = read_or_create_config_file(config_dir).unwrap_or_else(|e| {
+        eprintln!("{e} -> using the default config");
+        Default::default()
+    });
+
+    // The environment and the launch flags are given on purpose for this launch, so mistakes in them stop it
+    let mut config_layers = parse_env_settings(std::env::vars())
+        .and_then(|env_settings| {
+            ConfigLayers::new(file_settings, env_settings, launch_cmd.settings())
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+
+    // The data dir is only chosen once, so that restoring an archive with another data dir cannot split the data
+    let data_dir = match config_layers.base.data_dir() {
+        Some(data_dir) => &*Box::leak(data_dir.into_boxed_path()),
+        None => {
+            eprintln!("cannot find data dir");
+            std::process::exit(1);
+        }
+    };
+
+    // The settings changed with the option command take priority over the config file, but not over the settings
+    // given for this launch
+    let config = read_runtime_settings(data_dir)
+        .and_then(|runtime_settings| Ok(config_layers.apply_runtime_settings(runtime_settings)?))
+        .unwrap_or_else(|e| {
+            eprintln!("invalid runtime settings -> {e} -> ignoring them");
+            config_layers.base.clone()
+        });
+
+    // Other users can only reach the socket when the server is launched with peers already allowed
+    let shared_socket = !config.allowed_peers.is_empty();
+    let socket_name: &'static str =
+        Box::leak(get_server_socket_name(socket_override, shared_socket).into_boxed_str());
+
+    let listener = match bind_socket(socket_name, shared_socket).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot start server on socket {socket_name} -> {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Older versions kept the tracking data next to the settings
+    match migrate_data_files(config_dir, data_dir) {
+        Ok(moved) => {
+            for path in moved {
+                println!("moved tracking data to {}", path.display());
+            }
+        }
+        Err(e) => eprintln!("cannot move data files to {} -> {e}", data_dir.display()),
     }
 
     // Wrap global config in RwLock to allow multiple readers and one writer
-    let config = RwLock::new(Config::read().unwrap_or_default());
+    let config = RwLock::new(config);
 
     // Leak config so that it may live as long as the server lives and be shared across threads without an Arc
     let config = &*Box::leak(Box::new(config));
 
+    // The layers are kept to tell apart the settings changed at runtime and to show where each setting came from
+    let config_layers = &*Box::leak(Box::new(RwLock::new(config_layers)));
+
     // Do the same for the process list
-    let processes = RwLock::new(Processes::read().unwrap_or_default());
+    let processes = RwLock::new(Processes::read(data_dir).unwrap_or_default());
     let processes = &*Box::leak(Box::new(processes));
 
+    // The journal is kept across restarts so that operations from before a restart can still be undone
+    let journal = RwLock::new(Journal::read(data_dir).unwrap_or_default());
+    let journal = &*Box::leak(Box::new(journal));
+
+    // The other hosts are only known after the first sync, until then only this host is
+    let synced_hosts = RwLock::new(SyncedHosts {
+        local_host: config.read().await.host_name(),
+        ..Default::default()
+    });
+    let synced_hosts = &*Box::leak(Box::new(synced_hosts));
+
     let close_server_flag = &*Box::leak(Box::new(AtomicBool::new(false)));
 
+    let clock: &'static dyn Clock = &SystemClock;
+
     println!("Starting server on socket {socket_name}");
 
-    tokio::spawn(async move { update_duration(config, processes).await });
+    tokio::spawn(async move { update_duration(config, processes, clock).await });
+
+    tokio::spawn(async move {
+        check_running_processes(config, processes, SystemScanner::default(), clock).await
+    });
+
+    tokio::spawn(async move {
+        autosave_data(config, config_layers, processes, journal, data_dir, clock).await
+    });
+
+    tokio::spawn(async move { sync_hosts(config, processes, synced_hosts, clock).await });
+
+    tokio::spawn(async move { watch_config_file(config, config_layers, config_dir).await });
+
+    let audit_log = RwLock::new(AuditLog::new(data_dir.join("audit.log")));
+    let audit_log = &*Box::leak(Box::new(audit_log));
+
+    get_user_command(
+        listener,
+        ServerState {
+            socket_name,
+            shared_socket,
+            config,
+            config_layers,
+            processes,
+            journal,
+            data_dir,
+            synced_hosts,
+            audit_log,
+            close_server_flag,
+            clock,
+        },
+    )
+    .await;
+}
+
+/// Bind the socket so that only the user running the server can connect to it, or anyone when it is shared with
+/// other users, in which case every request is still checked against the allowed peers. A socket file left behind by
+/// a server that did not shut down cleanly is replaced, but a socket that another server still listens on is not
+async fn bind_socket(socket_name: &str, shared: bool) -> std::io::Result<LocalSocketListener> {
+    let is_path = !socket_name.starts_with('@');
 
-    tokio::spawn(async move { check_running_processes(config, processes).await });
+    // Sockets are created with the permissions allowed by the umask, so it is narrowed while binding to avoid
+    // a window in which others could connect
+    #[cfg(unix)]
+    // SAFETY: umask cannot fail and only changes the permissions of files created from now on
+    let old_umask = unsafe { libc::umask(0o177) };
 
-    tokio::spawn(async move { autosave_data(config, processes).await });
+    let mut result = LocalSocketListener::bind(socket_name);
 
-    get_user_command(config, processes, close_server_flag).await;
+    if is_path
+        && matches!(&result, Err(e) if e.kind() == std::io::ErrorKind::AddrInUse)
+        && LocalSocketStream::connect(socket_name).await.is_err()
+    {
+        result =
+            std::fs::remove_file(socket_name).and_then(|_| LocalSocketListener::bind(socket_name));
+    }
+
+    #[cfg(unix)]
+    // SAFETY: see above
+    unsafe {
+        libc::umask(old_umask)
+    };
+
+    let listener = result?;
+
+    #[cfg(unix)]
+    if is_path {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = if shared { 0o666 } else { 0o600 };
+        std::fs::set_permissions(socket_name, std::fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(listener)
 }
 
-async fn get_user_command(
+/// Everything a request may need. The fields are leaked, so this can be copied into every connection's task
+#[derive(Clone, Copy)]
+struct ServerState {
+    socket_name: &'static str,
+    shared_socket: bool,
     config: &'static RwLock<Config>,
+    config_layers: &'static RwLock<ConfigLayers>,
     processes: &'static RwLock<Processes>,
+    journal: &'static RwLock<Journal>,
+    data_dir: &'static Path,
+    synced_hosts: &'static RwLock<SyncedHosts>,
+    audit_log: &'static RwLock<AuditLog>,
     close_server_flag: &'static AtomicBool,
-) {
-    let listener = LocalSocketListener::bind(get_socket_name()).expect("could not bind to socket");
+    clock: &'static dyn Clock,
+}
 
+async fn get_user_command(listener: LocalSocketListener, state: ServerState) {
     loop {
         match listener.accept().await {
             Ok(conn) => {
-                tokio::spawn(async move {
-                    handle_user_command(conn, config, processes, close_server_flag).await
-                });
+                tokio::spawn(async move { handle_user_command(conn, state).await });
             }
             Err(e) => {
                 eprintln!("{e}");
@@ -72,43 +238,172 @@ async fn get_user_command(
     }
 }
 
-async fn handle_user_command(
-    conn: LocalSocketStream,
-    config: &RwLock<Config>,
-    processes: &RwLock<Processes>,
-    close_server_flag: &AtomicBool,
-) {
+async fn handle_user_command(conn: LocalSocketStream, state: ServerState) {
+    let ServerState {
+        socket_name,
+        config,
+        config_layers,
+        processes,
+        journal,
+        data_dir,
+        audit_log,
+        close_server_flag,
+        clock,
+        ..
+    } = state;
+
+    let peer = peer_credentials(&conn);
+
     let (reader, mut writer) = conn.into_split();
 
     let mut reader = BufReader::new(reader);
     let mut buffer = String::with_capacity(256);
     _ = reader.read_line(&mut buffer).await;
 
-    let command: Commands = serde_json::from_str(&buffer).expect("must not fail");
-
-    let response = match command {
-        Commands::View(show_cmd) => get_processes(show_cmd.ids, processes).await,
-        Commands::Settings => get_settings(config).await,
-        Commands::Remove(remove_cmd) => remove_processes(remove_cmd.id, processes).await,
-        Commands::Add(add_cmd) => add_new_process(add_cmd, processes).await,
-        Commands::Option(config_cmd) => change_config(config_cmd, config).await,
-        Commands::Change(change_cmd) => change_process(change_cmd, processes).await,
-        Commands::Duration(duration_cmd) => change_duration(duration_cmd, processes).await,
-        Commands::Export(export_cmd) => get_processes(export_cmd.ids, processes).await,
-        Commands::Import(import_cmd) => import_processes(import_cmd, processes).await,
-        Commands::Move(move_cmd) => move_process(move_cmd, processes).await,
-        Commands::Quit => set_exit_flag(close_server_flag).await,
+    // Clients that only check whether the server is running disconnect without sending anything
+    let command: Commands = match serde_json::from_str(&buffer) {
+        Ok(command) => command,
+        Err(e) if !buffer.is_empty() => {
+            let response: Result<String, String> = Err(format!("invalid request -> {e}"));
+            let serialized = serde_json::to_string(&response).expect("must serialize") + "\n";
+            _ = writer.write_all(serialized.as_bytes()).await;
+            return;
+        }
+        Err(_) => return,
+    };
 
-        _ => unreachable!(),
+    if command.is_client_only() {
+        let response: Result<String, String> = Err("not a server command".to_string());
+        let serialized = serde_json::to_string(&response).expect("must serialize") + "\n";
+        _ = writer.write_all(serialized.as_bytes()).await;
+        return;
     }
-    .map_err(|e| e.to_string());
+
+    let permission = authorize(&peer, server_uid(), &config.read().await.allowed_peers);
+    let required = required_permission(&command);
+
+    let response = if permission.is_none_or(|permission| permission < required) {
+        Err(format!(
+            "permission denied for uid {} -> {required} access is needed",
+            peer.uid
+                .map_or_else(|| "?".to_string(), |uid| uid.to_string())
+        ))
+    } else if command.is_audited() {
+        let request = serde_json::to_value(&command).expect("must serialize");
+
+        // Nothing else may change the config or the processes while the request is handled, so that only the changes
+        // made by this request are recorded. The request works on its own copy, which then replaces the shared state
+        let mut config_guard = config.write().await;
+        let mut processes_guard = processes.write().await;
+        let config_before = config_guard.clone();
+        let processes_before = processes_guard.clone();
+
+        let request_config = RwLock::new(std::mem::take(&mut *config_guard));
+        let request_processes = RwLock::new(std::mem::take(&mut *processes_guard));
+        let response = handle_command(command, state, &request_config, &request_processes).await;
+        *config_guard = request_config.into_inner();
+        *processes_guard = request_processes.into_inner();
+
+        let changes = diff_state(
+            (&config_before, &processes_before),
+            (&config_guard, &processes_guard),
+        );
+        drop((config_guard, processes_guard));
+
+        let entry = AuditEntry {
+            date: clock.now(),
+            peer,
+            command: request,
+            succeeded: response.is_ok(),
+            changes,
+        };
+
+        if let Err(e) = audit_log.write().await.append(&entry) {
+            eprintln!("{e}");
+        }
+
+        response
+    } else {
+        handle_command(command, state, config, processes).await
+    };
 
     let serialized = serde_json::to_string(&response).expect("must serialize") + "\n";
 
     _ = writer.write_all(serialized.as_bytes()).await;
 
     if close_server_flag.load(Ordering::Relaxed) {
-        _ = save_data(config, processes).await;
+        _ = save_data(config, config_layers, processes, journal, data_dir).await;
+
+        if !socket_name.starts_with('@') {
+            _ = std::fs::remove_file(socket_name);
+        }
+
         std::process::exit(0)
     }
 }
+
+/// Handle a request that is allowed, using the given config and processes instead of those of the state
+async fn handle_command(
+    command: Commands,
+    state: ServerState,
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+) -> Result<String, String> {
+    let ServerState {
+        shared_socket,
+        config_layers,
+        journal,
+        synced_hosts,
+        audit_log,
+        close_server_flag,
+        clock,
+        ..
+    } = state;
+
+    match command {
+        Commands::View(show_cmd) => {
+            get_processes(show_cmd.ids, show_cmd.host, processes, synced_hosts).await
+        }
+        Commands::Settings => get_settings(config, config_layers).await,
+        Commands::Remove(remove_cmd) => {
+            remove_processes(remove_cmd.id, processes, journal, clock).await
+        }
+        Commands::Add(add_cmd) => add_new_process(add_cmd, processes, journal, clock).await,
+        Commands::Option(config_cmd) => {
+            let before = config.read().await.clone();
+            let response = change_config(config_cmd, config, shared_socket)
+                .await
+                .map_err(|e| e.to_string());
+
+            // A setting hidden by this launch that is changed again is saved with its new value
+            let changed = config.read().await.overrides_from(&before);
+            config_layers.write().await.unshadow(&changed);
+
+            response.map_err(Into::into)
+        }
+        Commands::Change(change_cmd) => change_process(change_cmd, processes, journal, clock).await,
+        Commands::Duration(duration_cmd) => {
+            change_duration(duration_cmd, processes, journal, clock).await
+        }
+        Commands::Export(export_cmd) => {
+            get_processes(export_cmd.ids, None, processes, synced_hosts).await
+        }
+        Commands::Import(import_cmd) => {
+            import_processes(import_cmd, processes, journal, clock).await
+        }
+        Commands::Move(move_cmd) => move_process(move_cmd, processes, journal, clock).await,
+        Commands::Archive(archive_cmd) => {
+            handle_archive(archive_cmd, config, processes, journal, clock).await
+        }
+        Commands::Undo => undo_operation(processes, journal).await,
+        Commands::Redo => redo_operation(processes, journal).await,
+        Commands::History(history_cmd) => get_history(history_cmd, journal).await,
+        Commands::Audit(audit_cmd) => get_audit_entries(audit_cmd, audit_log, clock).await,
+        Commands::Quit => set_exit_flag(close_server_flag).await,
+
+        Commands::Launch(_) | Commands::Processes(_) | Commands::Profiles => {
+            Err("not a server command".into())
+        }
+    }
+    .map_err(|e| e.to_string())
+}
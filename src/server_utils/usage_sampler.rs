@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use crate::{process_scanner::ResourceUsage, structures::process::Processes};
+
+/// Turns samples of the running instances of tracked processes into the usage of each process. The CPU time of an
+/// instance only counts from the first time it was sampled while the server runs, so that the time it used before
+/// it was tracked is left out
+#[derive(Debug, Default)]
+pub struct UsageSampler {
+    cpu_millis: HashMap<u32, u64>,
+    sampled: bool,
+}
+
+impl UsageSampler {
+    /// Add a sample of every running instance, given as its PID, its name and what it has used so far
+    pub fn record(
+        &mut self,
+        processes: &mut Processes,
+        samples: Vec<(u32, String, ResourceUsage)>,
+    ) {
+        let mut totals: HashMap<String, (u64, u64, u64)> = HashMap::new();
+        let mut cpu_millis = HashMap::with_capacity(samples.len());
+
+        for (pid, name, usage) in samples {
+            let used_cpu_millis = match self.cpu_millis.get(&pid) {
+                Some(last_cpu_millis) => usage.cpu_millis.saturating_sub(*last_cpu_millis),
+                // Instances that were already running when the server started are only a baseline
+                None if !self.sampled => 0,
+                // Instances that started since the last sample used all of their CPU time while tracked
+                None => usage.cpu_millis,
+            };
+            cpu_millis.insert(pid, usage.cpu_millis);
+
+            let total = totals.entry(name).or_default();
+            total.0 += used_cpu_millis;
+            total.1 += usage.rss_kib;
+            total.2 += 1;
+        }
+
+        for (name, (used_cpu_millis, rss_kib, instances)) in totals {
+            if let Some(process) = processes.get_mut(&name) {
+                process.usage.record(used_cpu_millis, rss_kib, instances);
+            }
+        }
+
+        self.cpu_millis = cpu_millis;
+        self.sampled = true;
+    }
+}
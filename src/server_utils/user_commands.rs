@@ -3,19 +3,44 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::RwLock;
 
 use crate::{
-    commands, parse_datetime, parse_range, string_to_duration,
+    clock::Clock,
+    commands::{self, ConflictStrategy, ImportFormat},
+    format_datetime, format_duration,
+    human_input::parse_absolute_datetime,
+    parse_datetime, parse_range, string_to_duration,
     structures::{
-        config::Config,
+        activitywatch::ActivityWatchExport,
+        archive::{Archive, ArchiveData},
+        audit_log::AuditLog,
+        config::{Config, ConfigLayers},
+        csv_process::read_csv,
+        host_log::{SyncedHosts, ALL_HOSTS},
+        journal::{Journal, Operation},
         legacy_process::LegacyProcesses,
-        process::{Process, Processes},
+        process::{sessions_to_processes, Process, Processes},
+        timewarrior::TimewarriorExport,
+        toggl::read_toggl_csv,
     },
+    write_atomically, DisplayTimezone,
 };
 
 pub async fn get_processes(
     ids: Option<String>,
+    host: Option<String>,
     processes: &RwLock<Processes>,
+    synced_hosts: &RwLock<SyncedHosts>,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let processes = &processes.read().await.0;
+    let processes = processes.read().await;
+
+    // Other hosts are only read from the sync directory, so their processes are copies
+    let host_processes;
+    let processes = match host {
+        Some(host) => {
+            host_processes = synced_hosts.read().await.select(&host, &processes)?;
+            &host_processes
+        }
+        None => &processes.0,
+    };
 
     // We collect the processes to a Vec<(usize, &Process)> because we want to retain the process's ID
     // when showing it to the client
@@ -31,18 +56,34 @@ pub async fn get_processes(
         processes.iter().enumerate().collect()
     };
 
+    // How many instances are running is not saved with the process, so it is sent next to it
+    let targets: Vec<(usize, &Process, u64)> = targets
+        .into_iter()
+        .map(|(id, process)| (id, process, process.instances))
+        .collect();
+
     Ok(serde_json::to_string(&targets).expect("must serialize"))
 }
 
-pub async fn get_settings(config: &RwLock<Config>) -> Result<String, Box<dyn std::error::Error>> {
-    Ok(serde_json::to_string(&*config.read().await).expect("must serialize"))
+/// Every setting with where its value came from
+pub async fn get_settings(
+    config: &RwLock<Config>,
+    config_layers: &RwLock<ConfigLayers>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let settings = config_layers.read().await.settings(&*config.read().await);
+
+    Ok(serde_json::to_string(&settings).expect("must serialize"))
 }
 
 pub async fn add_new_process(
     add_cmd: commands::Add,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    if processes.read().await.contains_process(&add_cmd.name) {
+    let mut processes = processes.write().await;
+
+    if processes.contains_process(&add_cmd.name) {
         return Err(format!("process {} is already tracked", add_cmd.name).into());
     }
 
@@ -53,28 +94,48 @@ pub async fn add_new_process(
     };
 
     let added_date = if let Some(added_date) = add_cmd.added_date {
-        parse_datetime(&added_date)?
+        parse_datetime(&added_date, clock.now())?
+    } else {
+        clock.now()
+    };
+
+    let min_session = if let Some(min_session) = add_cmd.min_session {
+        string_to_duration(&min_session)?
     } else {
-        chrono::prelude::Local::now().naive_local()
+        0
     };
 
-    processes.write().await.0.push(Process {
-        is_running: false,
-        is_tracked: true,
+    let process = Process {
         icon: add_cmd.icon.unwrap_or_default(),
-        name: add_cmd.name.clone(),
         duration,
         notes: add_cmd.notes.unwrap_or_default(),
-        last_seen_date: chrono::NaiveDateTime::from_timestamp_millis(0).expect("0 is in range"),
-        added_date,
-    });
+        poll_interval: add_cmd.poll_interval,
+        min_session,
+        users: add_cmd.users.unwrap_or_default(),
+        cgroup: add_cmd.cgroup.filter(|pattern| !pattern.is_empty()),
+        ..Process::new(add_cmd.name.clone(), added_date)
+    };
 
-    Ok(format!("added {}", add_cmd.name))
+    let response = format!("added {}", add_cmd.name);
+
+    journal.write().await.record(
+        clock.now(),
+        &response,
+        Operation::Add {
+            id: processes.0.len(),
+            process: process.clone(),
+        },
+    );
+    processes.0.push(process);
+
+    Ok(response)
 }
 
 pub async fn remove_processes(
     id: usize,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let removed;
 
@@ -85,16 +146,28 @@ pub async fn remove_processes(
     } else if processes.len() - 1 < id {
         return Err(format!("no process with id {id}").into());
     } else {
-        removed = processes[id].name.clone();
-        processes.remove(id);
+        removed = processes.remove(id);
     }
 
-    Ok(format!("removed {removed}"))
+    let response = format!("removed {}", removed.name);
+
+    journal.write().await.record(
+        clock.now(),
+        &response,
+        Operation::Remove {
+            id,
+            process: removed,
+        },
+    );
+
+    Ok(response)
 }
 
+/// `shared_socket` tells whether the server listens where other users can reach it
 pub async fn change_config(
     config_cmd: commands::Config,
     config: &RwLock<Config>,
+    shared_socket: bool,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let mut config = config.write().await;
 
@@ -131,12 +204,59 @@ pub async fn change_config(
         config.autosave_interval = autosave_interval;
     }
 
+    if let Some(sync_interval) = config_cmd.sync_interval {
+        if sync_interval < crate::MIN_SYNC_INTERVAL {
+            return Err(format!(
+                "invalid sync interval -> min {}, got {sync_interval}",
+                crate::MIN_SYNC_INTERVAL
+            )
+            .into());
+        }
+        config.sync_interval = sync_interval;
+    }
+
+    // An empty path or host name turns syncing off or goes back to the system host name
+    if let Some(sync_dir) = config_cmd.sync_dir {
+        config.sync_dir = Some(sync_dir).filter(|dir| !dir.as_os_str().is_empty());
+    }
+
+    if let Some(host_name) = config_cmd.host_name {
+        if host_name == ALL_HOSTS {
+            return Err(format!("invalid host name -> {ALL_HOSTS} is reserved").into());
+        }
+        config.host_name = Some(host_name).filter(|name| !name.is_empty());
+    }
+
+    for revoked in config_cmd.revoke {
+        config
+            .allowed_peers
+            .retain(|allowed_peer| allowed_peer.peer != revoked);
+    }
+
+    for allowed in config_cmd.allow {
+        match config
+            .allowed_peers
+            .iter_mut()
+            .find(|allowed_peer| allowed_peer.peer == allowed.peer)
+        {
+            Some(allowed_peer) => allowed_peer.access = allowed.access,
+            None => config.allowed_peers.push(allowed),
+        }
+    }
+
+    // The socket cannot be moved out of the private runtime dir while clients may be connected to it
+    if !shared_socket && !config.allowed_peers.is_empty() {
+        return Ok("changed config -> launch the server again to let other users connect".into());
+    }
+
     Ok("changed config".into())
 }
 
 pub async fn change_process(
     change_cmd: commands::Change,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let processes = &mut processes.write().await.0;
 
@@ -144,6 +264,11 @@ pub async fn change_process(
         .get_mut(change_cmd.id)
         .ok_or_else(|| format!("invalid ID {}", change_cmd.id))?;
 
+    // Changes are made to a copy so that nothing is changed if a later value turns out to be invalid
+    let before = target.clone();
+    let mut changed = target.clone();
+    let target = &mut changed;
+
     if let Some(tracking) = change_cmd.tracking {
         target.is_tracked = tracking;
     }
@@ -161,15 +286,46 @@ pub async fn change_process(
     }
 
     if let Some(added_date) = change_cmd.added_date {
-        target.added_date = parse_datetime(&added_date)?;
+        target.added_date = parse_datetime(&added_date, clock.now())?;
+    }
+
+    // 0 goes back to the poll interval of the config
+    if let Some(poll_interval) = change_cmd.poll_interval {
+        target.poll_interval = Some(poll_interval).filter(|interval| *interval > 0);
+    }
+
+    if let Some(min_session) = change_cmd.min_session {
+        target.min_session = string_to_duration(&min_session)?;
+    }
+
+    if let Some(users) = change_cmd.users {
+        target.users = users;
+    }
+
+    if let Some(cgroup) = change_cmd.cgroup {
+        target.cgroup = Some(cgroup).filter(|pattern| !pattern.is_empty());
     }
 
-    Ok(format!("changed {}", target.name))
+    let response = format!("changed {}", changed.name);
+
+    processes[change_cmd.id] = changed.clone();
+    journal.write().await.record(
+        clock.now(),
+        &response,
+        Operation::Update {
+            before: Box::new(before),
+            after: Box::new(changed),
+        },
+    );
+
+    Ok(response)
 }
 
 pub async fn change_duration(
     duration_cmd: commands::Duration,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let processes = &mut processes.write().await.0;
 
@@ -177,6 +333,8 @@ pub async fn change_duration(
         .get_mut(duration_cmd.id)
         .ok_or_else(|| format!("invalid ID {}", duration_cmd.id))?;
 
+    let before = target.clone();
+
     let (action, amount);
 
     target.duration = match duration_cmd.operation {
@@ -192,74 +350,278 @@ pub async fn change_duration(
         }
     };
 
-    Ok(format!("{action} {amount} seconds for {}", target.name))
+    let response = format!("{action} {amount} seconds for {}", target.name);
+
+    journal.write().await.record(
+        clock.now(),
+        &response,
+        Operation::Update {
+            before: Box::new(before),
+            after: Box::new(target.clone()),
+        },
+    );
+
+    Ok(response)
 }
 
 pub async fn import_processes(
     import_cmd: commands::Import,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let file = std::fs::OpenOptions::new()
         .read(true)
         .open(&import_cmd.path)
         .map_err(|e| format!("cannot open file {} -> {e}", import_cmd.path.display()))?;
 
-    let processes = &mut processes.write().await;
+    let import_format = import_cmd.format();
+    let duration_format = import_cmd.duration_format.unwrap_or_default();
 
-    let (mut newly_added, mut already_existed) = (vec![], vec![]);
+    let mut rejected_rows = vec![];
+    let mut rejected_events = vec![];
 
-    if !import_cmd.legacy {
-        let new_processes: Processes =
-            serde_json::from_reader(file).map_err(|e| format!("error parsing json -> {e}"))?;
-
-        for new_process in new_processes.0 {
-            if !processes.contains_process(&new_process.name) {
-                newly_added.push(new_process.name.clone());
-                processes.0.push(Process {
-                    is_running: false,
-                    ..new_process
-                });
-            } else {
-                already_existed.push(new_process.name.clone());
-            }
+    let new_processes: Vec<Process> = match import_format {
+        ImportFormat::Json => {
+            let new_processes: Processes =
+                serde_json::from_reader(file).map_err(|e| format!("error parsing json -> {e}"))?;
+            new_processes.0
         }
-    } else {
-        let new_legacy_processes: LegacyProcesses =
-            serde_json::from_reader(file).map_err(|e| format!("error parsing json -> {e}"))?;
-
-        for (name, new_legacy_process) in new_legacy_processes.0 {
-            if !processes.contains_process(&name) {
-                newly_added.push(name.clone());
-
-                processes.0.push(Process {
-                    is_running: false,
-                    is_tracked: new_legacy_process.tracking,
-                    icon: new_legacy_process.icon_path,
-                    name,
-                    duration: new_legacy_process.duration,
-                    notes: new_legacy_process.notes,
-                    last_seen_date: parse_datetime(&new_legacy_process.last_seen)?,
-                    added_date: parse_datetime(&new_legacy_process.date_added)?,
+        ImportFormat::Legacy => {
+            let new_legacy_processes: LegacyProcesses =
+                serde_json::from_reader(file).map_err(|e| format!("error parsing json -> {e}"))?;
+
+            new_legacy_processes
+                .0
+                .into_iter()
+                .map(|(name, new_legacy_process)| {
+                    let added_date = parse_absolute_datetime(&new_legacy_process.date_added)?;
+
+                    Ok(Process {
+                        is_tracked: new_legacy_process.tracking,
+                        icon: new_legacy_process.icon_path,
+                        duration: new_legacy_process.duration,
+                        notes: new_legacy_process.notes,
+                        last_seen_date: parse_absolute_datetime(&new_legacy_process.last_seen)?,
+                        ..Process::new(name, added_date)
+                    })
                 })
-            } else {
-                already_existed.push(name.clone());
+                .collect::<Result<_, String>>()?
+        }
+        ImportFormat::Csv => {
+            let csv_import = read_csv(file, import_cmd.delimiter, clock.now())?;
+            rejected_rows = csv_import.rejected;
+            csv_import.processes
+        }
+        ImportFormat::ActivityWatch => {
+            let export: ActivityWatchExport = serde_json::from_reader(file)
+                .map_err(|e| format!("error parsing activitywatch json -> {e}"))?;
+            let activitywatch_import = export.into_sessions();
+            rejected_events = activitywatch_import.rejected;
+            sessions_to_processes(activitywatch_import.sessions)
+        }
+        ImportFormat::Timewarrior => {
+            let export: TimewarriorExport = serde_json::from_reader(file)
+                .map_err(|e| format!("error parsing timewarrior json -> {e}"))?;
+            sessions_to_processes(export.into_sessions(clock.now())?)
+        }
+        ImportFormat::Toggl => {
+            let toggl_import = read_toggl_csv(file, import_cmd.delimiter)?;
+            rejected_rows = toggl_import.rejected;
+            sessions_to_processes(toggl_import.sessions)
+        }
+    };
+
+    let strategy = import_cmd
+        .on_conflict
+        .unwrap_or(if import_format.is_session_based() {
+            // Time recorded by other trackers is added on top of what we already tracked
+            ConflictStrategy::Sum
+        } else {
+            ConflictStrategy::Skip
+        });
+
+    let mut processes = processes.write().await;
+
+    // A dry run imports into a copy, so that conflicts between processes in the same file are still shown correctly
+    let mut preview;
+    let target = if import_cmd.dry_run {
+        preview = processes.clone();
+        &mut preview
+    } else {
+        &mut *processes
+    };
+
+    let mut response = if import_cmd.dry_run {
+        "dry run, nothing was changed".to_string()
+    } else {
+        String::new()
+    };
+
+    let mut operations = vec![];
+
+    for new_process in new_processes {
+        let outcome = if let Some(existing) = target.get_mut(&new_process.name) {
+            let before = existing.clone();
+            let name = new_process.name.clone();
+
+            let outcome = match strategy {
+                ConflictStrategy::Skip => format!("skipped {name}, already tracked"),
+                ConflictStrategy::Overwrite => {
+                    existing.overwrite(new_process);
+                    format!(
+                        "overwrote {name}, duration {} -> {}",
+                        format_duration(before.duration, duration_format),
+                        format_duration(existing.duration, duration_format)
+                    )
+                }
+                ConflictStrategy::Sum | ConflictStrategy::Max | ConflictStrategy::MergeNotes => {
+                    existing.merge(new_process, strategy);
+                    format!(
+                        "merged {name}, duration {} -> {}",
+                        format_duration(before.duration, duration_format),
+                        format_duration(existing.duration, duration_format)
+                    )
+                }
+            };
+
+            if strategy != ConflictStrategy::Skip {
+                operations.push(Operation::Update {
+                    before: Box::new(before),
+                    after: Box::new(existing.clone()),
+                });
             }
+
+            outcome
+        } else {
+            let outcome = format!(
+                "added {}, duration {}",
+                new_process.name,
+                format_duration(new_process.duration, duration_format)
+            );
+            let new_process = Process {
+                is_running: false,
+                ..new_process
+            };
+            operations.push(Operation::Add {
+                id: target.0.len(),
+                process: new_process.clone(),
+            });
+            target.0.push(new_process);
+            outcome
+        };
+
+        if !response.is_empty() {
+            response.push('\n');
         }
+        response += &outcome;
     }
 
-    Ok(format!(
-        "added {newly_added:?}{}",
-        if already_existed.is_empty() {
-            "".to_string()
-        } else {
-            format!(", already tracked {already_existed:?}")
+    if response.is_empty() {
+        response = "nothing to import".into();
+    }
+
+    if !import_cmd.dry_run && !operations.is_empty() {
+        journal.write().await.record(
+            clock.now(),
+            &format!("import from {}", import_cmd.path.display()),
+            Operation::Batch(operations),
+        );
+    }
+
+    for rejected_row in rejected_rows {
+        response += &format!(
+            "\nrejected row on line {} -> {}",
+            rejected_row.line, rejected_row.reason
+        );
+    }
+
+    for rejected_event in rejected_events {
+        response += &format!("\nrejected {rejected_event}");
+    }
+
+    Ok(response)
+}
+
+pub async fn handle_archive(
+    archive_cmd: commands::Archive,
+    config: &RwLock<Config>,
+    processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
+) -> Result<String, Box<dyn std::error::Error>> {
+    match archive_cmd.action {
+        commands::ArchiveAction::Create { path } => {
+            let data = ArchiveData {
+                config: config.read().await.clone(),
+                processes: processes.read().await.clone(),
+                journal: journal.read().await.clone(),
+            };
+            let archive = Archive::new(&data, clock.now());
+
+            let serialized = serde_json::to_vec_pretty(&archive).expect("must serialize");
+
+            write_atomically(&path, &serialized)
+                .map_err(|e| format!("cannot write archive {} -> {e}", path.display()))?;
+
+            Ok(format!(
+                "archived {} processes to {}",
+                data.processes.0.len(),
+                path.display()
+            ))
         }
-    ))
+        commands::ArchiveAction::Restore { path } => {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .open(&path)
+                .map_err(|e| format!("cannot open file {} -> {e}", path.display()))?;
+
+            let archive: Archive = serde_json::from_reader(std::io::BufReader::new(file))
+                .map_err(|e| format!("error parsing archive -> {e}"))?;
+
+            let ArchiveData {
+                config: mut new_config,
+                processes: mut new_processes,
+                journal: new_journal,
+            } = archive.verify()?;
+
+            // Whether a process is running is checked again by the next poll
+            for process in new_processes.0.iter_mut() {
+                process.is_running = false;
+            }
+
+            let restored = new_processes.0.len();
+
+            {
+                let mut config = config.write().await;
+
+                // The archive may come from another machine, so who may connect, where the data is kept and the name
+                // of this host stay as they are
+                new_config.allowed_peers = std::mem::take(&mut config.allowed_peers);
+                new_config.data_dir = config.data_dir.take();
+                new_config.host_name = config.host_name.take();
+
+                *config = new_config;
+            }
+
+            // The operations of the current processes cannot be undone on the restored ones, so their journal replaces it
+            let mut processes = processes.write().await;
+            *processes = new_processes;
+            *journal.write().await = new_journal;
+
+            Ok(format!(
+                "restored {restored} processes, config and history from {}",
+                path.display()
+            ))
+        }
+    }
 }
 
 pub async fn move_process(
     move_cmd: commands::Move,
     processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+    clock: &dyn Clock,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let processes = &mut processes.write().await.0;
 
@@ -271,7 +633,7 @@ pub async fn move_process(
         return Err(format!("no process with id {}", move_cmd.id).into());
     }
 
-    let range: Box<dyn Iterator<Item = usize>>;
+    let range: Box<dyn Iterator<Item = usize> + Send>;
 
     let moved = processes[move_cmd.id].name.clone();
 
@@ -303,11 +665,32 @@ pub async fn move_process(
         }
     }
 
+    let (mut from, mut to) = (move_cmd.id, move_cmd.id);
+
     for i in range {
         processes.swap(i, i + 1);
+        from = from.min(i);
+        to = to.max(i + 1);
+    }
+
+    // Moving up swaps towards the start of the list, so the process ends up at the lower index
+    if let Up | Top = move_cmd.direction {
+        std::mem::swap(&mut from, &mut to);
     }
 
-    Ok(format!("moved {moved}"))
+    let response = format!("moved {moved}");
+
+    journal.write().await.record(
+        clock.now(),
+        &response,
+        Operation::Move {
+            name: moved,
+            from,
+            to,
+        },
+    );
+
+    Ok(response)
 }
 
 pub async fn set_exit_flag(
@@ -317,3 +700,87 @@ pub async fn set_exit_flag(
 
     Ok("stopping server".into())
 }
+
+pub async fn undo_operation(
+    processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut processes = processes.write().await;
+
+    Ok(journal.write().await.undo(&mut processes)?)
+}
+
+pub async fn redo_operation(
+    processes: &RwLock<Processes>,
+    journal: &RwLock<Journal>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut processes = processes.write().await;
+
+    Ok(journal.write().await.redo(&mut processes)?)
+}
+
+pub async fn get_history(
+    history_cmd: commands::History,
+    journal: &RwLock<Journal>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let journal = journal.read().await;
+
+    if journal.undo.is_empty() && journal.redo.is_empty() {
+        return Ok("no operations recorded".into());
+    }
+
+    // The newest operation comes first, which is also the one that undo reverts
+    let mut lines: Vec<String> = journal
+        .undo
+        .iter()
+        .rev()
+        .take(history_cmd.limit)
+        .map(|entry| {
+            format!(
+                "{} {}",
+                format_datetime(&entry.date, DisplayTimezone::Local),
+                entry.description
+            )
+        })
+        .collect();
+
+    if !journal.redo.is_empty() {
+        lines.push(format!(
+            "{} undone operations can be redone",
+            journal.redo.len()
+        ));
+    }
+
+    Ok(lines.join("\n"))
+}
+
+pub async fn get_audit_entries(
+    audit_cmd: commands::Audit,
+    audit_log: &RwLock<AuditLog>,
+    clock: &dyn Clock,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let since = match audit_cmd.since {
+        Some(since) => Some(parse_datetime(&since, clock.now())?),
+        None => None,
+    };
+
+    let entries = audit_log.read().await.read()?;
+
+    let mut matching: Vec<_> = entries
+        .into_iter()
+        .filter(|entry| audit_cmd.uid.is_none() || entry.peer.uid == audit_cmd.uid)
+        .filter(|entry| since.is_none_or(|since| entry.date >= since))
+        .filter(|entry| {
+            audit_cmd
+                .process
+                .as_ref()
+                .is_none_or(|process| entry.changes.iter().any(|change| &change.target == process))
+        })
+        .collect();
+
+    // Only the most recent entries are sent, oldest first
+    let skipped = matching.len().saturating_sub(audit_cmd.limit);
+    matching.drain(..skipped);
+
+    Ok(serde_json::to_string(&matching).expect("must serialize"))
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::process::ImportedSession;
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityWatchEvent {
+    pub timestamp: DateTime<Utc>,
+    /// Seconds
+    pub duration: f64,
+    #[serde(default)]
+    pub data: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityWatchBucket {
+    #[serde(default)]
+    pub events: Vec<ActivityWatchEvent>,
+}
+
+#[derive(Debug, Default)]
+pub struct ActivityWatchImport {
+    pub sessions: Vec<ImportedSession>,
+    /// Why events of an app could not be imported, e.g. because of an invalid duration
+    pub rejected: Vec<String>,
+}
+
+/// The JSON file created when exporting one or all buckets from ActivityWatch
+#[derive(Debug, Deserialize)]
+pub struct ActivityWatchExport {
+    pub buckets: HashMap<String, ActivityWatchBucket>,
+}
+
+impl ActivityWatchExport {
+    /// Turn every event that has an app name, such as the ones from the window watcher, into a session of that app.
+    /// Events from other watchers, such as the AFK watcher, are ignored
+    pub fn into_sessions(self) -> ActivityWatchImport {
+        let mut import = ActivityWatchImport::default();
+
+        for event in self.buckets.into_values().flat_map(|bucket| bucket.events) {
+            let Some(name) = event.data.get("app").and_then(|app| app.as_str()) else {
+                continue;
+            };
+
+            match event_end(&event) {
+                Some(end) => import.sessions.push(ImportedSession {
+                    name: name.to_owned(),
+                    start: event.timestamp,
+                    end,
+                }),
+                None => import.rejected.push(format!(
+                    "event of {name} at {} -> invalid duration {}",
+                    event.timestamp.to_rfc3339(),
+                    event.duration
+                )),
+            }
+        }
+
+        // Buckets come from a map, so we sort to keep the order of imported processes stable
+        import.sessions.sort_by_key(|session| session.start);
+
+        import
+    }
+}
+
+/// When the event ended, unless its duration is negative or too large to be a time
+fn event_end(event: &ActivityWatchEvent) -> Option<DateTime<Utc>> {
+    if !event.duration.is_finite() || event.duration < 0.0 {
+        return None;
+    }
+
+    // Casting saturates, so durations that are too large for milliseconds fail to be added instead
+    let duration = chrono::Duration::milliseconds((event.duration * 1000.0) as i64);
+
+    event.timestamp.checked_add_signed(duration)
+}
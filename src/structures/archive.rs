@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+
+use super::{config::Config, journal::Journal, process::Processes};
+
+/// Increased whenever the archive format changes in a way older versions cannot read
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Everything needed to recreate the state of a tracker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveData {
+    pub config: Config,
+    pub processes: Processes,
+    /// The operations that can be undone and redone, which refer to the processes by name
+    pub journal: Journal,
+}
+
+/// A full backup of the tracker in a single file, unlike exports which only contain processes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub created_date: DateTime<Utc>,
+    /// SHA-256 of the data exactly as it is written in the archive, in lowercase hex
+    pub checksum: String,
+    /// The data is kept as written, so that the checksum does not depend on how this version would serialize it
+    pub data: Box<RawValue>,
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+impl Archive {
+    pub fn new(data: &ArchiveData, created_date: DateTime<Utc>) -> Self {
+        let data = serde_json::value::to_raw_value(data).expect("must serialize");
+
+        Self {
+            version: ARCHIVE_VERSION,
+            created_date,
+            checksum: checksum(data.get().as_bytes()),
+            data,
+        }
+    }
+
+    /// Check that the archive can be read by this version and that its data is unchanged since it was created, then
+    /// read the data
+    pub fn verify(&self) -> Result<ArchiveData, String> {
+        if self.version > ARCHIVE_VERSION {
+            return Err(format!(
+                "archive version {} is newer than the supported version {ARCHIVE_VERSION}",
+                self.version
+            ));
+        }
+
+        let actual = checksum(self.data.get().as_bytes());
+
+        if actual != self.checksum {
+            return Err(format!(
+                "archive checksum mismatch -> expected {}, got {actual}",
+                self.checksum
+            ));
+        }
+
+        serde_json::from_str(self.data.get())
+            .map_err(|e| format!("error parsing archive data -> {e}"))
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{config::Config, process::Processes};
+
+/// The audit log is rotated once it grows past this many bytes
+pub const AUDIT_LOG_MAX_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated audit logs are kept next to the current one
+pub const AUDIT_LOG_ROTATIONS: usize = 3;
+
+/// Process fields that change on their own while the server runs and are therefore not audited
+const RUNTIME_FIELDS: [&str; 5] = [
+    "is_running",
+    "last_seen_date",
+    "launches",
+    "longest_session",
+    "usage",
+];
+
+/// Who sent a request, as reported by the operating system for the other end of the socket
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCredentials {
+    pub uid: Option<u32>,
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// The supplementary groups of the peer, which are only needed to authorize it
+    #[serde(skip)]
+    pub groups: Vec<u32>,
+}
+
+/// One field that a request changed. The target is either "config" or the name of a process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub target: String,
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub date: DateTime<Utc>,
+    pub peer: PeerCredentials,
+    /// The request as it was sent by the client
+    pub command: Value,
+    pub succeeded: bool,
+    pub changes: Vec<FieldChange>,
+}
+
+/// An append only log of JSON lines that is rotated by size, so that audit.log becomes audit.log.1,
+/// audit.log.1 becomes audit.log.2 and so on
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub rotations: usize,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            max_size: AUDIT_LOG_MAX_SIZE,
+            rotations: AUDIT_LOG_ROTATIONS,
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let size = std::fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+
+        if size >= self.max_size {
+            self.rotate()?;
+        }
+
+        let mut line = serde_json::to_vec(entry).expect("must serialize");
+        line.push(b'\n');
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| format!("cannot write audit log {} -> {e}", self.path.display()))?;
+
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.rotations == 0 {
+            std::fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+
+        // The oldest log is overwritten by the one before it
+        for index in (1..self.rotations).rev() {
+            let from = self.rotated_path(index);
+
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+
+        Ok(())
+    }
+
+    /// Read every entry from the oldest rotated log to the current one. Lines that cannot be parsed are skipped
+    pub fn read(&self) -> Result<Vec<AuditEntry>, Box<dyn std::error::Error>> {
+        let mut entries = vec![];
+
+        let paths = (1..=self.rotations)
+            .rev()
+            .map(|index| self.rotated_path(index))
+            .chain(std::iter::once(self.path.clone()));
+
+        for path in paths {
+            let file = match std::fs::File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(format!("cannot read audit log {} -> {e}", path.display()).into())
+                }
+            };
+
+            for line in std::io::BufReader::new(file).lines() {
+                if let Ok(entry) = serde_json::from_str(&line?) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Compare the state of the server before and after a request and list every field that changed
+pub fn diff_state(
+    (config_before, processes_before): (&Config, &Processes),
+    (config_after, processes_after): (&Config, &Processes),
+) -> Vec<FieldChange> {
+    let mut changes = vec![];
+
+    diff_objects(
+        "config",
+        serde_json::to_value(config_before).expect("must serialize"),
+        serde_json::to_value(config_after).expect("must serialize"),
+        &mut changes,
+    );
+
+    let position = |processes: &Processes, name: &str| {
+        processes.0.iter().position(|process| process.name == name)
+    };
+
+    for (id, before) in processes_before.0.iter().enumerate() {
+        let before_value = serde_json::to_value(before).expect("must serialize");
+
+        match position(processes_after, &before.name) {
+            Some(new_id) => {
+                if id != new_id {
+                    changes.push(FieldChange {
+                        target: before.name.clone(),
+                        field: "id".into(),
+                        before: id.into(),
+                        after: new_id.into(),
+                    });
+                }
+
+                let after_value =
+                    serde_json::to_value(&processes_after.0[new_id]).expect("must serialize");
+                diff_objects(&before.name, before_value, after_value, &mut changes);
+            }
+            None => changes.push(FieldChange {
+                target: before.name.clone(),
+                field: "process".into(),
+                before: without_runtime_fields(before_value),
+                after: Value::Null,
+            }),
+        }
+    }
+
+    for after in processes_after.0.iter() {
+        if position(processes_before, &after.name).is_none() {
+            changes.push(FieldChange {
+                target: after.name.clone(),
+                field: "process".into(),
+                before: Value::Null,
+                after: without_runtime_fields(serde_json::to_value(after).expect("must serialize")),
+            });
+        }
+    }
+
+    changes
+}
+
+fn diff_objects(target: &str, before: Value, after: Value, changes: &mut Vec<FieldChange>) {
+    let (Value::Object(before), Value::Object(mut after)) = (before, after) else {
+        return;
+    };
+
+    let mut fields: Vec<(String, Value, Value)> = before
+        .into_iter()
+        .map(|(field, before)| {
+            let after = after.remove(&field).unwrap_or(Value::Null);
+            (field, before, after)
+        })
+        .collect();
+
+    // Fields that are left out while empty may only be set after the request
+    fields.extend(
+        after
+            .into_iter()
+            .map(|(field, after)| (field, Value::Null, after)),
+    );
+
+    for (field, before, after) in fields {
+        if before != after && !RUNTIME_FIELDS.contains(&field.as_str()) {
+            changes.push(FieldChange {
+                target: target.into(),
+                field,
+                before,
+                after,
+            });
+        }
+    }
+}
+
+fn without_runtime_fields(mut value: Value) -> Value {
+    if let Value::Object(object) = &mut value {
+        for field in RUNTIME_FIELDS {
+            object.remove(field);
+        }
+    }
+
+    value
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{get_config_dir, DurationFormat};
+
+/// Settings that only affect how the client shows data. Unlike [`super::config::Config`] these are never sent to
+/// the server
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    pub duration_format: DurationFormat,
+}
+
+impl ClientConfig {
+    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
+        let config_path = get_config_dir()
+            .ok_or_else(|| "cannot find config dir".to_string())?
+            .join("client.json");
+
+        let reader = std::fs::OpenOptions::new().read(true).open(config_path)?;
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let config_dir = get_config_dir().ok_or_else(|| "cannot find config dir".to_string())?;
+
+        if !config_dir.exists() {
+            std::fs::create_dir_all(&config_dir)?;
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(config_dir.join("client.json"))
+            .map_err(|e| format!("cannot open client config path -> {e}"))?;
+
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+}
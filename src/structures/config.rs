@@ -1,34 +1,458 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{data_dir_from, get_data_dir, get_host_name, get_profile, write_atomically};
 
-use crate::get_config_dir;
+use super::host_log::ALL_HOSTS;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub poll_interval: u64,
     pub duration_update_interval: u64,
     pub autosave_interval: u64,
+    /// A directory shared with other machines, e.g. through Syncthing or a NAS, used to combine their tracked time
+    pub sync_dir: Option<PathBuf>,
+    pub sync_interval: u64,
+    /// The name this machine uses in the sync directory instead of its host name
+    pub host_name: Option<String>,
+    /// Other users that may connect to the server besides the user running it
+    pub allowed_peers: Vec<AllowedPeer>,
+    /// Where processes, the journal and the audit log are kept instead of the data dir of the profile
+    pub data_dir: Option<PathBuf>,
+}
+
+/// The settings file that is edited by hand. The server creates it once and never writes to it afterwards
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// The settings changed with the option command, kept in the data dir apart from the config file
+pub const RUNTIME_SETTINGS_FILE_NAME: &str = "settings.json";
+
+/// The prefix of the environment variables that override settings
+pub const ENV_VAR_PREFIX: &str = "SPT_";
+
+/// The config of older versions, which was rewritten on every save and is converted to the config file once
+pub const LEGACY_CONFIG_FILE_NAME: &str = "config.json";
+
+/// Every setting in the order it appears in the config file, with its documentation and an example for settings
+/// that are not set by default
+const CONFIG_FILE_DOCS: [(&str, &str, &str); 8] = [
+    (
+        "poll_interval",
+        "How often to check which processes are running when the system does not report them as they start, in seconds. At least 10",
+        "15",
+    ),
+    (
+        "duration_update_interval",
+        "How often the duration of running processes is increased, in seconds. At least 1",
+        "10",
+    ),
+    (
+        "autosave_interval",
+        "How often processes are saved to the data dir, in seconds. At least 60",
+        "300",
+    ),
+    (
+        "sync_dir",
+        "A directory shared with other machines, e.g. through Syncthing or a NAS, used to combine their tracked time",
+        "\"/mnt/nas/simple-process-tracker\"",
+    ),
+    (
+        "sync_interval",
+        "How often to sync through the sync dir, in seconds. At least 60",
+        "300",
+    ),
+    (
+        "host_name",
+        "The name this machine uses in the sync dir instead of its host name",
+        "\"desktop\"",
+    ),
+    (
+        "allowed_peers",
+        "Other users or groups that may connect to the server, by their numeric ID. The access is read-only or read-write",
+        "[{ uid = 1001, access = \"read-only\" }, { group = 100, access = \"read-write\" }]",
+    ),
+    (
+        "data_dir",
+        "Where processes, the undo history and the audit log are kept. Only read when the server is launched",
+        "\"/home/user/tracking\"",
+    ),
+];
+
+/// Where the value of a setting came from, from lowest to highest priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConfigSource {
+    Default,
+    ConfigFile,
+    Environment,
+    LaunchFlag,
+    /// Changed with the option command while the server runs
+    Runtime,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::ConfigFile => CONFIG_FILE_NAME,
+            ConfigSource::Environment => "environment",
+            ConfigSource::LaunchFlag => "launch flag",
+            ConfigSource::Runtime => "option command",
+        };
+
+        write!(f, "{source}")
+    }
+}
+
+/// The value of a setting and where it came from, as shown by the settings command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Setting {
+    pub name: String,
+    pub value: Value,
+    pub source: ConfigSource,
+}
+
+/// The settings given when the server was launched, each only holding the settings it sets. Together they make
+/// up the config before anything is changed at runtime
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    pub file: Map<String, Value>,
+    pub env: Map<String, Value>,
+    pub flags: Map<String, Value>,
+    /// The defaults with the config file, the environment and the launch flags applied in that order
+    pub base: Config,
+    /// The settings changed at runtime before this launch that the environment or the launch flags hide. They are
+    /// saved again, so that they apply once more when the server is launched without them
+    pub shadowed: Map<String, Value>,
+}
+
+impl ConfigLayers {
+    pub fn new(
+        file: Map<String, Value>,
+        env: Map<String, Value>,
+        flags: Map<String, Value>,
+    ) -> Result<Self, String> {
+        let mut merged = file.clone();
+        merged.extend(env.clone());
+        merged.extend(flags.clone());
+
+        let base = Config::default().with_overrides(&merged)?;
+
+        Ok(Self {
+            file,
+            env,
+            flags,
+            base,
+            shadowed: Map::new(),
+        })
+    }
+
+    /// The same layers with another config file
+    pub fn with_file(&self, file: Map<String, Value>) -> Result<Self, String> {
+        Ok(Self {
+            shadowed: self.shadowed.clone(),
+            ..Self::new(file, self.env.clone(), self.flags.clone())?
+        })
+    }
+
+    /// The config with the settings saved at runtime applied, except those given for this launch, which are kept
+    /// aside instead
+    pub fn apply_runtime_settings(
+        &mut self,
+        runtime_settings: Map<String, Value>,
+    ) -> Result<Config, String> {
+        let (shadowed, applied): (Map<String, Value>, Map<String, Value>) = runtime_settings
+            .into_iter()
+            .partition(|(name, _)| self.env.contains_key(name) || self.flags.contains_key(name));
+
+        let config = self.base.with_overrides(&applied)?;
+        self.shadowed = shadowed;
+
+        Ok(config)
+    }
+
+    /// The settings to save: those changed at runtime and those hidden for this launch that were not changed since
+    pub fn runtime_settings(&self, config: &Config) -> Map<String, Value> {
+        let mut runtime_settings = self.shadowed.clone();
+        runtime_settings.extend(config.overrides_from(&self.base));
+        runtime_settings
+    }
+
+    /// Stop keeping aside the hidden settings that were changed at runtime, so that the new values are saved
+    pub fn unshadow(&mut self, changed: &Map<String, Value>) {
+        self.shadowed.retain(|name, _| !changed.contains_key(name));
+    }
+
+    /// Every setting of the config in the order of the config file and where its value came from
+    pub fn settings(&self, config: &Config) -> Vec<Setting> {
+        let Value::Object(mut values) = serde_json::to_value(config).expect("must serialize")
+        else {
+            unreachable!("config is a struct");
+        };
+        let runtime_settings = config.overrides_from(&self.base);
+
+        CONFIG_FILE_DOCS
+            .iter()
+            .map(|(name, ..)| {
+                let source = if runtime_settings.contains_key(*name) {
+                    ConfigSource::Runtime
+                } else if self.flags.contains_key(*name) {
+                    ConfigSource::LaunchFlag
+                } else if self.env.contains_key(*name) {
+                    ConfigSource::Environment
+                } else if self.file.contains_key(*name) {
+                    ConfigSource::ConfigFile
+                } else {
+                    ConfigSource::Default
+                };
+
+                Setting {
+                    name: name.to_string(),
+                    value: values.remove(*name).unwrap_or(Value::Null),
+                    source,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Read the config file from the config dir. A missing config file is created with every setting documented,
+/// using the values of the legacy JSON config if there is one
+pub fn read_or_create_config_file(
+    config_dir: &Path,
+) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    let config_path = config_dir.join(CONFIG_FILE_NAME);
+
+    if config_path.exists() {
+        return Ok(read_config_file(&config_path)?);
+    }
+
+    let legacy_path = config_dir.join(LEGACY_CONFIG_FILE_NAME);
+
+    let config = match std::fs::read(&legacy_path) {
+        Ok(contents) => {
+            let config: Config = serde_json::from_slice(&contents)
+                .map_err(|e| format!("invalid config {} -> {e}", legacy_path.display()))?;
+            config.validate()?;
+            config
+        }
+        Err(_) => Config::default(),
+    };
+
+    let contents = config.to_commented_toml();
+
+    std::fs::create_dir_all(config_dir)?;
+    write_atomically(&config_path, contents.as_bytes())
+        .map_err(|e| format!("cannot write {} -> {e}", config_path.display()))?;
+
+    // The legacy config is kept as a backup but no longer read
+    if legacy_path.exists() {
+        std::fs::rename(&legacy_path, legacy_path.with_extension("json.bak"))?;
+    }
+
+    Ok(parse_config_file(&contents)?)
+}
+
+pub fn read_config_file(path: &Path) -> Result<Map<String, Value>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {} -> {e}", path.display()))?;
+
+    parse_config_file(&contents).map_err(|e| format!("invalid config {} -> {e}", path.display()))
+}
+
+/// Parse the settings set in a config file. They are checked on top of the defaults
+pub fn parse_config_file(contents: &str) -> Result<Map<String, Value>, String> {
+    let table: toml::Table = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+    let Value::Object(settings) = serde_json::to_value(table).map_err(|e| e.to_string())? else {
+        unreachable!("a table is an object");
+    };
+
+    Config::default().with_overrides(&settings)?;
+
+    Ok(settings)
+}
+
+/// Parse the settings set with SPT_* environment variables, e.g. SPT_POLL_INTERVAL=30 or
+/// SPT_ALLOWED_PEERS=uid:1001,group:100:read-write. Empty paths and names unset the setting
+pub fn parse_env_settings(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Map<String, Value>, String> {
+    let mut settings = Map::new();
+
+    for (var, input) in vars {
+        let Some(name) = var
+            .strip_prefix(ENV_VAR_PREFIX)
+            .map(str::to_lowercase)
+            .filter(|name| CONFIG_FILE_DOCS.iter().any(|(field, ..)| field == name))
+        else {
+            continue;
+        };
+
+        let value = match name.as_str() {
+            "allowed_peers" => {
+                let allowed_peers = input
+                    .split(',')
+                    .filter(|peer| !peer.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<Vec<AllowedPeer>, _>>()
+                    .map_err(|e| format!("invalid {var} -> {e}"))?;
+                serde_json::to_value(allowed_peers).expect("must serialize")
+            }
+            "sync_dir" | "host_name" | "data_dir" if input.is_empty() => Value::Null,
+            // Relative paths would change with the working dir. Profiles keep their own data under the data dir, the
+            // same as under the default data dir
+            "data_dir" if !Path::new(&input).is_absolute() => {
+                return Err(format!(
+                    "invalid {var} {input} -> expected an absolute path"
+                ));
+            }
+            "data_dir" => data_dir_from(Some(input.into()), None, None, get_profile())
+                .map(|dir| Value::String(dir.display().to_string()))
+                .unwrap_or(Value::Null),
+            "sync_dir" | "host_name" => Value::String(input),
+            _ => input
+                .parse::<u64>()
+                .map(Value::from)
+                .map_err(|e| format!("invalid {var} {input} -> {e}"))?,
+        };
+
+        settings.insert(name, value);
+    }
+
+    Config::default()
+        .with_overrides(&settings)
+        .map_err(|e| format!("invalid environment -> {e}"))?;
+
+    Ok(settings)
 }
 
 impl Config {
-    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = get_config_dir()
-            .expect("cannot find config dir")
-            .join("config.json");
+    pub fn parse_toml(contents: &str) -> Result<Self, String> {
+        Config::default().with_overrides(&parse_config_file(contents)?)
+    }
 
-        let reader = std::fs::OpenOptions::new().read(true).open(config_path)?;
+    pub fn validate(&self) -> Result<(), String> {
+        let intervals = [
+            (
+                "poll_interval",
+                self.poll_interval,
+                crate::MIN_POLL_INTERVAL,
+            ),
+            (
+                "duration_update_interval",
+                self.duration_update_interval,
+                crate::MIN_DURATION_UPDATE_INTERVAL,
+            ),
+            (
+                "autosave_interval",
+                self.autosave_interval,
+                crate::MIN_AUTOSAVE_INTERVAL,
+            ),
+            (
+                "sync_interval",
+                self.sync_interval,
+                crate::MIN_SYNC_INTERVAL,
+            ),
+        ];
 
-        let config: Config = serde_json::from_reader(reader)?;
+        for (name, interval, min) in intervals {
+            if interval < min {
+                return Err(format!("invalid {name} -> min {min}, got {interval}"));
+            }
+        }
 
-        if config.poll_interval < crate::MIN_POLL_INTERVAL
-            || config.duration_update_interval < crate::MIN_DURATION_UPDATE_INTERVAL
-            || config.autosave_interval < crate::MIN_AUTOSAVE_INTERVAL
-        {
-            return Err("invalid config interval".into());
+        if self.host_name.as_deref() == Some(ALL_HOSTS) {
+            return Err(format!("invalid host_name -> {ALL_HOSTS} is reserved"));
         }
 
+        Ok(())
+    }
+
+    /// Apply settings on top of this config, e.g. the settings changed at runtime on top of the config file
+    pub fn with_overrides(&self, overrides: &Map<String, Value>) -> Result<Self, String> {
+        let mut value = serde_json::to_value(self).expect("must serialize");
+
+        if let Value::Object(fields) = &mut value {
+            fields.extend(overrides.clone());
+        }
+
+        let config: Config = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        config.validate()?;
+
         Ok(config)
     }
+
+    /// The settings of this config that differ from another one, e.g. those changed at runtime since the config file
+    /// was read
+    pub fn overrides_from(&self, base: &Config) -> Map<String, Value> {
+        let (Value::Object(fields), Value::Object(base_fields)) = (
+            serde_json::to_value(self).expect("must serialize"),
+            serde_json::to_value(base).expect("must serialize"),
+        ) else {
+            unreachable!("config is a struct");
+        };
+
+        fields
+            .into_iter()
+            .filter(|(field, value)| base_fields.get(field) != Some(value))
+            .collect()
+    }
+
+    /// The config file with every setting documented. Settings that are not set are left commented out
+    pub fn to_commented_toml(&self) -> String {
+        let toml::Value::Table(fields) = toml::Value::try_from(self).expect("must serialize")
+        else {
+            unreachable!("config is a struct");
+        };
+
+        let mut contents = String::from(
+            "# Simple process tracker configuration\n\
+             #\n\
+             # Changes are applied as soon as this file is saved. If it has a mistake, the last valid config is kept.\n\
+             # Settings changed with the option command are kept in the data dir and take priority over this file.\n",
+        );
+
+        for (field, doc, example) in CONFIG_FILE_DOCS {
+            contents.push_str(&format!("\n# {doc}\n"));
+
+            match fields.get(field) {
+                Some(value) => contents.push_str(&format!("{field} = {value}\n")),
+                None => contents.push_str(&format!("# {field} = {example}\n")),
+            }
+        }
+
+        contents
+    }
+
+    /// The data dir that is set, or the data dir of the profile
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        self.data_dir.clone().or_else(get_data_dir)
+    }
+
+    pub fn host_name(&self) -> String {
+        self.host_name.clone().unwrap_or_else(get_host_name)
+    }
+}
+
+/// Read the settings changed at runtime. There are none until the option command is used
+pub fn read_runtime_settings(
+    data_dir: &Path,
+) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    let settings_path = data_dir.join(RUNTIME_SETTINGS_FILE_NAME);
+
+    match std::fs::read(&settings_path) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents)
+            .map_err(|e| format!("invalid settings {} -> {e}", settings_path.display()))?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Map::new()),
+        Err(e) => Err(format!("cannot read {} -> {e}", settings_path.display()).into()),
+    }
 }
 
 impl Default for Config {
@@ -37,6 +461,77 @@ impl Default for Config {
             poll_interval: 15,
             duration_update_interval: 10,
             autosave_interval: 300,
+            sync_dir: None,
+            sync_interval: 300,
+            host_name: None,
+            allowed_peers: vec![],
+            data_dir: None,
         }
     }
 }
+
+/// A user or every member of a group, identified by their numeric ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerId {
+    Uid(u32),
+    Group(u32),
+}
+
+impl FromStr for PeerId {
+    type Err = String;
+
+    /// Accepts uid:1001 or group:100
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = input
+            .split_once(':')
+            .ok_or_else(|| format!("invalid peer {input} -> expected uid:<id> or group:<id>"))?;
+
+        let id = id
+            .parse::<u32>()
+            .map_err(|e| format!("invalid peer id {id} -> {e}"))?;
+
+        match kind {
+            "uid" => Ok(Self::Uid(id)),
+            "group" => Ok(Self::Group(id)),
+            _ => Err(format!(
+                "invalid peer {input} -> expected uid:<id> or group:<id>"
+            )),
+        }
+    }
+}
+
+/// What an allowed peer may do. Read only peers can look at processes and settings but not change anything
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessLevel {
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedPeer {
+    #[serde(flatten)]
+    pub peer: PeerId,
+    pub access: AccessLevel,
+}
+
+impl FromStr for AllowedPeer {
+    type Err = String;
+
+    /// Accepts a peer optionally followed by its access level, e.g. uid:1001, uid:1001:read-write
+    /// or group:100:read-only. Peers are read only unless stated otherwise
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (peer, access) = match input.rsplit_once(':') {
+            Some((peer, "read-only")) => (peer, AccessLevel::ReadOnly),
+            Some((peer, "read-write")) => (peer, AccessLevel::ReadWrite),
+            _ => (input, AccessLevel::ReadOnly),
+        };
+
+        Ok(Self {
+            peer: peer.parse()?,
+            access,
+        })
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::{
+    format_duration,
+    human_input::parse_absolute_datetime,
+    string_to_duration,
+    structures::process::{Process, Usage, Users},
+    DurationFormat,
+};
+
+pub const CSV_HEADERS: [&str; 19] = [
+    "name",
+    "tracking",
+    "duration",
+    "duration_seconds",
+    "notes",
+    "icon",
+    "last_seen_date",
+    "added_date",
+    "launches",
+    "longest_session_seconds",
+    "poll_interval",
+    "min_session_seconds",
+    "users",
+    "cgroup",
+    "cpu_millis",
+    "peak_rss_kib",
+    "avg_rss_kib",
+    "rss_samples",
+    "peak_instances",
+];
+
+/// A CSV row that could not be imported
+#[derive(Debug, PartialEq, Eq)]
+pub struct RejectedRow {
+    /// The line in the CSV file the row starts on
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct CsvImport {
+    pub processes: Vec<Process>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// Write processes as CSV with a header row. Durations are written both in the given human readable format
+/// and in seconds
+pub fn write_csv<W: Write>(
+    writer: W,
+    processes: &[Process],
+    delimiter: u8,
+    duration_format: DurationFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+
+    writer.write_record(CSV_HEADERS)?;
+
+    for process in processes {
+        writer.write_record([
+            process.name.clone(),
+            process.is_tracked.to_string(),
+            format_duration(process.duration, duration_format),
+            process.duration.to_string(),
+            process.notes.clone(),
+            process.icon.display().to_string(),
+            process.last_seen_date.to_rfc3339(),
+            process.added_date.to_rfc3339(),
+            process.launches.to_string(),
+            process.longest_session.to_string(),
+            process
+                .poll_interval
+                .map(|interval| interval.to_string())
+                .unwrap_or_default(),
+            process.min_session.to_string(),
+            process.users.to_string(),
+            process.cgroup.clone().unwrap_or_default(),
+            process.usage.cpu_millis.to_string(),
+            process.usage.peak_rss_kib.to_string(),
+            process.usage.avg_rss_kib.to_string(),
+            process.usage.rss_samples.to_string(),
+            process.usage.peak_instances.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Read processes from CSV, mapping columns by their header name. Only the name column is required.
+/// Header names are matched ignoring case, so "Duration Seconds" is the same as "duration_seconds".
+/// Rows that cannot be read are reported instead of failing the whole import
+pub fn read_csv<R: Read>(
+    reader: R,
+    delimiter: u8,
+    now: DateTime<Utc>,
+) -> Result<CsvImport, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("error reading csv header -> {e}"))?
+        .iter()
+        .map(normalize_header)
+        .collect();
+
+    if !headers.iter().any(|header| header == "name") {
+        return Err("csv header has no name column".into());
+    }
+
+    let mut import = CsvImport::default();
+    let mut seen_names = HashSet::new();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                import.rejected.push(RejectedRow {
+                    line: e.position().map_or(0, |position| position.line()),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let line = record.position().map_or(0, |position| position.line());
+
+        let row: HashMap<&str, &str> = headers
+            .iter()
+            .map(String::as_str)
+            .zip(record.iter())
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+
+        match row_to_process(&row, now) {
+            Ok(process) if !seen_names.insert(process.name.clone()) => {
+                import.rejected.push(RejectedRow {
+                    line,
+                    reason: format!("duplicate name {}", process.name),
+                });
+            }
+            Ok(process) => import.processes.push(process),
+            Err(reason) => import.rejected.push(RejectedRow { line, reason }),
+        }
+    }
+
+    Ok(import)
+}
+
+/// Make header names such as "Start date" or "Duration-Seconds" match "start_date" and "duration_seconds"
+pub(crate) fn normalize_header(header: &str) -> String {
+    header.to_lowercase().replace([' ', '-'], "_")
+}
+
+fn row_to_process(row: &HashMap<&str, &str>, now: DateTime<Utc>) -> Result<Process, String> {
+    let name = row.get("name").ok_or_else(|| "missing name".to_string())?;
+
+    let is_tracked = match row.get("tracking") {
+        Some(tracking) => parse_bool(tracking)?,
+        None => true,
+    };
+
+    // The exact number of seconds takes priority over the human readable duration, which may be rounded
+    let duration = match (row.get("duration_seconds"), row.get("duration")) {
+        (Some(seconds), _) => seconds
+            .parse::<u64>()
+            .map_err(|e| format!("invalid duration_seconds {seconds} -> {e}"))?,
+        (None, Some(duration)) => string_to_duration(duration)?,
+        (None, None) => 0,
+    };
+
+    let last_seen_date = match row.get("last_seen_date") {
+        Some(date) => parse_absolute_datetime(date)?,
+        None => Utc.timestamp_millis_opt(0).single().expect("0 is in range"),
+    };
+
+    let added_date = match row.get("added_date") {
+        Some(date) => parse_absolute_datetime(date)?,
+        None => now,
+    };
+
+    let launches = parse_number(row, "launches")?.unwrap_or(0);
+    let longest_session = parse_number(row, "longest_session_seconds")?.unwrap_or(0);
+
+    let users = match row.get("users") {
+        Some(users) => users.parse()?,
+        None => Users::Me,
+    };
+
+    let usage = Usage {
+        cpu_millis: parse_number(row, "cpu_millis")?.unwrap_or(0),
+        peak_rss_kib: parse_number(row, "peak_rss_kib")?.unwrap_or(0),
+        avg_rss_kib: parse_number(row, "avg_rss_kib")?.unwrap_or(0),
+        rss_samples: parse_number(row, "rss_samples")?.unwrap_or(0),
+        peak_instances: parse_number(row, "peak_instances")?.unwrap_or(0),
+    };
+
+    Ok(Process {
+        is_tracked,
+        icon: row.get("icon").map(PathBuf::from).unwrap_or_default(),
+        duration,
+        notes: row
+            .get("notes")
+            .map(|notes| notes.to_string())
+            .unwrap_or_default(),
+        last_seen_date,
+        poll_interval: parse_number(row, "poll_interval")?.filter(|interval| *interval > 0),
+        min_session: parse_number(row, "min_session_seconds")?.unwrap_or(0),
+        usage,
+        launches,
+        longest_session,
+        users,
+        cgroup: row.get("cgroup").map(|cgroup| cgroup.to_string()),
+        ..Process::new(name.to_string(), added_date)
+    })
+}
+
+fn parse_number(row: &HashMap<&str, &str>, column: &str) -> Result<Option<u64>, String> {
+    row.get(column)
+        .map(|number| {
+            number
+                .parse::<u64>()
+                .map_err(|e| format!("invalid {column} {number} -> {e}"))
+        })
+        .transpose()
+}
+
+fn parse_bool(input: &str) -> Result<bool, String> {
+    match input.to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" | "on" => Ok(true),
+        "false" | "no" | "n" | "0" | "off" => Ok(false),
+        _ => Err(format!(
+            "invalid tracking {input} -> expected true or false"
+        )),
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::write_atomically;
+
+use super::process::{Process, Processes};
+
+/// Shown instead of a host name to combine the processes of every host
+pub const ALL_HOSTS: &str = "all";
+
+/// The file in the data dir that remembers where the local host log was last written
+pub const WRITTEN_HOST_LOG_FILE_NAME: &str = "host_log_path.txt";
+
+/// The processes of one machine as written to the sync directory. Each machine only ever writes its own file and
+/// always writes its full totals, so reading a file again or reading an older copy of it never counts time twice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostLog {
+    pub host: String,
+    pub updated_date: DateTime<Utc>,
+    pub processes: Vec<Process>,
+}
+
+impl HostLog {
+    pub fn file_name(host: &str) -> String {
+        // Host names may contain characters that cannot be used in file names on every system
+        let sanitized: String = host
+            .chars()
+            .map(|char| {
+                if char.is_alphanumeric() || matches!(char, '-' | '_' | '.') {
+                    char
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        if sanitized == host {
+            return format!("{host}.json");
+        }
+
+        // Host names that only differ in the replaced characters still get their own file
+        let hash: String = Sha256::digest(host.as_bytes())[..4]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        format!("{sanitized}-{hash}.json")
+    }
+
+    /// Write the log to the sync directory and return where it was written
+    pub fn write(&self, sync_dir: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let serialized = serde_json::to_vec_pretty(self).expect("must serialize");
+        let path = sync_dir.join(Self::file_name(&self.host));
+
+        write_atomically(&path, &serialized)
+            .map_err(|e| format!("cannot write host log {} -> {e}", path.display()))?;
+
+        Ok(path)
+    }
+}
+
+/// Remember in the data dir where the local host log was written, and remove the log written before when it was
+/// written under an earlier host name or to an earlier sync directory, so that its time is not counted twice. Only
+/// logs this tracker wrote itself are removed, even when another machine is a copy of this one
+pub fn replace_written_host_log(data_dir: &Path, written: &Path) -> Result<(), String> {
+    let record_path = data_dir.join(WRITTEN_HOST_LOG_FILE_NAME);
+
+    let previous = match std::fs::read_to_string(&record_path) {
+        Ok(previous) => Some(PathBuf::from(previous)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("cannot read {} -> {e}", record_path.display())),
+    };
+
+    if previous.as_deref() == Some(written) {
+        return Ok(());
+    }
+
+    if let Some(previous) = previous {
+        match std::fs::remove_file(&previous) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(format!(
+                    "cannot remove old host log {} -> {e}",
+                    previous.display()
+                ))
+            }
+        }
+    }
+
+    std::fs::create_dir_all(data_dir)
+        .and_then(|()| write_atomically(&record_path, written.to_string_lossy().as_bytes()))
+        .map_err(|e| format!("cannot write {} -> {e}", record_path.display()))
+}
+
+/// The logs of the other hosts by host name, and the files that could not be read
+pub type HostLogs = (BTreeMap<String, HostLog>, Vec<String>);
+
+/// Read the logs of every host in the sync directory except the local one. Files that cannot be read are
+/// reported and skipped, since another machine may be in the middle of syncing them
+pub fn read_host_logs(
+    sync_dir: &Path,
+    local_host: &str,
+) -> Result<HostLogs, Box<dyn std::error::Error>> {
+    let entries = std::fs::read_dir(sync_dir)
+        .map_err(|e| format!("cannot read sync dir {} -> {e}", sync_dir.display()))?;
+
+    let (mut host_logs, mut errors) = (BTreeMap::new(), vec![]);
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.extension().is_none_or(|extension| extension != "json")
+            || entry.file_name().to_string_lossy() == HostLog::file_name(local_host)
+        {
+            continue;
+        }
+
+        let host_log: HostLog = match std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_slice(&contents).map_err(|e| e.to_string()))
+        {
+            Ok(host_log) => host_log,
+            Err(e) => {
+                errors.push(format!("cannot read host log {} -> {e}", path.display()));
+                continue;
+            }
+        };
+
+        if host_log.host != local_host {
+            host_logs.insert(host_log.host.clone(), host_log);
+        }
+    }
+
+    Ok((host_logs, errors))
+}
+
+/// Sum the processes of every host by name
+pub fn combine_hosts<'a>(
+    local: &[Process],
+    others: impl IntoIterator<Item = &'a HostLog>,
+) -> Vec<Process> {
+    let mut combined = local.to_vec();
+
+    for host_log in others {
+        for process in host_log.processes.iter() {
+            match combined
+                .iter_mut()
+                .find(|combined| combined.name == process.name)
+            {
+                Some(existing) => {
+                    existing.duration = existing.duration.saturating_add(process.duration);
+                    existing.usage.combine(&process.usage);
+                    existing.launches = existing.launches.saturating_add(process.launches);
+                    existing.longest_session =
+                        existing.longest_session.max(process.longest_session);
+                    existing.is_running |= process.is_running;
+                    existing.is_tracked |= process.is_tracked;
+                    existing.added_date = existing.added_date.min(process.added_date);
+                    existing.last_seen_date = existing.last_seen_date.max(process.last_seen_date);
+                }
+                None => combined.push(process.clone()),
+            }
+        }
+    }
+
+    combined
+}
+
+/// What the server knows about the other hosts that share its sync directory
+#[derive(Debug, Default)]
+pub struct SyncedHosts {
+    pub local_host: String,
+    pub others: BTreeMap<String, HostLog>,
+}
+
+impl SyncedHosts {
+    /// The processes of the given host, or of all hosts combined
+    pub fn select(&self, host: &str, local: &Processes) -> Result<Vec<Process>, String> {
+        if host == ALL_HOSTS {
+            Ok(combine_hosts(&local.0, self.others.values()))
+        } else if host == self.local_host {
+            Ok(local.0.clone())
+        } else if let Some(host_log) = self.others.get(host) {
+            Ok(host_log.processes.clone())
+        } else {
+            let known: Vec<&str> = std::iter::once(self.local_host.as_str())
+                .chain(self.others.keys().map(String::as_str))
+                .collect();
+
+            Err(format!(
+                "unknown host {host} -> expected {ALL_HOSTS} or one of {known:?}"
+            ))
+        }
+    }
+}
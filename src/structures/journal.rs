@@ -0,0 +1,207 @@
+use std::{collections::VecDeque, path::Path};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::process::{Process, Processes};
+
+/// How many operations can be undone. Older operations are forgotten
+pub const JOURNAL_CAPACITY: usize = 100;
+
+/// A change to the process list that knows enough about the state before it to be reverted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation {
+    /// A process was inserted at the given ID
+    Add { id: usize, process: Process },
+    /// A process was removed from the given ID
+    Remove { id: usize, process: Process },
+    /// The data of a process was changed
+    Update {
+        before: Box<Process>,
+        after: Box<Process>,
+    },
+    /// A process was moved from one ID to another
+    Move {
+        name: String,
+        from: usize,
+        to: usize,
+    },
+    /// Several operations done by a single command, e.g. an import
+    Batch(Vec<Operation>),
+}
+
+impl Operation {
+    /// The operation that reverts this one
+    pub fn inverse(&self) -> Operation {
+        match self.clone() {
+            Operation::Add { id, process } => Operation::Remove { id, process },
+            Operation::Remove { id, process } => Operation::Add { id, process },
+            Operation::Update { before, after } => Operation::Update {
+                before: after,
+                after: before,
+            },
+            Operation::Move { name, from, to } => Operation::Move {
+                name,
+                from: to,
+                to: from,
+            },
+            Operation::Batch(operations) => {
+                Operation::Batch(operations.iter().rev().map(Operation::inverse).collect())
+            }
+        }
+    }
+
+    /// Apply the operation to the processes. Processes are found by name, so an operation fails instead of
+    /// changing the wrong process if the process list has changed in some other way since
+    pub fn apply(&self, processes: &mut Processes) -> Result<(), String> {
+        match self {
+            Operation::Add { id, process } => {
+                if processes.contains_process(&process.name) {
+                    return Err(format!("process {} is already tracked", process.name));
+                }
+
+                // Whether the process is running is checked again by the next poll
+                let id = (*id).min(processes.0.len());
+                processes.0.insert(
+                    id,
+                    Process {
+                        is_running: false,
+                        ..process.clone()
+                    },
+                );
+            }
+            Operation::Remove { process, .. } => {
+                let id = position(processes, &process.name)?;
+                processes.0.remove(id);
+            }
+            Operation::Update { before, after } => {
+                let current = processes
+                    .get_mut(&after.name)
+                    .ok_or_else(|| format!("process {} is no longer tracked", after.name))?;
+
+                // Time tracked since the operation was recorded is kept by only applying the difference in duration
+                let duration = (current.duration as i128 - before.duration as i128
+                    + after.duration as i128)
+                    .clamp(0, u64::MAX as i128) as u64;
+
+                *current = Process {
+                    is_running: current.is_running,
+                    last_seen_date: current.last_seen_date,
+                    session_duration: current.session_duration,
+                    counted_until: current.counted_until,
+                    seen_stopped: current.seen_stopped,
+                    usage: current.usage,
+                    launches: current.launches,
+                    longest_session: current.longest_session,
+                    instances: current.instances,
+                    duration,
+                    ..(**after).clone()
+                };
+            }
+            Operation::Move { name, to, .. } => {
+                let from = position(processes, name)?;
+                let process = processes.0.remove(from);
+                let to = (*to).min(processes.0.len());
+                processes.0.insert(to, process);
+            }
+            Operation::Batch(operations) => {
+                for operation in operations {
+                    operation.apply(processes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn position(processes: &Processes, name: &str) -> Result<usize, String> {
+    processes
+        .0
+        .iter()
+        .position(|process| process.name == name)
+        .ok_or_else(|| format!("process {name} is no longer tracked"))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub date: DateTime<Utc>,
+    /// What the command responded with when it was run, e.g. "removed mpv"
+    pub description: String,
+    pub operation: Operation,
+}
+
+/// The recent operations that can be undone and the undone operations that can be redone
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub undo: VecDeque<JournalEntry>,
+    pub redo: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn read(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let journal_path = data_dir.join("journal.json");
+
+        let contents = std::fs::read(journal_path)?;
+
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Remember a new operation. Anything that was undone can no longer be redone after this
+    pub fn record(&mut self, date: DateTime<Utc>, description: &str, operation: Operation) {
+        self.redo.clear();
+        self.push_undo(JournalEntry {
+            date,
+            description: description.into(),
+            operation,
+        });
+    }
+
+    fn push_undo(&mut self, entry: JournalEntry) {
+        self.undo.push_back(entry);
+
+        while self.undo.len() > JOURNAL_CAPACITY {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Revert the last operation. The processes are left untouched if it cannot be reverted
+    pub fn undo(&mut self, processes: &mut Processes) -> Result<String, String> {
+        let entry = self.undo.pop_back().ok_or("nothing to undo")?;
+
+        if let Err(e) = apply_all_or_nothing(&entry.operation.inverse(), processes) {
+            let description = entry.description.clone();
+            self.undo.push_back(entry);
+            return Err(format!("cannot undo {description} -> {e}"));
+        }
+
+        let response = format!("undid {}", entry.description);
+        self.redo.push(entry);
+
+        Ok(response)
+    }
+
+    /// Apply the last undone operation again
+    pub fn redo(&mut self, processes: &mut Processes) -> Result<String, String> {
+        let entry = self.redo.pop().ok_or("nothing to redo")?;
+
+        if let Err(e) = apply_all_or_nothing(&entry.operation, processes) {
+            let description = entry.description.clone();
+            self.redo.push(entry);
+            return Err(format!("cannot redo {description} -> {e}"));
+        }
+
+        let response = format!("redid {}", entry.description);
+        self.push_undo(entry);
+
+        Ok(response)
+    }
+}
+
+fn apply_all_or_nothing(operation: &Operation, processes: &mut Processes) -> Result<(), String> {
+    let mut changed = processes.clone();
+    operation.apply(&mut changed)?;
+    *processes = changed;
+
+    Ok(())
+}
@@ -1,3 +1,12 @@
+pub mod activitywatch;
+pub mod archive;
+pub mod audit_log;
+pub mod client_config;
 pub mod config;
+pub mod csv_process;
+pub mod host_log;
+pub mod journal;
 pub mod legacy_process;
 pub mod process;
+pub mod timewarrior;
+pub mod toggl;
@@ -1,11 +1,14 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
-use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 
-use crate::get_config_dir;
+use crate::{commands::ConflictStrategy, local_to_utc, process_scanner::ProcessSnapshot};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Process {
     pub is_running: bool,
     pub is_tracked: bool,
@@ -13,18 +16,393 @@ pub struct Process {
     pub name: String,
     pub duration: u64,
     pub notes: String,
-    pub last_seen_date: NaiveDateTime,
-    pub added_date: NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub last_seen_date: DateTime<Utc>,
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub added_date: DateTime<Utc>,
+    /// How often to check if this process is running in seconds, instead of the poll interval of the config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+    /// Runs shorter than this many seconds are not counted
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub min_session: u64,
+    /// How long the process has been running since it was last seen starting. Only known while the server runs
+    #[serde(skip)]
+    pub session_duration: u64,
+    /// Up to when the current run has been added to the duration. Only known while the server runs
+    #[serde(skip)]
+    pub counted_until: Option<DateTime<Utc>>,
+    /// Whether the process has been seen not running since the server started. A run that was already going on
+    /// before is not a launch. Only known while the server runs
+    #[serde(skip)]
+    pub seen_stopped: bool,
+    /// How heavy the process has been on the system while it was tracked
+    #[serde(default, skip_serializing_if = "Usage::is_empty")]
+    pub usage: Usage,
+    /// How many times the process started running while tracked. Runs shorter than the minimum session length are
+    /// not counted
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub launches: u64,
+    /// The longest single run in seconds
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub longest_session: u64,
+    /// How many instances of the process are running right now. Only known while the server runs
+    #[serde(skip)]
+    pub instances: u64,
+    /// Whose instances of the process count as it running
+    #[serde(default, skip_serializing_if = "Users::is_me")]
+    pub users: Users,
+    /// Count the instances whose cgroup path, systemd unit, Flatpak app ID or container ID matches this glob pattern
+    /// instead of the instances with the same name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// The users whose instances of a process are tracked
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Users {
+    /// Only the user running Simple process tracker
+    #[default]
+    Me,
+    /// Every user
+    Any,
+    /// The users with these IDs
+    Uids(Vec<u32>),
+}
+
+impl Users {
+    pub fn is_me(&self) -> bool {
+        *self == Users::Me
+    }
+
+    /// Whether an instance owned by the user with the given ID is tracked. Instances with no known owner always are
+    pub fn allows(&self, uid: Option<u32>) -> bool {
+        let Some(uid) = uid else {
+            return true;
+        };
+
+        match self {
+            Users::Me => crate::current_uid().is_none_or(|current_uid| current_uid == uid),
+            Users::Any => true,
+            Users::Uids(uids) => uids.contains(&uid),
+        }
+    }
+}
+
+impl std::fmt::Display for Users {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Users::Me => write!(f, "me"),
+            Users::Any => write!(f, "any"),
+            Users::Uids(uids) => {
+                let uids: Vec<String> = uids.iter().map(u32::to_string).collect();
+                write!(f, "{}", uids.join(","))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Users {
+    type Err = String;
+
+    /// Parse "me", "any" or a list of user IDs such as 1000,1001
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "me" => Ok(Users::Me),
+            "any" => Ok(Users::Any),
+            _ => input
+                .split(',')
+                .map(|uid| {
+                    uid.trim().parse().map_err(|e| {
+                        format!(
+                            "invalid user ID {uid} -> {e}, expected me, any or a list of user IDs"
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()
+                .map(Users::Uids),
+        }
+    }
+}
+
+/// How heavy a process has been on the system while it was tracked, summed across all of its instances
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    /// CPU time in milliseconds
+    pub cpu_millis: u64,
+    /// The most resident memory used at once, in KiB
+    pub peak_rss_kib: u64,
+    /// The resident memory used on average while running, in KiB
+    pub avg_rss_kib: u64,
+    /// How many times the memory was sampled, so that averages can be combined
+    pub rss_samples: u64,
+    /// The most instances that ran at once
+    pub peak_instances: u64,
+}
+
+impl Usage {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Add a sample of every running instance of the process, with the CPU time they used since the last sample
+    pub fn record(&mut self, cpu_millis: u64, rss_kib: u64, instances: u64) {
+        self.cpu_millis = self.cpu_millis.saturating_add(cpu_millis);
+        self.peak_rss_kib = self.peak_rss_kib.max(rss_kib);
+        self.peak_instances = self.peak_instances.max(instances);
+        self.add_rss_samples(rss_kib, 1);
+    }
+
+    /// Add the usage of the same process on another host or from an import
+    pub fn combine(&mut self, other: &Usage) {
+        self.cpu_millis = self.cpu_millis.saturating_add(other.cpu_millis);
+        self.peak_rss_kib = self.peak_rss_kib.max(other.peak_rss_kib);
+        self.peak_instances = self.peak_instances.max(other.peak_instances);
+        self.add_rss_samples(other.avg_rss_kib, other.rss_samples);
+    }
+
+    fn add_rss_samples(&mut self, avg_rss_kib: u64, samples: u64) {
+        let total_samples = self.rss_samples.saturating_add(samples);
+
+        if total_samples == 0 {
+            return;
+        }
+
+        let total_rss = self.avg_rss_kib as u128 * self.rss_samples as u128
+            + avg_rss_kib as u128 * samples as u128;
+
+        self.avg_rss_kib = (total_rss / total_samples as u128) as u64;
+        self.rss_samples = total_samples;
+    }
+}
+
+fn is_zero(seconds: &u64) -> bool {
+    *seconds == 0
+}
+
+impl Process {
+    /// A process that is tracked but has never been seen running
+    pub fn new(name: String, added_date: DateTime<Utc>) -> Self {
+        Self {
+            is_running: false,
+            is_tracked: true,
+            icon: PathBuf::new(),
+            name,
+            duration: 0,
+            notes: String::new(),
+            last_seen_date: Utc.timestamp_millis_opt(0).single().expect("0 is in range"),
+            added_date,
+            poll_interval: None,
+            min_session: 0,
+            session_duration: 0,
+            counted_until: None,
+            seen_stopped: false,
+            usage: Usage::default(),
+            launches: 0,
+            longest_session: 0,
+            instances: 0,
+            users: Users::Me,
+            cgroup: None,
+        }
+    }
+
+    /// Replace everything about this process with the imported one, except whether it is currently running.
+    /// Most import formats cannot hold how the process is matched and polled or its usage, so the current values of
+    /// these are kept unless the imported process sets them
+    pub fn overwrite(&mut self, imported: Process) {
+        let current = std::mem::replace(self, imported);
+
+        self.is_running = current.is_running;
+        self.session_duration = current.session_duration;
+        self.counted_until = current.counted_until;
+        self.seen_stopped = current.seen_stopped;
+        self.instances = current.instances;
+
+        if self.poll_interval.is_none() {
+            self.poll_interval = current.poll_interval;
+        }
+        if self.min_session == 0 {
+            self.min_session = current.min_session;
+        }
+        if self.usage.is_empty() {
+            self.usage = current.usage;
+        }
+        if self.users.is_me() {
+            self.users = current.users;
+        }
+        if self.cgroup.is_none() {
+            self.cgroup = current.cgroup;
+        }
+    }
+
+    /// Whether the given instance is one of this process's, ignoring whether it is tracked
+    pub fn matches(&self, process: &ProcessSnapshot) -> bool {
+        let is_same_process = match &self.cgroup {
+            Some(pattern) => process
+                .cgroup
+                .as_ref()
+                .is_some_and(|cgroup| cgroup.matches(pattern)),
+            None => self.name == process.name,
+        };
+
+        is_same_process && self.users.allows(process.uid)
+    }
+
+    /// Mark the process as seen running at the given time, starting a new run if it was not running before
+    fn mark_running(&mut self, now: DateTime<Utc>) {
+        if self.counted_until.is_none() && self.seen_stopped && self.min_session == 0 {
+            self.launches += 1;
+        }
+
+        self.is_running = true;
+        self.last_seen_date = now;
+        self.counted_until.get_or_insert(now);
+    }
+
+    /// Mark the process as not running, counting its last run up to when it was last seen
+    fn mark_stopped(&mut self) {
+        if self.is_running && self.is_tracked {
+            self.accrue_until(self.last_seen_date);
+        }
+
+        self.is_running = false;
+        self.session_duration = 0;
+        self.counted_until = None;
+        self.instances = 0;
+    }
+
+    /// Add the time the current run has lasted since it was last counted. A run only counts once it has lasted the
+    /// minimum session length of its process, and then counts from its start
+    fn accrue_until(&mut self, until: DateTime<Utc>) {
+        let Some(counted_until) = self.counted_until else {
+            return;
+        };
+
+        // Whole seconds are counted and the rest is left for the next time
+        let seconds = (until - counted_until).num_seconds().max(0) as u64;
+        self.counted_until = Some(counted_until + chrono::Duration::seconds(seconds as i64));
+
+        let before = self.session_duration;
+        self.session_duration = before.saturating_add(seconds);
+
+        if self.session_duration < self.min_session {
+            return;
+        }
+
+        self.longest_session = self.longest_session.max(self.session_duration);
+
+        self.duration += if before < self.min_session {
+            // Runs that had to last the minimum session length are only launches once they did
+            if self.seen_stopped {
+                self.launches += 1;
+            }
+            self.session_duration
+        } else {
+            seconds
+        };
+    }
+
+    /// Combine an imported process with the same name into this one
+    pub fn merge(&mut self, imported: Process, strategy: ConflictStrategy) {
+        match strategy {
+            ConflictStrategy::Skip => return,
+            ConflictStrategy::Overwrite => return self.overwrite(imported),
+            ConflictStrategy::Sum => {
+                self.duration = self.duration.saturating_add(imported.duration);
+                self.usage.combine(&imported.usage);
+                self.launches = self.launches.saturating_add(imported.launches);
+            }
+            ConflictStrategy::Max => {
+                self.duration = self.duration.max(imported.duration);
+                self.launches = self.launches.max(imported.launches);
+
+                if imported.usage.cpu_millis > self.usage.cpu_millis {
+                    self.usage = imported.usage;
+                }
+            }
+            ConflictStrategy::MergeNotes => {
+                if !imported.notes.is_empty() && !self.notes.contains(&imported.notes) {
+                    if !self.notes.is_empty() {
+                        self.notes.push('\n');
+                    }
+                    self.notes += &imported.notes;
+                }
+            }
+        }
+
+        if self.notes.is_empty() {
+            self.notes = imported.notes;
+        }
+
+        if self.icon.as_os_str().is_empty() {
+            self.icon = imported.icon;
+        }
+
+        self.is_tracked |= imported.is_tracked;
+        self.longest_session = self.longest_session.max(imported.longest_session);
+        self.added_date = self.added_date.min(imported.added_date);
+        self.last_seen_date = self.last_seen_date.max(imported.last_seen_date);
+    }
+}
+
+/// A period of time in which a process was running, as recorded by another time tracker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedSession {
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// Sum the sessions of each process name into one process. A process counts as added at the start of its first session
+/// and last seen at the end of its last session
+pub fn sessions_to_processes(sessions: impl IntoIterator<Item = ImportedSession>) -> Vec<Process> {
+    let mut processes: Vec<Process> = vec![];
+    let mut indices: HashMap<String, usize> = HashMap::new();
+
+    for session in sessions {
+        let seconds = (session.end - session.start).num_seconds().max(0) as u64;
+
+        let index = *indices.entry(session.name.clone()).or_insert_with(|| {
+            let mut process = Process::new(session.name, session.start);
+            process.last_seen_date = session.end;
+            processes.push(process);
+            processes.len() - 1
+        });
+
+        let process = &mut processes[index];
+        process.duration += seconds;
+        process.added_date = process.added_date.min(session.start);
+        process.last_seen_date = process.last_seen_date.max(session.end);
+    }
+
+    processes
+}
+
+/// Dates are stored in UTC, but older versions stored them in local time without an offset.
+/// Such dates are migrated by assuming they were saved in this system's local timezone
+fn deserialize_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    let input = String::deserialize(deserializer)?;
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(&input) {
+        return Ok(date.with_timezone(&Utc));
+    }
+
+    input
+        .parse::<NaiveDateTime>()
+        .map(local_to_utc)
+        .map_err(|e| serde::de::Error::custom(format!("invalid date time {input} -> {e}")))
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Processes(pub Vec<Process>);
 
 impl Processes {
-    pub fn read() -> Result<Self, Box<dyn std::error::Error>> {
-        let processes_path = get_config_dir()
-            .expect("cannot find config dir")
-            .join("processes.json");
+    pub fn read(data_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let processes_path = data_dir.join("processes.json");
 
         let reader = std::fs::OpenOptions::new()
             .read(true)
@@ -50,6 +428,10 @@ impl Processes {
         Ok(processes)
     }
 
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Process> {
+        self.0.iter_mut().find(|process| process.name == name)
+    }
+
     pub fn contains_process(&self, name: &str) -> bool {
         for process in self.0.iter() {
             if process.name == name {
@@ -58,4 +440,119 @@ impl Processes {
         }
         false
     }
+
+    /// The names of the tracked processes that are matched by name
+    pub fn tracked_names(&self) -> HashSet<String> {
+        self.0
+            .iter()
+            .filter(|process| process.is_tracked && process.cgroup.is_none())
+            .map(|process| process.name.clone())
+            .collect()
+    }
+
+    /// The cgroup patterns of the tracked processes that are matched by cgroup
+    pub fn tracked_cgroups(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|process| process.is_tracked)
+            .filter_map(|process| process.cgroup.clone())
+            .collect()
+    }
+
+    /// Whether exactly the processes with the given names are tracked by name
+    pub fn tracks_exactly(&self, names: &HashSet<String>) -> bool {
+        let mut tracked = self
+            .0
+            .iter()
+            .filter(|process| process.is_tracked && process.cgroup.is_none());
+
+        tracked.clone().count() == names.len()
+            && tracked.all(|process| names.contains(&process.name))
+    }
+
+    /// Mark every tracked process that appears in the scanned process list as running and every other process as not running
+    pub fn update_running(&mut self, process_list: &[ProcessSnapshot], now: DateTime<Utc>) {
+        self.update_running_of(process_list, now, |_| true);
+    }
+
+    /// The same as [`Processes::update_running`], but only for the processes that were due to be checked. Untracked
+    /// processes are never running, so they do not have to be checked
+    pub fn update_running_of(
+        &mut self,
+        process_list: &[ProcessSnapshot],
+        now: DateTime<Utc>,
+        is_checked: impl Fn(&Process) -> bool,
+    ) {
+        let mut running: HashMap<&str, Vec<&ProcessSnapshot>> = HashMap::new();
+        for running_process in process_list {
+            running
+                .entry(running_process.name.as_str())
+                .or_default()
+                .push(running_process);
+        }
+
+        for process in self.0.iter_mut() {
+            let is_running = match process.cgroup {
+                Some(_) => process_list
+                    .iter()
+                    .any(|instance| process.matches(instance)),
+                None => running.get(process.name.as_str()).is_some_and(|instances| {
+                    instances.iter().any(|instance| process.matches(instance))
+                }),
+            };
+
+            if !process.is_tracked {
+                process.mark_stopped();
+            } else if !is_checked(process) {
+                continue;
+            } else if is_running {
+                process.mark_running(now);
+            } else {
+                process.mark_stopped();
+                process.seen_stopped = true;
+            }
+        }
+    }
+
+    /// Update a tracked process after one of its instances started or exited at the given time, for when the system
+    /// reports this as it happens. A process that stops was last seen when its last instance exited
+    pub fn update_running_from_event(&mut self, name: &str, is_running: bool, now: DateTime<Utc>) {
+        let Some(process) = self.get_mut(name).filter(|process| process.is_tracked) else {
+            return;
+        };
+
+        if is_running {
+            process.mark_running(now);
+        } else if process.is_running {
+            process.last_seen_date = now;
+            process.mark_stopped();
+            process.seen_stopped = true;
+        }
+    }
+
+    /// Set how many instances of each tracked process are running, given the names of every running instance
+    pub fn update_instances<'a>(&mut self, running_names: impl IntoIterator<Item = &'a str>) {
+        let mut instances: HashMap<&str, u64> = HashMap::new();
+        for name in running_names {
+            *instances.entry(name).or_default() += 1;
+        }
+
+        for process in self.0.iter_mut() {
+            process.instances = if process.is_running {
+                // A process that is running has at least one instance, even if its PID is not known
+                instances.get(process.name.as_str()).copied().unwrap_or(1)
+            } else {
+                0
+            };
+        }
+    }
+
+    /// Add the time every tracked and running process has run since it was last counted, up to the given time
+    pub fn accrue_duration(&mut self, now: DateTime<Utc>) {
+        for process in self.0.iter_mut() {
+            if process.is_running && process.is_tracked {
+                process.accrue_until(now);
+            }
+        }
+    }
 }
@@ -0,0 +1,48 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use super::process::ImportedSession;
+
+#[derive(Debug, Deserialize)]
+pub struct TimewarriorInterval {
+    pub start: String,
+    /// Missing while the interval is still being tracked
+    pub end: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// The JSON created by "timew export"
+#[derive(Debug, Deserialize)]
+pub struct TimewarriorExport(pub Vec<TimewarriorInterval>);
+
+fn parse_timewarrior_date(input: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDateTime::parse_from_str(input, "%Y%m%dT%H%M%SZ")
+        .map(|date| Utc.from_utc_datetime(&date))
+        .map_err(|e| format!("invalid timewarrior date {input} -> {e}"))
+}
+
+impl TimewarriorExport {
+    /// Turn every interval into one session per tag. Intervals that are still open end at `now`
+    pub fn into_sessions(self, now: DateTime<Utc>) -> Result<Vec<ImportedSession>, String> {
+        let mut sessions = vec![];
+
+        for interval in self.0 {
+            let start = parse_timewarrior_date(&interval.start)?;
+            let end = match interval.end {
+                Some(end) => parse_timewarrior_date(&end)?,
+                None => now,
+            };
+
+            for tag in interval.tags {
+                sessions.push(ImportedSession {
+                    name: tag,
+                    start,
+                    end,
+                });
+            }
+        }
+
+        Ok(sessions)
+    }
+}
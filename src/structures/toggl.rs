@@ -0,0 +1,110 @@
+use std::{collections::HashMap, io::Read};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+use crate::{local_to_utc, string_to_duration};
+
+use super::{
+    csv_process::{normalize_header, RejectedRow},
+    process::ImportedSession,
+};
+
+#[derive(Debug, Default)]
+pub struct TogglImport {
+    pub sessions: Vec<ImportedSession>,
+    pub rejected: Vec<RejectedRow>,
+}
+
+/// Read the time entries of a Toggl Track detailed report CSV. Each entry becomes a session of its project,
+/// or of its description if it has no project
+pub fn read_toggl_csv<R: Read>(
+    reader: R,
+    delimiter: u8,
+) -> Result<TogglImport, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| format!("error reading csv header -> {e}"))?
+        .iter()
+        .map(normalize_header)
+        .collect();
+
+    for required in ["start_date", "start_time", "duration"] {
+        if !headers.iter().any(|header| header == required) {
+            return Err(format!("toggl csv header has no {required} column").into());
+        }
+    }
+
+    let mut import = TogglImport::default();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                import.rejected.push(RejectedRow {
+                    line: e.position().map_or(0, |position| position.line()),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let line = record.position().map_or(0, |position| position.line());
+
+        let row: HashMap<&str, &str> = headers
+            .iter()
+            .map(String::as_str)
+            .zip(record.iter())
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+
+        match row_to_session(&row) {
+            Ok(session) => import.sessions.push(session),
+            Err(reason) => import.rejected.push(RejectedRow { line, reason }),
+        }
+    }
+
+    Ok(import)
+}
+
+fn row_to_session(row: &HashMap<&str, &str>) -> Result<ImportedSession, String> {
+    let name = row
+        .get("project")
+        .or_else(|| row.get("description"))
+        .ok_or_else(|| "missing project and description".to_string())?;
+
+    let start = parse_toggl_date(row.get("start_date"), row.get("start_time"))?;
+
+    let duration = row
+        .get("duration")
+        .ok_or_else(|| "missing duration".to_string())
+        .and_then(|duration| string_to_duration(duration))?;
+
+    let end = start
+        + chrono::Duration::from_std(std::time::Duration::from_secs(duration))
+            .map_err(|e| format!("invalid duration -> {e}"))?;
+
+    Ok(ImportedSession {
+        name: name.to_string(),
+        start,
+        end,
+    })
+}
+
+/// Toggl writes dates and times in the local time of the user that created the report in separate columns
+fn parse_toggl_date(date: Option<&&str>, time: Option<&&str>) -> Result<DateTime<Utc>, String> {
+    let date = date.ok_or_else(|| "missing start date".to_string())?;
+    let time = time.ok_or_else(|| "missing start time".to_string())?;
+
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| format!("invalid start date {date} -> {e}"))?;
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .map_err(|e| format!("invalid start time {time} -> {e}"))?;
+
+    Ok(local_to_utc(date.and_time(time)))
+}
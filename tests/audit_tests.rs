@@ -0,0 +1,287 @@
+mod common;
+
+use serde_json::json;
+use simple_process_tracker_rs::structures::{
+    audit_log::{diff_state, AuditEntry, AuditLog, FieldChange, PeerCredentials},
+    config::Config,
+    process::Processes,
+};
+
+use common::{process, utc};
+
+fn entry(command: &str) -> AuditEntry {
+    AuditEntry {
+        date: utc("2023-05-20T14:00:00Z"),
+        peer: PeerCredentials {
+            uid: Some(1000),
+            pid: Some(1234),
+            ..Default::default()
+        },
+        command: json!(command),
+        succeeded: true,
+        changes: vec![],
+    }
+}
+
+#[test]
+fn test_diff_state() {
+    let config = Config::default();
+    let changed_config = Config {
+        poll_interval: 30,
+        ..Default::default()
+    };
+
+    let before = Processes(vec![process("mpv", 100), process("vlc", 60)]);
+
+    let mut mpv = process("mpv", 0);
+    // Runtime fields change on their own and are never reported
+    mpv.is_running = true;
+    mpv.last_seen_date = utc("2023-05-28T00:00:00Z");
+    // Fields that are left out while unset are reported once they are set
+    mpv.poll_interval = Some(30);
+    let after = Processes(vec![process("firefox", 5), mpv]);
+
+    let changes = diff_state((&config, &before), (&changed_config, &after));
+
+    assert_eq!(
+        changes,
+        [
+            FieldChange {
+                target: "config".into(),
+                field: "poll_interval".into(),
+                before: json!(15),
+                after: json!(30),
+            },
+            FieldChange {
+                target: "mpv".into(),
+                field: "id".into(),
+                before: json!(0),
+                after: json!(1),
+            },
+            FieldChange {
+                target: "mpv".into(),
+                field: "duration".into(),
+                before: json!(100),
+                after: json!(0),
+            },
+            FieldChange {
+                target: "mpv".into(),
+                field: "poll_interval".into(),
+                before: json!(null),
+                after: json!(30),
+            },
+            FieldChange {
+                target: "vlc".into(),
+                field: "process".into(),
+                before: json!({
+                    "is_tracked": true,
+                    "icon": "",
+                    "name": "vlc",
+                    "duration": 60,
+                    "notes": "",
+                    "added_date": "2023-05-27T16:42:03Z",
+                }),
+                after: json!(null),
+            },
+            FieldChange {
+                target: "firefox".into(),
+                field: "process".into(),
+                before: json!(null),
+                after: json!({
+                    "is_tracked": true,
+                    "icon": "",
+                    "name": "firefox",
+                    "duration": 5,
+                    "notes": "",
+                    "added_date": "2023-05-27T16:42:03Z",
+                }),
+            },
+        ]
+    );
+
+    assert!(diff_state((&config, &before), (&config, &before)).is_empty());
+}
+
+#[test]
+fn test_audit_log_rotates_by_size() {
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-audit",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+
+    let audit_log = AuditLog {
+        path: dir.join("audit.log"),
+        // Small enough that every entry rotates the log
+        max_size: 1,
+        rotations: 2,
+    };
+
+    for command in ["Undo", "Redo", "Quit", "Undo"] {
+        audit_log.append(&entry(command)).unwrap();
+    }
+
+    assert!(dir.join("audit.log.1").exists());
+    assert!(dir.join("audit.log.2").exists());
+    assert!(!dir.join("audit.log.3").exists());
+
+    // The oldest entry was rotated out, the rest are read oldest first
+    let commands: Vec<_> = audit_log
+        .read()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.command)
+        .collect();
+    assert_eq!(commands, [json!("Redo"), json!("Quit"), json!("Undo")]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_peer_credentials_of_local_socket() {
+    use interprocess::local_socket::tokio::{LocalSocketListener, LocalSocketStream};
+    use simple_process_tracker_rs::server_utils::peer_credentials::peer_credentials;
+
+    let name = format!("@simple-process-tracker-test-{}.sock", std::process::id());
+    let listener = LocalSocketListener::bind(name.as_str()).unwrap();
+
+    let (client, server) =
+        tokio::join!(LocalSocketStream::connect(name.as_str()), listener.accept());
+    let (_client, server) = (client.unwrap(), server.unwrap());
+
+    let peer = peer_credentials(&server);
+
+    // The client is this test process
+    assert_eq!(peer.pid, Some(std::process::id()));
+    assert!(peer.uid.is_some());
+}
+
+#[test]
+fn test_authorize_peers() {
+    use simple_process_tracker_rs::server_utils::peer_credentials::{authorize, Permission};
+
+    let peer = |uid, gid, groups: &[u32]| PeerCredentials {
+        uid: Some(uid),
+        pid: Some(1234),
+        gid: Some(gid),
+        groups: groups.to_vec(),
+    };
+
+    let allowed_peers = [
+        "uid:1001".parse().unwrap(),
+        "group:100:read-write".parse().unwrap(),
+        "uid:1002:read-write".parse().unwrap(),
+        "group:200:read-only".parse().unwrap(),
+    ];
+
+    // Only the user running the server is allowed by default
+    assert_eq!(
+        authorize(&peer(1000, 1000, &[]), Some(1000), &[]),
+        Some(Permission::Owner)
+    );
+    assert_eq!(authorize(&peer(1001, 1001, &[]), Some(1000), &[]), None);
+
+    assert_eq!(
+        authorize(&peer(1001, 1001, &[]), Some(1000), &allowed_peers),
+        Some(Permission::ReadOnly)
+    );
+    assert_eq!(
+        authorize(&peer(1003, 1003, &[200, 100]), Some(1000), &allowed_peers),
+        Some(Permission::ReadWrite)
+    );
+    assert_eq!(
+        authorize(&peer(1004, 200, &[]), Some(1000), &allowed_peers),
+        Some(Permission::ReadOnly)
+    );
+    assert_eq!(
+        authorize(&peer(1005, 300, &[]), Some(1000), &allowed_peers),
+        None
+    );
+
+    // A peer whose uid is unknown is never allowed when users can be told apart
+    let unknown = PeerCredentials::default();
+    assert_eq!(authorize(&unknown, Some(1000), &allowed_peers), None);
+    assert_eq!(authorize(&unknown, None, &[]), Some(Permission::Owner));
+}
+
+#[test]
+fn test_required_permission() {
+    use clap::Parser;
+    use simple_process_tracker_rs::{
+        commands::Commands,
+        server_utils::peer_credentials::{required_permission, Permission},
+    };
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    let parse = |args: &[&str]| {
+        Cli::try_parse_from(std::iter::once("cli").chain(args.iter().copied()))
+            .unwrap()
+            .command
+    };
+    let required = |args: &[&str]| required_permission(&parse(args));
+
+    // Creating an archive changes nothing but is recorded, because the server writes the file
+    assert!(parse(&["archive", "create", "a.json"]).is_audited());
+    assert!(parse(&["remove", "0"]).is_audited());
+    assert!(!parse(&["view"]).is_audited());
+
+    assert_eq!(required(&["view"]), Permission::ReadOnly);
+    assert_eq!(required(&["audit"]), Permission::ReadOnly);
+    assert_eq!(
+        required(&["import", "a.json", "--dry-run"]),
+        Permission::Owner
+    );
+    assert_eq!(required(&["import", "a.json"]), Permission::Owner);
+    assert_eq!(required(&["remove", "0"]), Permission::ReadWrite);
+    assert_eq!(required(&["quit"]), Permission::ReadWrite);
+    assert_eq!(
+        required(&["archive", "create", "a.json"]),
+        Permission::Owner
+    );
+    assert_eq!(
+        required(&["archive", "restore", "a.json"]),
+        Permission::Owner
+    );
+    assert_eq!(required(&["option", "-p", "30"]), Permission::ReadWrite);
+    assert_eq!(
+        required(&["option", "--sync-dir", "/tmp/sync"]),
+        Permission::Owner
+    );
+    assert_eq!(
+        required(&["option", "--allow", "uid:1001"]),
+        Permission::Owner
+    );
+}
+
+#[test]
+fn test_parse_allowed_peer() {
+    use simple_process_tracker_rs::structures::config::{AccessLevel, AllowedPeer, PeerId};
+
+    assert_eq!(
+        "uid:1001".parse::<AllowedPeer>().unwrap(),
+        AllowedPeer {
+            peer: PeerId::Uid(1001),
+            access: AccessLevel::ReadOnly
+        }
+    );
+    assert_eq!(
+        "group:100:read-write".parse::<AllowedPeer>().unwrap(),
+        AllowedPeer {
+            peer: PeerId::Group(100),
+            access: AccessLevel::ReadWrite
+        }
+    );
+    assert!("user:1001".parse::<AllowedPeer>().is_err());
+    assert!("uid:alice".parse::<AllowedPeer>().is_err());
+
+    // The config file stores the peer and its access level side by side
+    let serialized =
+        serde_json::to_value("group:100:read-write".parse::<AllowedPeer>().unwrap()).unwrap();
+    assert_eq!(serialized, json!({ "group": 100, "access": "read-write" }));
+}
@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+use simple_process_tracker_rs::structures::process::Process;
+
+pub fn utc(input: &str) -> DateTime<Utc> {
+    input.parse().unwrap()
+}
+
+pub fn process(name: &str, duration: u64) -> Process {
+    let mut process = Process::new(name.into(), utc("2023-05-27T16:42:03Z"));
+    process.duration = duration;
+    process.last_seen_date = utc("2023-05-27T16:43:20Z");
+    process
+}
@@ -0,0 +1,342 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde_json::json;
+use simple_process_tracker_rs::{
+    server_utils::background_tasks::{apply_config_file, watch_config_file},
+    structures::config::{
+        parse_config_file, parse_env_settings, read_config_file, read_or_create_config_file,
+        Config, ConfigLayers, ConfigSource, CONFIG_FILE_NAME, LEGACY_CONFIG_FILE_NAME,
+    },
+};
+use tokio::sync::RwLock;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-{name}",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+fn to_json(config: &Config) -> serde_json::Value {
+    serde_json::to_value(config).unwrap()
+}
+
+#[test]
+fn test_commented_config_file_round_trips() {
+    let default_file = Config::default().to_commented_toml();
+
+    // Settings without a default are documented but left out
+    assert!(default_file.contains("\n# How often to check which processes are running"));
+    assert!(default_file.contains("\npoll_interval = 15\n"));
+    assert!(default_file.contains("\n# sync_dir = "));
+    assert_eq!(
+        to_json(&Config::parse_toml(&default_file).unwrap()),
+        to_json(&Config::default())
+    );
+
+    let config = Config {
+        poll_interval: 30,
+        sync_dir: Some("/mnt/nas/tracking".into()),
+        allowed_peers: vec![
+            "uid:1001".parse().unwrap(),
+            "group:100:read-write".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+
+    let file = config.to_commented_toml();
+    assert!(file.contains("\nsync_dir = \"/mnt/nas/tracking\"\n"));
+    assert_eq!(
+        to_json(&Config::parse_toml(&file).unwrap()),
+        to_json(&config)
+    );
+}
+
+#[test]
+fn test_invalid_config_file() {
+    let error = Config::parse_toml("poll_interval = 5").unwrap_err();
+    assert_eq!(error, "invalid poll_interval -> min 10, got 5");
+
+    assert!(Config::parse_toml("poll_intervall = 15").is_err());
+    assert!(Config::parse_toml("poll_interval = \"15\"").is_err());
+    assert!(Config::parse_toml("host_name = \"all\"").is_err());
+
+    // Settings that are not in the file keep their defaults
+    let config = Config::parse_toml("autosave_interval = 600").unwrap();
+    assert_eq!(config.autosave_interval, 600);
+    assert_eq!(config.poll_interval, Config::default().poll_interval);
+}
+
+#[test]
+fn test_runtime_settings_override_config_file() {
+    let config_file = Config::default();
+    let config = Config {
+        poll_interval: 60,
+        sync_dir: Some("/mnt/nas/tracking".into()),
+        ..Default::default()
+    };
+
+    let overrides = config.overrides_from(&config_file);
+    assert_eq!(
+        serde_json::Value::Object(overrides.clone()),
+        json!({ "poll_interval": 60, "sync_dir": "/mnt/nas/tracking" })
+    );
+
+    let new_config_file = Config {
+        poll_interval: 20,
+        autosave_interval: 120,
+        ..Default::default()
+    };
+    let merged = new_config_file.with_overrides(&overrides).unwrap();
+    assert_eq!(merged.poll_interval, 60);
+    assert_eq!(merged.autosave_interval, 120);
+    assert_eq!(merged.sync_dir, Some("/mnt/nas/tracking".into()));
+
+    // Turning syncing off at runtime overrides a sync dir in the config file
+    let overrides = Config::default().overrides_from(&merged);
+    let merged = merged.with_overrides(&overrides).unwrap();
+    assert_eq!(merged.sync_dir, None);
+}
+
+#[tokio::test]
+async fn test_apply_config_file_keeps_other_settings() {
+    let config_layers = ConfigLayers::new(
+        parse_config_file("sync_interval = 600").unwrap(),
+        parse_env_settings([("SPT_AUTOSAVE_INTERVAL".into(), "120".into())]).unwrap(),
+        Default::default(),
+    )
+    .unwrap();
+    let config = RwLock::new(Config {
+        poll_interval: 60,
+        ..config_layers.base.clone()
+    });
+    let config_layers = RwLock::new(config_layers);
+
+    apply_config_file(
+        &config,
+        &config_layers,
+        parse_config_file("autosave_interval = 900\nduration_update_interval = 5").unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let config = config.read().await;
+    // The setting changed at runtime and the environment still take priority over the config file
+    assert_eq!(config.poll_interval, 60);
+    assert_eq!(config.autosave_interval, 120);
+    assert_eq!(config.duration_update_interval, 5);
+    // A setting removed from the config file goes back to its default
+    assert_eq!(config.sync_interval, Config::default().sync_interval);
+}
+
+#[test]
+fn test_settings_given_for_a_launch_do_not_replace_saved_ones() {
+    use clap::Parser;
+    use simple_process_tracker_rs::commands::Launch;
+
+    let launch_cmd = Launch::try_parse_from(["launch", "--poll-interval", "60"]).unwrap();
+    let mut config_layers = ConfigLayers::new(
+        Default::default(),
+        Default::default(),
+        launch_cmd.settings(),
+    )
+    .unwrap();
+
+    let saved = json!({ "poll_interval": 30, "sync_interval": 600 });
+    let serde_json::Value::Object(saved) = saved else {
+        unreachable!()
+    };
+
+    let mut config = config_layers.apply_runtime_settings(saved).unwrap();
+    assert_eq!(config.poll_interval, 60);
+    assert_eq!(config.sync_interval, 600);
+
+    // The saved poll interval is only hidden for this launch
+    assert_eq!(
+        serde_json::Value::Object(config_layers.runtime_settings(&config)),
+        json!({ "poll_interval": 30, "sync_interval": 600 })
+    );
+
+    // Until it is changed again
+    let before = config.clone();
+    config.poll_interval = 45;
+    config_layers.unshadow(&config.overrides_from(&before));
+    assert_eq!(
+        serde_json::Value::Object(config_layers.runtime_settings(&config)),
+        json!({ "poll_interval": 45, "sync_interval": 600 })
+    );
+}
+
+#[test]
+fn test_env_settings() {
+    let vars = [
+        ("SPT_POLL_INTERVAL", "30"),
+        ("SPT_SYNC_DIR", "/mnt/nas/tracking"),
+        ("SPT_HOST_NAME", ""),
+        ("SPT_ALLOWED_PEERS", "uid:1001,group:100:read-write"),
+        // Variables that are not settings are left to whoever reads them
+        ("SPT_SOCKET", "@test.sock"),
+        ("POLL_INTERVAL", "99"),
+    ]
+    .map(|(var, value)| (var.to_string(), value.to_string()));
+
+    let settings = parse_env_settings(vars).unwrap();
+    assert_eq!(
+        serde_json::Value::Object(settings),
+        json!({
+            "poll_interval": 30,
+            "sync_dir": "/mnt/nas/tracking",
+            "host_name": null,
+            "allowed_peers": [
+                { "uid": 1001, "access": "read-only" },
+                { "group": 100, "access": "read-write" },
+            ],
+        })
+    );
+
+    for (var, value) in [
+        ("SPT_POLL_INTERVAL", "fast"),
+        ("SPT_POLL_INTERVAL", "1"),
+        ("SPT_ALLOWED_PEERS", "alice"),
+        ("SPT_DATA_DIR", "tracking"),
+    ] {
+        let error = parse_env_settings([(var.to_string(), value.to_string())]).unwrap_err();
+        assert!(error.contains("invalid"), "{error}");
+    }
+}
+
+#[test]
+fn test_config_precedence_and_sources() {
+    use clap::Parser;
+    use simple_process_tracker_rs::commands::Launch;
+
+    let launch_cmd =
+        Launch::try_parse_from(["launch", "--poll-interval", "40", "--sync-dir", ""]).unwrap();
+
+    let config_layers = ConfigLayers::new(
+        parse_config_file("poll_interval = 20\nautosave_interval = 600\nsync_dir = \"/nas\"")
+            .unwrap(),
+        parse_env_settings([
+            ("SPT_POLL_INTERVAL".into(), "30".into()),
+            ("SPT_SYNC_INTERVAL".into(), "120".into()),
+        ])
+        .unwrap(),
+        launch_cmd.settings(),
+    )
+    .unwrap();
+
+    // Launch flags beat the environment, which beats the config file, which beats the defaults
+    let base = &config_layers.base;
+    assert_eq!(base.poll_interval, 40);
+    assert_eq!(base.sync_interval, 120);
+    assert_eq!(base.autosave_interval, 600);
+    assert_eq!(base.sync_dir, None);
+    assert_eq!(
+        base.duration_update_interval,
+        Config::default().duration_update_interval
+    );
+
+    let config = Config {
+        host_name: Some("desktop".into()),
+        ..base.clone()
+    };
+
+    let sources: Vec<_> = config_layers
+        .settings(&config)
+        .into_iter()
+        .map(|setting| (setting.name, setting.value, setting.source))
+        .collect();
+
+    assert_eq!(
+        sources[..6],
+        [
+            ("poll_interval".into(), json!(40), ConfigSource::LaunchFlag),
+            (
+                "duration_update_interval".into(),
+                json!(10),
+                ConfigSource::Default
+            ),
+            (
+                "autosave_interval".into(),
+                json!(600),
+                ConfigSource::ConfigFile
+            ),
+            ("sync_dir".into(), json!(null), ConfigSource::LaunchFlag),
+            (
+                "sync_interval".into(),
+                json!(120),
+                ConfigSource::Environment
+            ),
+            ("host_name".into(), json!("desktop"), ConfigSource::Runtime),
+        ]
+    );
+
+    // Launch flags are checked like the option command
+    assert!(Launch::try_parse_from(["launch", "--poll-interval", "1"]).is_err());
+}
+
+#[test]
+fn test_legacy_config_is_converted() {
+    let dir = temp_dir("legacy-config");
+
+    std::fs::write(
+        dir.join(LEGACY_CONFIG_FILE_NAME),
+        r#"{"poll_interval": 20, "duration_update_interval": 10, "autosave_interval": 300}"#,
+    )
+    .unwrap();
+
+    let settings = read_or_create_config_file(&dir).unwrap();
+    assert_eq!(settings["poll_interval"], json!(20));
+
+    assert!(!dir.join(LEGACY_CONFIG_FILE_NAME).exists());
+    assert!(dir.join("config.json.bak").exists());
+
+    assert_eq!(
+        read_config_file(&dir.join(CONFIG_FILE_NAME)).unwrap(),
+        settings
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_config_file_is_reloaded() {
+    let dir = temp_dir("watch-config");
+    let config_path = dir.join(CONFIG_FILE_NAME);
+    std::fs::write(&config_path, Config::default().to_commented_toml()).unwrap();
+
+    let config: &'static RwLock<Config> = Box::leak(Box::default());
+    let config_layers: &'static RwLock<ConfigLayers> = Box::leak(Box::default());
+    let watched_dir: &'static std::path::Path = Box::leak(dir.clone().into_boxed_path());
+
+    let watcher = tokio::spawn(watch_config_file(config, config_layers, watched_dir));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let wait_for_poll_interval = |expected: u64| async move {
+        for _ in 0..50 {
+            if config.read().await.poll_interval == expected {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    };
+
+    std::fs::write(&config_path, "poll_interval = 45\n").unwrap();
+    assert!(wait_for_poll_interval(45).await);
+
+    // An invalid file is reported and the last valid config is kept
+    std::fs::write(&config_path, "poll_interval = 1\n").unwrap();
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(config.read().await.poll_interval, 45);
+
+    std::fs::write(&config_path, "poll_interval = 50\n").unwrap();
+    assert!(wait_for_poll_interval(50).await);
+
+    watcher.abort();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
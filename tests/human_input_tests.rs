@@ -0,0 +1,230 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use simple_process_tracker_rs::{human_input::parse_absolute_datetime, *};
+
+fn utc(input: &str) -> DateTime<Utc> {
+    input.parse().unwrap()
+}
+
+fn local(input: &str) -> DateTime<Utc> {
+    local_to_utc(NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S").unwrap())
+}
+
+#[test]
+fn test_duration_seconds() {
+    assert_eq!(string_to_duration("0"), Ok(0));
+    assert_eq!(string_to_duration("90"), Ok(90));
+    assert_eq!(string_to_duration(" 3600 "), Ok(3600));
+}
+
+#[test]
+fn test_duration_clock() {
+    assert_eq!(string_to_duration("01:30:00"), Ok(5400));
+    assert_eq!(string_to_duration("1409:32:00"), Ok(5074320));
+    assert_eq!(string_to_duration("2:30"), Ok(9000));
+    assert_eq!(string_to_duration("0:00:45"), Ok(45));
+}
+
+#[test]
+fn test_duration_units() {
+    assert_eq!(string_to_duration("1h30m"), Ok(5400));
+    assert_eq!(string_to_duration("90m"), Ok(5400));
+    assert_eq!(string_to_duration("45s"), Ok(45));
+    assert_eq!(string_to_duration("1d 2h"), Ok(93600));
+    assert_eq!(string_to_duration("1w"), Ok(604800));
+    assert_eq!(string_to_duration("1.5h"), Ok(5400));
+    assert_eq!(string_to_duration("3 days"), Ok(259200));
+    assert_eq!(
+        string_to_duration("1 hour, 2 minutes and 3 seconds"),
+        Ok(3723)
+    );
+    assert_eq!(string_to_duration("2 Hours 5 Mins"), Ok(7500));
+}
+
+#[test]
+fn test_duration_iso8601() {
+    assert_eq!(string_to_duration("PT1H30M"), Ok(5400));
+    assert_eq!(string_to_duration("PT45S"), Ok(45));
+    assert_eq!(string_to_duration("P1DT2H"), Ok(93600));
+    assert_eq!(string_to_duration("P2W"), Ok(1209600));
+    assert_eq!(string_to_duration("pt10m"), Ok(600));
+}
+
+#[test]
+fn test_duration_invalid() {
+    for input in [
+        "",
+        "   ",
+        "abc",
+        "1:2:3:4",
+        "1:xx",
+        "-5m",
+        "5x",
+        "h",
+        "1.2.3h",
+        "P",
+        "PT",
+        "P1Y",
+        "P1M",
+        "PT1H30",
+        "P1H",
+        "99999999999999999999",
+        "99999999999999999:00:00",
+        "P99999999999999999999D",
+    ] {
+        let res = string_to_duration(input);
+        assert!(res.is_err(), "{input:?} should not parse");
+    }
+}
+
+#[test]
+fn test_duration_error_lists_formats() {
+    let error = string_to_duration("soon").unwrap_err();
+
+    assert!(error.contains("soon"));
+    assert!(error.contains("HH:MM:SS"));
+    assert!(error.contains("1h30m"));
+    assert!(error.contains("PT1H30M"));
+}
+
+#[test]
+fn test_datetime_legacy_format() {
+    let now = utc("2023-05-20T12:00:00Z");
+
+    assert_eq!(
+        parse_datetime("2023/05/20 13:45:09", now),
+        Ok(local("2023-05-20 13:45:09"))
+    );
+    assert_eq!(
+        parse_datetime("2023/05/20 13:45", now),
+        Ok(local("2023-05-20 13:45:00"))
+    );
+    assert_eq!(
+        parse_datetime("2023/05/20", now),
+        Ok(local("2023-05-20 00:00:00"))
+    );
+
+    // Leap days only exist in leap years
+    assert!(parse_datetime("2024/02/29 00:00:00", now).is_ok());
+    assert!(parse_datetime("2023/02/29 00:00:00", now).is_err());
+}
+
+#[test]
+fn test_datetime_iso8601() {
+    let now = utc("2023-05-20T12:00:00Z");
+
+    assert_eq!(
+        parse_datetime("2023-05-20T18:00:00Z", now),
+        Ok(utc("2023-05-20T18:00:00Z"))
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20T18:00:00+02:00", now),
+        Ok(utc("2023-05-20T16:00:00Z"))
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20 18:00:00-0130", now),
+        Ok(utc("2023-05-20T19:30:00Z"))
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20T18:00:00.250Z", now)
+            .unwrap()
+            .timestamp_millis(),
+        utc("2023-05-20T18:00:00Z").timestamp_millis() + 250
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20T18:00:00", now),
+        Ok(local("2023-05-20 18:00:00"))
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20 18:00", now),
+        Ok(local("2023-05-20 18:00:00"))
+    );
+    assert_eq!(
+        parse_datetime("2023-05-20", now),
+        Ok(local("2023-05-20 00:00:00"))
+    );
+    assert_eq!(
+        parse_datetime("20230520T180000", now),
+        Ok(local("2023-05-20 18:00:00"))
+    );
+}
+
+#[test]
+fn test_datetime_relative() {
+    let now = utc("2023-05-20T12:00:00Z");
+    let today = now.with_timezone(&Local).date_naive();
+    let yesterday = today.pred_opt().unwrap();
+    let at = |date: NaiveDate, time: &str| local(&format!("{date} {time}"));
+
+    assert_eq!(parse_datetime("now", now), Ok(now));
+    assert_eq!(parse_datetime("today", now), Ok(at(today, "00:00:00")));
+    assert_eq!(
+        parse_datetime("Today 08:15", now),
+        Ok(at(today, "08:15:00"))
+    );
+    assert_eq!(
+        parse_datetime("yesterday", now),
+        Ok(at(yesterday, "00:00:00"))
+    );
+    assert_eq!(
+        parse_datetime("yesterday 18:00", now),
+        Ok(at(yesterday, "18:00:00"))
+    );
+    assert_eq!(
+        parse_datetime("yesterday 18:00:30", now),
+        Ok(at(yesterday, "18:00:30"))
+    );
+    assert_eq!(
+        parse_datetime("3 days ago", now),
+        Ok(utc("2023-05-17T12:00:00Z"))
+    );
+    assert_eq!(
+        parse_datetime("1h30m ago", now),
+        Ok(utc("2023-05-20T10:30:00Z"))
+    );
+    assert_eq!(
+        parse_datetime("90 ago", now),
+        Ok(utc("2023-05-20T11:58:30Z"))
+    );
+}
+
+#[test]
+fn test_datetime_invalid() {
+    let now = utc("2023-05-20T12:00:00Z");
+
+    for input in [
+        "",
+        "2023/13/01 00:00:00",
+        "2023/04/31 00:00:00",
+        "2023/05/20 24:00:00",
+        "2023/05/20 13:60:00",
+        "2023/05/20 13:45:09 extra",
+        "2023-05-20T18:00:00+25:00",
+        "tomorrow-ish",
+        "yesterday 25:00",
+        "today at noon",
+        "ago",
+        "soon ago",
+        "99999999999 weeks ago",
+    ] {
+        let res = parse_datetime(input, now);
+        assert!(res.is_err(), "{input:?} should not parse");
+        assert!(res.unwrap_err().contains(input));
+    }
+}
+
+#[test]
+fn test_datetime_error_lists_formats() {
+    let error = parse_datetime("next week", utc("2023-05-20T12:00:00Z")).unwrap_err();
+
+    assert!(error.contains("YYYY/MM/DD HH:MM:SS"));
+    assert!(error.contains("ISO-8601"));
+    assert!(error.contains("yesterday 18:00"));
+    assert!(error.contains("3 days ago"));
+}
+
+#[test]
+fn test_absolute_datetime_ignores_relative_input() {
+    assert!(parse_absolute_datetime("2023/05/20 13:45:09").is_ok());
+    assert!(parse_absolute_datetime("today").is_err());
+    assert!(parse_absolute_datetime("3 days ago").is_err());
+}
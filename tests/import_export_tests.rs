@@ -0,0 +1,756 @@
+mod common;
+
+use chrono::Utc;
+use simple_process_tracker_rs::{
+    structures::{
+        csv_process::{read_csv, write_csv, RejectedRow},
+        process::{Process, Usage, Users},
+    },
+    *,
+};
+
+use common::{process, utc};
+
+#[test]
+fn test_csv_round_trip() {
+    let mut mpv = process("mpv", 5074320);
+    mpv.notes = "MPV video player, \"the\" best".into();
+    mpv.icon = "/usr/share/icons/mpv.png".into();
+    mpv.launches = 212;
+    mpv.longest_session = 14400;
+    mpv.poll_interval = Some(30);
+    mpv.min_session = 60;
+    mpv.users = Users::Uids(vec![1000, 1001]);
+    mpv.cgroup = Some("app-flatpak-io.mpv.Mpv-*.scope".into());
+    mpv.usage = Usage {
+        cpu_millis: 90000,
+        peak_rss_kib: 204800,
+        avg_rss_kib: 102400,
+        rss_samples: 12,
+        peak_instances: 2,
+    };
+    let mut strawberry = process("strawberry", 2607934);
+    strawberry.is_tracked = false;
+
+    let mut output = vec![];
+    write_csv(&mut output, &[mpv, strawberry], b',', DurationFormat::Human).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(
+        lines.next(),
+        Some("name,tracking,duration,duration_seconds,notes,icon,last_seen_date,added_date,launches,longest_session_seconds,poll_interval,min_session_seconds,users,cgroup,cpu_millis,peak_rss_kib,avg_rss_kib,rss_samples,peak_instances")
+    );
+    assert_eq!(
+        lines.next(),
+        Some(
+            r#"mpv,true,"58 days, 17 hours",5074320,"MPV video player, ""the"" best",/usr/share/icons/mpv.png,2023-05-27T16:43:20+00:00,2023-05-27T16:42:03+00:00,212,14400,30,60,"1000,1001",app-flatpak-io.mpv.Mpv-*.scope,90000,204800,102400,12,2"#
+        )
+    );
+
+    let import = read_csv(output.as_bytes(), b',', utc("2024-01-01T00:00:00Z")).unwrap();
+    assert!(import.rejected.is_empty());
+    assert_eq!(import.processes.len(), 2);
+
+    let mpv = &import.processes[0];
+    assert_eq!(mpv.name, "mpv");
+    assert_eq!(mpv.duration, 5074320);
+    assert_eq!(mpv.notes, "MPV video player, \"the\" best");
+    assert_eq!(mpv.icon.to_str(), Some("/usr/share/icons/mpv.png"));
+    assert_eq!(mpv.last_seen_date, utc("2023-05-27T16:43:20Z"));
+    assert_eq!(mpv.added_date, utc("2023-05-27T16:42:03Z"));
+    assert_eq!(mpv.launches, 212);
+    assert_eq!(mpv.longest_session, 14400);
+    assert_eq!(mpv.poll_interval, Some(30));
+    assert_eq!(mpv.min_session, 60);
+    assert_eq!(mpv.users, Users::Uids(vec![1000, 1001]));
+    assert_eq!(
+        mpv.cgroup.as_deref(),
+        Some("app-flatpak-io.mpv.Mpv-*.scope")
+    );
+    assert_eq!(
+        mpv.usage,
+        Usage {
+            cpu_millis: 90000,
+            peak_rss_kib: 204800,
+            avg_rss_kib: 102400,
+            rss_samples: 12,
+            peak_instances: 2,
+        }
+    );
+
+    let strawberry = &import.processes[1];
+    assert!(!strawberry.is_tracked);
+    assert_eq!(strawberry.poll_interval, None);
+    assert_eq!(strawberry.users, Users::Me);
+    assert_eq!(strawberry.cgroup, None);
+}
+
+#[test]
+fn test_csv_custom_delimiter() {
+    let mut output = vec![];
+    write_csv(
+        &mut output,
+        &[process("mpv", 60)],
+        b';',
+        DurationFormat::Clock,
+    )
+    .unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("mpv;true;00:01:00;60;"));
+
+    let import = read_csv(output.as_bytes(), b';', Utc::now()).unwrap();
+    assert_eq!(import.processes[0].duration, 60);
+
+    // Reading with the wrong delimiter finds no name column
+    assert!(read_csv(output.as_bytes(), b',', Utc::now()).is_err());
+}
+
+#[test]
+fn test_csv_maps_columns_by_header_name() {
+    let input = "\
+Notes\tDuration\tName\tSomething else
+first\t1h30m\tmpv\tignored
+second\t\tfirefox\t
+";
+
+    let now = utc("2024-01-01T00:00:00Z");
+    let import = read_csv(input.as_bytes(), b'\t', now).unwrap();
+
+    assert!(import.rejected.is_empty());
+
+    let mpv = &import.processes[0];
+    assert_eq!(mpv.name, "mpv");
+    assert_eq!(mpv.notes, "first");
+    assert_eq!(mpv.duration, 5400);
+    assert!(mpv.is_tracked);
+    // Missing dates default to now for the added date
+    assert_eq!(mpv.added_date, now);
+
+    assert_eq!(import.processes[1].name, "firefox");
+    assert_eq!(import.processes[1].duration, 0);
+}
+
+#[test]
+fn test_csv_seconds_take_priority_over_human_duration() {
+    let input = "name,duration,duration_seconds\nmpv,1409.53h,5074320\n";
+
+    let import = read_csv(input.as_bytes(), b',', Utc::now()).unwrap();
+
+    assert_eq!(import.processes[0].duration, 5074320);
+}
+
+#[test]
+fn test_csv_reports_rejected_rows() {
+    let input = "\
+name,tracking,duration,added_date
+mpv,true,01:00:00,2023-05-27T16:42:03Z
+,true,01:00:00,
+firefox,maybe,01:00:00,
+strawberry,true,soon,
+vlc,true,01:00:00,last tuesday
+mpv,false,00:00:01,
+okular,no,2:30,2023/05/27 16:42:03
+";
+
+    let import = read_csv(input.as_bytes(), b',', Utc::now()).unwrap();
+
+    let names: Vec<&str> = import.processes.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec!["mpv", "okular"]);
+
+    let lines: Vec<u64> = import.rejected.iter().map(|row| row.line).collect();
+    assert_eq!(lines, vec![3, 4, 5, 6, 7]);
+
+    assert_eq!(
+        import.rejected[0],
+        RejectedRow {
+            line: 3,
+            reason: "missing name".into()
+        }
+    );
+    assert!(import.rejected[1].reason.contains("invalid tracking maybe"));
+    assert!(import.rejected[2].reason.contains("invalid duration soon"));
+    assert!(import.rejected[3]
+        .reason
+        .contains("invalid date time last tuesday"));
+    assert_eq!(import.rejected[4].reason, "duplicate name mpv");
+}
+
+#[test]
+fn test_csv_without_name_column_is_an_error() {
+    let input = "process,duration\nmpv,60\n";
+
+    assert!(read_csv(input.as_bytes(), b',', Utc::now()).is_err());
+}
+
+#[test]
+fn test_parse_delimiter() {
+    assert_eq!(parse_delimiter(","), Ok(b','));
+    assert_eq!(parse_delimiter(";"), Ok(b';'));
+    assert_eq!(parse_delimiter("tab"), Ok(b'\t'));
+    assert_eq!(parse_delimiter("\t"), Ok(b'\t'));
+
+    assert!(parse_delimiter("").is_err());
+    assert!(parse_delimiter(",,").is_err());
+    assert!(parse_delimiter("é").is_err());
+}
+
+fn write_temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-{name}",
+        std::process::id()
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+const ACTIVITYWATCH_EXPORT: &str = r#"{
+  "buckets": {
+    "aw-watcher-window_laptop": {
+      "id": "aw-watcher-window_laptop",
+      "type": "currentwindow",
+      "events": [
+        {"timestamp": "2023-05-20T10:00:00+00:00", "duration": 120.4, "data": {"app": "firefox", "title": "News"}},
+        {"timestamp": "2023-05-20T09:00:00+00:00", "duration": 60.0, "data": {"app": "mpv", "title": "video.mkv"}},
+        {"timestamp": "2023-05-20T11:00:00+02:00", "duration": 30.0, "data": {"app": "firefox", "title": "Mail"}}
+      ]
+    },
+    "aw-watcher-afk_laptop": {
+      "id": "aw-watcher-afk_laptop",
+      "type": "afkstatus",
+      "events": [
+        {"timestamp": "2023-05-20T10:00:00+00:00", "duration": 600.0, "data": {"status": "not-afk"}}
+      ]
+    }
+  }
+}"#;
+
+#[test]
+fn test_activitywatch_sessions() {
+    use structures::{activitywatch::ActivityWatchExport, process::sessions_to_processes};
+
+    let export: ActivityWatchExport = serde_json::from_str(ACTIVITYWATCH_EXPORT).unwrap();
+    let import = export.into_sessions();
+    assert!(import.rejected.is_empty());
+    let processes = sessions_to_processes(import.sessions);
+
+    let names: Vec<&str> = processes.iter().map(|p| p.name.as_str()).collect();
+    // Ordered by the first session of each app, and AFK events are ignored
+    assert_eq!(names, vec!["mpv", "firefox"]);
+
+    let firefox = &processes[1];
+    assert_eq!(firefox.duration, 150);
+    assert_eq!(firefox.added_date, utc("2023-05-20T09:00:00Z"));
+    assert_eq!(firefox.last_seen_date, utc("2023-05-20T10:02:00.400Z"));
+}
+
+#[test]
+fn test_activitywatch_invalid_durations_are_rejected() {
+    use structures::activitywatch::ActivityWatchExport;
+
+    let export: ActivityWatchExport = serde_json::from_str(
+        r#"{"buckets": {"aw-watcher-window_laptop": {"events": [
+            {"timestamp": "2023-05-20T10:00:00Z", "duration": 1e300, "data": {"app": "firefox"}},
+            {"timestamp": "2023-05-20T11:00:00Z", "duration": 9.2e15, "data": {"app": "firefox"}},
+            {"timestamp": "2023-05-20T12:00:00Z", "duration": -5.0, "data": {"app": "firefox"}},
+            {"timestamp": "2023-05-20T13:00:00Z", "duration": 60.0, "data": {"app": "mpv"}}
+        ]}}}"#,
+    )
+    .unwrap();
+
+    let import = export.into_sessions();
+    assert_eq!(import.sessions.len(), 1);
+    assert_eq!(import.sessions[0].name, "mpv");
+    assert_eq!(import.rejected.len(), 3);
+    assert!(import.rejected[2].contains("invalid duration -5"));
+}
+
+const TIMEWARRIOR_EXPORT: &str = r#"[
+  {"id": 3, "start": "20230520T100000Z", "end": "20230520T113000Z", "tags": ["mpv", "music"]},
+  {"id": 2, "start": "20230520T120000Z", "end": "20230520T121500Z", "tags": ["mpv"], "annotation": "podcast"},
+  {"id": 1, "start": "20230520T130000Z"}
+]"#;
+
+#[test]
+fn test_timewarrior_sessions() {
+    use structures::{process::sessions_to_processes, timewarrior::TimewarriorExport};
+
+    let export: TimewarriorExport = serde_json::from_str(TIMEWARRIOR_EXPORT).unwrap();
+    let sessions = export.into_sessions(utc("2023-05-20T14:00:00Z")).unwrap();
+    let processes = sessions_to_processes(sessions);
+
+    assert_eq!(processes.len(), 2);
+    assert_eq!(processes[0].name, "mpv");
+    assert_eq!(processes[0].duration, 5400 + 900);
+    assert_eq!(processes[0].last_seen_date, utc("2023-05-20T12:15:00Z"));
+    assert_eq!(processes[1].name, "music");
+    assert_eq!(processes[1].duration, 5400);
+}
+
+#[test]
+fn test_timewarrior_open_interval_ends_now() {
+    use structures::timewarrior::TimewarriorExport;
+
+    let export: TimewarriorExport =
+        serde_json::from_str(r#"[{"start": "20230520T130000Z", "tags": ["mpv"]}]"#).unwrap();
+    let sessions = export.into_sessions(utc("2023-05-20T14:00:00Z")).unwrap();
+
+    assert_eq!(sessions[0].end, utc("2023-05-20T14:00:00Z"));
+
+    let export: TimewarriorExport =
+        serde_json::from_str(r#"[{"start": "yesterday", "tags": ["mpv"]}]"#).unwrap();
+    assert!(export.into_sessions(Utc::now()).is_err());
+}
+
+const TOGGL_CSV: &str = "\
+User,Email,Client,Project,Task,Description,Billable,Start date,Start time,End date,End time,Duration,Tags,Amount ()
+Me,me@example.com,,Writing,,Chapter 1,No,2023-05-20,10:00:00,2023-05-20,11:30:00,01:30:00,,
+Me,me@example.com,,,,Email,No,2023-05-20,12:00:00,2023-05-20,12:10:00,00:10:00,,
+Me,me@example.com,,Writing,,Chapter 2,No,2023-05-21,09:00:00,2023-05-21,09:45:00,00:45:00,,
+Me,me@example.com,,,,,No,2023-05-21,10:00:00,2023-05-21,10:01:00,00:01:00,,
+Me,me@example.com,,Writing,,Chapter 3,No,2023-05-21,9am,2023-05-21,10:00:00,01:00:00,,
+";
+
+#[test]
+fn test_toggl_csv_sessions() {
+    use structures::{process::sessions_to_processes, toggl::read_toggl_csv};
+
+    let import = read_toggl_csv(TOGGL_CSV.as_bytes(), b',').unwrap();
+    let processes = sessions_to_processes(import.sessions);
+
+    assert_eq!(processes.len(), 2);
+    assert_eq!(processes[0].name, "Writing");
+    assert_eq!(processes[0].duration, 5400 + 2700);
+    assert_eq!(processes[1].name, "Email");
+    assert_eq!(processes[1].duration, 600);
+
+    let lines: Vec<u64> = import.rejected.iter().map(|row| row.line).collect();
+    assert_eq!(lines, vec![5, 6]);
+    assert_eq!(import.rejected[0].reason, "missing project and description");
+    assert!(import.rejected[1].reason.contains("invalid start time 9am"));
+}
+
+#[test]
+fn test_toggl_csv_requires_toggl_columns() {
+    use structures::toggl::read_toggl_csv;
+
+    assert!(read_toggl_csv("name,duration\nmpv,60\n".as_bytes(), b',').is_err());
+}
+
+#[tokio::test]
+async fn test_import_sums_sessions_into_existing_processes() {
+    use clock::FakeClock;
+    use commands::{Import, ImportFormat};
+    use server_utils::user_commands::import_processes;
+    use structures::process::Processes;
+    use tokio::sync::RwLock;
+
+    let path = write_temp_file("timewarrior.json", TIMEWARRIOR_EXPORT);
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+
+    let mut mpv = process("mpv", 100);
+    mpv.added_date = utc("2023-06-01T00:00:00Z");
+    let processes = RwLock::new(Processes(vec![mpv]));
+
+    let response = import_processes(
+        Import {
+            path: path.clone(),
+            format: Some(ImportFormat::Timewarrior),
+            delimiter: b',',
+            on_conflict: None,
+            dry_run: false,
+            duration_format: Some(DurationFormat::Seconds),
+        },
+        &processes,
+        &RwLock::default(),
+        &clock,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    // Durations are shown in the format the client asked for
+    assert_eq!(
+        response,
+        "merged mpv, duration 100 -> 6400\nadded music, duration 5400"
+    );
+
+    let processes = processes.read().await;
+    assert_eq!(processes.0[0].duration, 100 + 5400 + 900);
+    // The earliest added date and the latest last seen date are kept
+    assert_eq!(processes.0[0].added_date, utc("2023-05-20T10:00:00Z"));
+    assert_eq!(processes.0[0].last_seen_date, utc("2023-05-27T16:43:20Z"));
+    assert_eq!(processes.0[1].name, "music");
+}
+
+#[tokio::test]
+async fn test_import_skips_existing_native_processes() {
+    use clock::FakeClock;
+    use commands::Import;
+    use server_utils::user_commands::import_processes;
+    use structures::process::Processes;
+    use tokio::sync::RwLock;
+
+    let exported = serde_json::to_string(&[process("mpv", 60), process("vlc", 60)]).unwrap();
+    let path = write_temp_file("native.json", &exported);
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+    let processes = RwLock::new(Processes(vec![process("mpv", 100)]));
+
+    let import = Import {
+        path: path.clone(),
+        format: None,
+        delimiter: b',',
+        on_conflict: None,
+        dry_run: false,
+        duration_format: None,
+    };
+    assert_eq!(import.format(), commands::ImportFormat::Json);
+
+    let response = import_processes(import, &processes, &RwLock::default(), &clock)
+        .await
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(
+        response,
+        "skipped mpv, already tracked\nadded vlc, duration 00:01:00"
+    );
+    assert_eq!(processes.read().await.0[0].duration, 100);
+}
+
+#[test]
+fn test_import_format_from_extension() {
+    use commands::{Import, ImportFormat};
+
+    let import = |path: &str, format| Import {
+        path: path.into(),
+        format,
+        delimiter: b',',
+        on_conflict: None,
+        dry_run: false,
+        duration_format: None,
+    };
+
+    assert_eq!(import("a.csv", None).format(), ImportFormat::Csv);
+    assert_eq!(import("a.CSV", None).format(), ImportFormat::Csv);
+    assert_eq!(import("a.json", None).format(), ImportFormat::Json);
+    assert_eq!(
+        import("a.csv", Some(ImportFormat::Toggl)).format(),
+        ImportFormat::Toggl
+    );
+}
+
+fn merge(strategy: commands::ConflictStrategy) -> Process {
+    let mut existing = process("mpv", 3600);
+    existing.is_tracked = false;
+    existing.notes = "desktop".into();
+    existing.added_date = utc("2023-01-01T00:00:00Z");
+    existing.last_seen_date = utc("2023-03-01T00:00:00Z");
+    existing.poll_interval = Some(30);
+    existing.users = Users::Any;
+    existing.cgroup = Some("*mpv*".into());
+    existing.usage.cpu_millis = 90000;
+
+    let mut imported = process("mpv", 1800);
+    imported.notes = "laptop".into();
+    imported.icon = "/icons/mpv.png".into();
+    imported.added_date = utc("2023-02-01T00:00:00Z");
+    imported.last_seen_date = utc("2023-04-01T00:00:00Z");
+
+    existing.merge(imported, strategy);
+    existing
+}
+
+#[test]
+fn test_merge_strategies() {
+    use commands::ConflictStrategy::*;
+
+    let skipped = merge(Skip);
+    assert_eq!(skipped.duration, 3600);
+    assert_eq!(skipped.notes, "desktop");
+    assert!(!skipped.is_tracked);
+    assert_eq!(skipped.last_seen_date, utc("2023-03-01T00:00:00Z"));
+
+    let overwritten = merge(Overwrite);
+    assert_eq!(overwritten.duration, 1800);
+    assert_eq!(overwritten.notes, "laptop");
+    assert_eq!(overwritten.added_date, utc("2023-02-01T00:00:00Z"));
+    // Imports that cannot hold how the process is matched or its usage keep the current values
+    assert_eq!(overwritten.poll_interval, Some(30));
+    assert_eq!(overwritten.users, Users::Any);
+    assert_eq!(overwritten.cgroup.as_deref(), Some("*mpv*"));
+    assert_eq!(overwritten.usage.cpu_millis, 90000);
+
+    let summed = merge(Sum);
+    assert_eq!(summed.duration, 5400);
+    assert_eq!(summed.notes, "desktop");
+
+    let max = merge(Max);
+    assert_eq!(max.duration, 3600);
+
+    let merged_notes = merge(MergeNotes);
+    assert_eq!(merged_notes.duration, 3600);
+    assert_eq!(merged_notes.notes, "desktop\nlaptop");
+
+    for merged in [summed, max, merged_notes] {
+        assert_eq!(merged.added_date, utc("2023-01-01T00:00:00Z"));
+        assert_eq!(merged.last_seen_date, utc("2023-04-01T00:00:00Z"));
+        // Tracking stays on if either side is tracked
+        assert!(merged.is_tracked);
+        // A missing icon is taken from the imported process
+        assert_eq!(merged.icon.to_str(), Some("/icons/mpv.png"));
+    }
+}
+
+#[test]
+fn test_merge_notes_does_not_repeat_notes() {
+    let mut existing = process("mpv", 0);
+    existing.notes = "video player".into();
+
+    let mut imported = process("mpv", 0);
+    imported.notes = "video player".into();
+
+    existing.merge(imported, commands::ConflictStrategy::MergeNotes);
+    assert_eq!(existing.notes, "video player");
+}
+
+#[tokio::test]
+async fn test_import_dry_run_changes_nothing() {
+    use clock::FakeClock;
+    use commands::{ConflictStrategy, Import};
+    use server_utils::user_commands::import_processes;
+    use structures::process::Processes;
+    use tokio::sync::RwLock;
+
+    let exported = serde_json::to_string(&[process("mpv", 60), process("vlc", 60)]).unwrap();
+    let path = write_temp_file("dry-run.json", &exported);
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+    let processes = RwLock::new(Processes(vec![process("mpv", 100)]));
+
+    let import = |dry_run| Import {
+        path: path.clone(),
+        format: None,
+        delimiter: b',',
+        on_conflict: Some(ConflictStrategy::Max),
+        dry_run,
+        duration_format: None,
+    };
+
+    let preview = import_processes(import(true), &processes, &RwLock::default(), &clock)
+        .await
+        .unwrap();
+    assert_eq!(
+        preview,
+        "dry run, nothing was changed\n\
+         merged mpv, duration 00:01:40 -> 00:01:40\n\
+         added vlc, duration 00:01:00"
+    );
+    assert_eq!(processes.read().await.0.len(), 1);
+
+    let response = import_processes(import(false), &processes, &RwLock::default(), &clock)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The real import has the same outcome as the preview
+    assert_eq!(
+        preview.lines().skip(1).collect::<Vec<_>>(),
+        response.lines().collect::<Vec<_>>()
+    );
+    assert_eq!(processes.read().await.0.len(), 2);
+}
+
+#[tokio::test]
+async fn test_archive_round_trip() {
+    use clock::{Clock, FakeClock};
+    use commands::{Archive, ArchiveAction};
+    use server_utils::user_commands::handle_archive;
+    use structures::{
+        config::Config,
+        journal::{Journal, Operation},
+        process::Processes,
+    };
+    use tokio::sync::RwLock;
+
+    let path = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-archive.json",
+        std::process::id()
+    ));
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+
+    let mut mpv = process("mpv", 5074320);
+    mpv.notes = "video player".into();
+    let config = RwLock::new(Config {
+        poll_interval: 30,
+        ..Default::default()
+    });
+    let processes = RwLock::new(Processes(vec![mpv, process("vlc", 60)]));
+    let journal = RwLock::new(Journal::default());
+    journal.write().await.record(
+        clock.now(),
+        "added vlc",
+        Operation::Add {
+            id: 1,
+            process: process("vlc", 60),
+        },
+    );
+
+    let response = handle_archive(
+        Archive {
+            action: ArchiveAction::Create { path: path.clone() },
+        },
+        &config,
+        &processes,
+        &journal,
+        &clock,
+    )
+    .await
+    .unwrap();
+    assert!(response.starts_with("archived 2 processes"));
+
+    // No temporary files are left next to the archive
+    let leftovers = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .contains(&format!("{}-archive.json.", std::process::id()))
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+
+    let local_config = Config {
+        host_name: Some("laptop".into()),
+        data_dir: Some("/home/user/tracker".into()),
+        allowed_peers: vec!["uid:1001".parse().unwrap()],
+        ..Default::default()
+    };
+    let restored_config = RwLock::new(local_config.clone());
+    let restored_processes = RwLock::new(Processes(vec![process("bar", 0)]));
+    let restored_journal = RwLock::new(Journal::default());
+    restored_journal.write().await.record(
+        clock.now(),
+        "added bar",
+        Operation::Add {
+            id: 0,
+            process: process("bar", 0),
+        },
+    );
+
+    handle_archive(
+        Archive {
+            action: ArchiveAction::Restore { path: path.clone() },
+        },
+        &restored_config,
+        &restored_processes,
+        &restored_journal,
+        &clock,
+    )
+    .await
+    .unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    // Settings that belong to this machine are kept
+    let restored_config = restored_config.read().await;
+    assert_eq!(restored_config.poll_interval, 30);
+    assert_eq!(restored_config.host_name, local_config.host_name);
+    assert_eq!(restored_config.data_dir, local_config.data_dir);
+    assert_eq!(
+        serde_json::to_string(&restored_config.allowed_peers).unwrap(),
+        serde_json::to_string(&local_config.allowed_peers).unwrap()
+    );
+    assert_eq!(
+        serde_json::to_string(&*restored_processes.read().await).unwrap(),
+        serde_json::to_string(&*processes.read().await).unwrap()
+    );
+
+    // The history of the archived processes replaces the one of the processes they replaced
+    let restored_journal = restored_journal.read().await;
+    assert_eq!(restored_journal.undo.len(), 1);
+    assert_eq!(restored_journal.undo[0].description, "added vlc");
+}
+
+#[test]
+fn test_archive_rejects_tampering_and_newer_versions() {
+    use structures::{
+        archive::{Archive, ArchiveData, ARCHIVE_VERSION},
+        config::Config,
+        process::Processes,
+    };
+
+    let archive = Archive::new(
+        &ArchiveData {
+            config: Config::default(),
+            processes: Processes(vec![process("mpv", 60)]),
+            journal: Default::default(),
+        },
+        utc("2023-05-20T14:00:00Z"),
+    );
+    assert!(archive.verify().is_ok());
+    assert_eq!(archive.version, ARCHIVE_VERSION);
+
+    let serialized = serde_json::to_string(&archive).unwrap();
+
+    let tampered: Archive =
+        serde_json::from_str(&serialized.replace(r#""duration":60"#, r#""duration":6000"#))
+            .unwrap();
+    assert!(tampered.verify().unwrap_err().contains("checksum mismatch"));
+
+    let mut newer: Archive = serde_json::from_str(&serialized).unwrap();
+    newer.version = ARCHIVE_VERSION + 1;
+    assert!(newer.verify().unwrap_err().contains("newer"));
+}
+
+#[test]
+fn test_archive_checksum_covers_the_bytes_as_written() {
+    use sha2::{Digest, Sha256};
+    use structures::{
+        archive::{Archive, ArchiveData, ARCHIVE_VERSION},
+        config::Config,
+        process::Processes,
+    };
+
+    // The data is hashed as written, so it does not have to be written the way this version would serialize it
+    let data = serde_json::to_string_pretty(&ArchiveData {
+        config: Config {
+            poll_interval: 30,
+            ..Default::default()
+        },
+        processes: Processes(vec![process("mpv", 60)]),
+        journal: Default::default(),
+    })
+    .unwrap();
+    let checksum: String = Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    let archive: Archive = serde_json::from_str(&format!(
+        r#"{{"version":{ARCHIVE_VERSION},"created_date":"2023-05-20T14:00:00Z","checksum":"{checksum}","data":{data}}}"#
+    ))
+    .unwrap();
+
+    let data = archive.verify().unwrap();
+    assert_eq!(data.config.poll_interval, 30);
+    assert_eq!(data.processes.0[0].duration, 60);
+}
+
+#[test]
+fn test_write_atomically_replaces_existing_file() {
+    let path = write_temp_file("atomic.txt", "old contents");
+
+    write_atomically(&path, b"new contents").unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new contents");
+
+    std::fs::remove_file(&path).unwrap();
+
+    // Writing into a missing directory fails without creating anything
+    let missing = std::env::temp_dir()
+        .join("simple-process-tracker-missing-dir")
+        .join("file.txt");
+    assert!(write_atomically(&missing, b"contents").is_err());
+    assert!(!missing.exists());
+}
@@ -1,5 +1,9 @@
+mod common;
+
 use simple_process_tracker_rs::*;
 
+use common::{process, utc};
+
 #[test]
 fn test_string_to_duration() {
     let res = string_to_duration("01:00:00");
@@ -15,7 +19,7 @@ fn test_string_to_duration_invalid() {
 
     assert!(res.is_err());
 
-    let res = string_to_duration("0");
+    let res = string_to_duration("0:0:0:0");
 
     assert!(res.is_err());
 }
@@ -27,9 +31,1493 @@ fn test_duration_to_string() {
     assert_eq!(duration_to_string(0), "00:00:00");
 }
 
+#[test]
+fn test_format_duration() {
+    let duration = string_to_duration("1409:32:00").unwrap();
+
+    assert_eq!(
+        format_duration(duration, DurationFormat::Clock),
+        "1409:32:00"
+    );
+    assert_eq!(
+        format_duration(duration, DurationFormat::Days),
+        "58d 17h 32m"
+    );
+    assert_eq!(
+        format_duration(duration, DurationFormat::Human),
+        "58 days, 17 hours"
+    );
+    assert_eq!(format_duration(duration, DurationFormat::Hours), "1409.53h");
+    assert_eq!(
+        format_duration(duration, DurationFormat::Seconds),
+        "5074320"
+    );
+}
+
+#[test]
+fn test_format_duration_small_values() {
+    assert_eq!(format_duration(0, DurationFormat::Days), "0d 00h 00m");
+    assert_eq!(format_duration(0, DurationFormat::Human), "0 seconds");
+    assert_eq!(format_duration(0, DurationFormat::Hours), "0.00h");
+
+    assert_eq!(format_duration(3665, DurationFormat::Days), "0d 01h 01m");
+    assert_eq!(format_duration(1, DurationFormat::Human), "1 second");
+    assert_eq!(
+        format_duration(61, DurationFormat::Human),
+        "1 minute, 1 second"
+    );
+    // Units after the two most significant ones are left out, even when the second one is zero
+    assert_eq!(format_duration(86405, DurationFormat::Human), "1 day");
+    assert_eq!(
+        format_duration(90000, DurationFormat::Human),
+        "1 day, 1 hour"
+    );
+}
+
 #[test]
 fn test_parse_range() {
     let res = parse_range("0-3,5,7");
     assert!(res.is_ok());
     assert_eq!(res.unwrap(), vec![0, 1, 2, 3, 5, 7]);
 }
+
+#[test]
+fn test_fake_scanner_spawn_and_kill() {
+    use process_scanner::{FakeScanner, ProcessScanner};
+
+    let mut scanner = FakeScanner::default();
+
+    let first = scanner.spawn("mpv");
+    let second = scanner.spawn("mpv");
+    scanner.spawn("firefox");
+
+    assert_ne!(first, second);
+    assert_eq!(scanner.scan().unwrap().len(), 3);
+
+    scanner.kill(first);
+    let names: Vec<String> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|p| p.name)
+        .collect();
+    assert_eq!(names, vec!["mpv", "firefox"]);
+
+    scanner.kill_all("mpv");
+    let names: Vec<String> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|p| p.name)
+        .collect();
+    assert_eq!(names, vec!["firefox"]);
+}
+
+#[test]
+fn test_fake_scanner_scripted_error() {
+    use process_scanner::{FakeScanner, ProcessScanner};
+
+    let mut scanner = FakeScanner::default();
+    scanner.spawn("mpv");
+    scanner.fail_next_scan("permission denied");
+
+    assert_eq!(scanner.scan().unwrap_err().to_string(), "permission denied");
+    assert_eq!(scanner.scan().unwrap().len(), 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_system_scanner_reads_procfs_tree() {
+    use process_scanner::{ProcessScanner, SystemScanner};
+    use std::collections::HashSet;
+
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-procfs",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+
+    for (pid, name) in [
+        (1, "systemd"),
+        (42, "mpv"),
+        (43, "Web Content"),
+        (44, "mpv"),
+    ] {
+        std::fs::create_dir_all(dir.join(pid.to_string())).unwrap();
+        std::fs::write(dir.join(pid.to_string()).join("comm"), format!("{name}\n")).unwrap();
+    }
+    // Entries that do not belong to a process, and a process that exited before its comm file was read
+    std::fs::create_dir_all(dir.join("self")).unwrap();
+    std::fs::create_dir_all(dir.join("45")).unwrap();
+
+    let mut scanner = SystemScanner::with_proc_dir(&dir);
+    let mut process_list: Vec<(u32, String)> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|process| (process.pid, process.name))
+        .collect();
+    process_list.sort();
+    assert_eq!(
+        process_list,
+        [
+            (1, "systemd".into()),
+            (42, "mpv".into()),
+            (43, "Web Content".into()),
+            (44, "mpv".into()),
+        ]
+    );
+
+    scanner.filter_names(HashSet::from(["mpv".into()]));
+    let mut pids: Vec<u32> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|process| process.pid)
+        .collect();
+    pids.sort();
+    assert_eq!(pids, [42, 44]);
+
+    // The name may hold spaces and parentheses, so the CPU time is read after the last one
+    std::fs::write(
+        dir.join("43").join("stat"),
+        "43 (Web (Content)) S 1 43 43 0 -1 4194304 84 0 0 0 150 50 0 0 20 0 1 0 560452\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("43").join("status"),
+        "Name:\tWeb Content\nUid:\t1000\t1000\t1000\t1000\nVmPeak:\t  9000 kB\nVmRSS:\t  4096 kB\nThreads:\t1\n",
+    )
+    .unwrap();
+
+    // SAFETY: sysconf has no memory safety requirements
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    assert_eq!(
+        scanner.usage(43),
+        Some(process_scanner::ResourceUsage {
+            cpu_millis: 200 * 1000 / ticks_per_second,
+            rss_kib: 4096,
+        })
+    );
+    assert_eq!(scanner.usage(44), None);
+
+    // The owner is read from the status file, if the process still has one
+    scanner.filter_names(HashSet::from(["Web Content".into(), "mpv".into()]));
+    let mut owners: Vec<(u32, Option<u32>)> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|process| (process.pid, process.uid))
+        .collect();
+    owners.sort();
+    assert_eq!(owners, [(42, None), (43, Some(1000)), (44, None)]);
+
+    // Processes with other names are found by their cgroup
+    std::fs::write(
+        dir.join("43").join("cgroup"),
+        "0::/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-org.mozilla.firefox-77.scope\n",
+    )
+    .unwrap();
+    scanner.filter_names(HashSet::from(["mpv".into()]));
+    scanner.filter_cgroups(vec!["org.mozilla.*".into()]);
+    let mut flatpak_apps: Vec<(u32, Option<String>)> = scanner
+        .scan()
+        .unwrap()
+        .into_iter()
+        .map(|process| {
+            let flatpak_app = process.cgroup.and_then(|cgroup| cgroup.flatpak_app);
+            (process.pid, flatpak_app)
+        })
+        .collect();
+    flatpak_apps.sort();
+    assert_eq!(
+        flatpak_apps,
+        [
+            (42, None),
+            (43, Some("org.mozilla.firefox".into())),
+            (44, None)
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_scan_thread_keeps_the_scanner() {
+    use process_scanner::{FakeScanner, ProcessScanner, ScanThread};
+    use std::collections::HashSet;
+
+    let scanner = FakeScanner::default();
+    scanner.spawn("mpv");
+    scanner.spawn("firefox");
+
+    let scan_thread = ScanThread::spawn(scanner.clone());
+
+    let names = scan_thread
+        .run(|scanner| {
+            scanner.filter_names(HashSet::from(["mpv".into()]));
+            scanner.scan()
+        })
+        .await
+        .unwrap();
+    assert_eq!(names.len(), 1);
+
+    // The names to look for stay set for the next scans
+    let names = scan_thread.run(|scanner| scanner.scan()).await.unwrap();
+    assert_eq!(names[0].name, "mpv");
+    assert_eq!(scanner.scan_count(), 2);
+}
+
+#[test]
+fn test_start_stop_transitions() {
+    use process_scanner::{FakeScanner, ProcessScanner};
+    use structures::process::Processes;
+
+    let mut scanner = FakeScanner::default();
+    let mut processes = Processes(vec![process("mpv", 0), process("firefox", 0)]);
+
+    let first_seen = utc("2023-05-20T10:00:00Z");
+    let second_seen = utc("2023-05-20T10:00:15Z");
+    let after_exit = utc("2023-05-20T10:00:30Z");
+
+    let pid = scanner.spawn("mpv");
+    processes.update_running(&scanner.scan().unwrap(), first_seen);
+    assert!(processes.0[0].is_running);
+    assert_eq!(processes.0[0].last_seen_date, first_seen);
+    assert!(!processes.0[1].is_running);
+
+    processes.accrue_duration(second_seen);
+    processes.update_running(&scanner.scan().unwrap(), second_seen);
+    assert_eq!(processes.0[0].duration, 15);
+    assert_eq!(processes.0[0].last_seen_date, second_seen);
+
+    scanner.kill(pid);
+    processes.update_running(&scanner.scan().unwrap(), after_exit);
+    assert!(!processes.0[0].is_running);
+    // The last seen date must stay at the last scan where the process was still running
+    assert_eq!(processes.0[0].last_seen_date, second_seen);
+
+    processes.accrue_duration(utc("2023-05-20T10:00:45Z"));
+    assert_eq!(processes.0[0].duration, 15);
+}
+
+#[test]
+fn test_untracked_process_is_never_running() {
+    use process_scanner::{FakeScanner, ProcessScanner};
+    use structures::process::Processes;
+
+    let mut scanner = FakeScanner::default();
+    let mut processes = Processes(vec![process("mpv", 0)]);
+    processes.0[0].is_tracked = false;
+
+    scanner.spawn("mpv");
+    processes.update_running(&scanner.scan().unwrap(), utc("2023-05-20T10:00:00Z"));
+    processes.accrue_duration(utc("2023-05-20T10:01:00Z"));
+
+    assert!(!processes.0[0].is_running);
+    assert_eq!(processes.0[0].duration, 0);
+}
+
+#[test]
+fn test_fake_clock_advance_and_set() {
+    use clock::{Clock, FakeClock};
+
+    let start = utc("2023-12-31T23:59:50Z");
+    let clock = FakeClock::new(start);
+    let shared = clock.clone();
+
+    assert_eq!(clock.now(), start);
+
+    shared.advance(std::time::Duration::from_secs(20));
+    assert_eq!(clock.now(), utc("2024-01-01T00:00:10Z"));
+
+    clock.set(start);
+    assert_eq!(shared.now(), start);
+}
+
+#[tokio::test]
+async fn test_fake_clock_sleep_waits_for_advance() {
+    use clock::{Clock, FakeClock};
+    use std::time::Duration;
+
+    let clock = FakeClock::new(utc("2023-05-20T10:00:00Z"));
+
+    let mut sleep = clock.sleep(Duration::from_secs(10));
+
+    clock.advance(Duration::from_secs(9));
+    assert!(futures_lite::future::poll_once(&mut sleep).await.is_none());
+
+    clock.advance(Duration::from_secs(1));
+    assert!(futures_lite::future::poll_once(&mut sleep).await.is_some());
+}
+
+/// Give the spawned background tasks a chance to react to the fake clock or fake scanner
+async fn wait_until(mut condition: impl FnMut() -> bool) {
+    for _ in 0..1000 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    panic!("condition was not met in time");
+}
+
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+#[tokio::test]
+async fn test_duration_accrual() {
+    use clock::FakeClock;
+    use server_utils::background_tasks::update_duration;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let clock = leak(FakeClock::new(utc("2023-05-20T10:00:00Z")));
+    let config = leak(RwLock::new(Config {
+        duration_update_interval: 10,
+        ..Default::default()
+    }));
+
+    let mut running = process("mpv", 0);
+    running.is_running = true;
+    running.counted_until = Some(utc("2023-05-20T10:00:00Z"));
+    let processes = leak(RwLock::new(Processes(vec![running, process("firefox", 0)])));
+
+    tokio::spawn(update_duration(config, processes, clock));
+    tokio::task::yield_now().await;
+
+    clock.advance(Duration::from_secs(10));
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].duration == 10)).await;
+
+    // Nothing is added until a full interval has passed
+    clock.advance(Duration::from_secs(5));
+    tokio::task::yield_now().await;
+    assert_eq!(processes.read().await.0[0].duration, 10);
+
+    clock.advance(Duration::from_secs(5));
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].duration == 20)).await;
+
+    // Processes that are not running never accrue time
+    assert_eq!(processes.read().await.0[1].duration, 0);
+}
+
+#[tokio::test]
+async fn test_last_seen_updates() {
+    use clock::{Clock, FakeClock};
+    use process_scanner::FakeScanner;
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let start = utc("2023-05-20T10:00:00Z");
+    let clock = leak(FakeClock::new(start));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 15,
+        ..Default::default()
+    }));
+    let processes = leak(RwLock::new(Processes(vec![process("mpv", 0)])));
+
+    let scanner = FakeScanner::default();
+    let pid = scanner.spawn("mpv");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    // The first check happens immediately, before any sleeping
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+    assert_eq!(processes.read().await.0[0].last_seen_date, start);
+
+    clock.advance(Duration::from_secs(15));
+    let second_check = clock.now();
+    wait_until(|| {
+        processes
+            .try_read()
+            .is_ok_and(|p| p.0[0].last_seen_date == second_check)
+    })
+    .await;
+
+    scanner.kill(pid);
+    clock.advance(Duration::from_secs(15));
+    wait_until(|| processes.try_read().is_ok_and(|p| !p.0[0].is_running)).await;
+    assert_eq!(processes.read().await.0[0].last_seen_date, second_check);
+}
+
+#[test]
+fn test_short_sessions_are_not_counted() {
+    use process_scanner::{FakeScanner, ProcessScanner};
+    use structures::process::Processes;
+
+    let mut scanner = FakeScanner::default();
+    let mut processes = Processes(vec![process("mpv", 0)]);
+    processes.0[0].min_session = 60;
+
+    let pid = scanner.spawn("mpv");
+    processes.update_running(&scanner.scan().unwrap(), utc("2023-05-20T10:00:00Z"));
+    processes.accrue_duration(utc("2023-05-20T10:00:30Z"));
+    assert_eq!(processes.0[0].duration, 0);
+
+    // A run that ends before the minimum session length is discarded
+    scanner.kill(pid);
+    processes.update_running(&scanner.scan().unwrap(), utc("2023-05-20T10:00:45Z"));
+    scanner.spawn("mpv");
+    processes.update_running(&scanner.scan().unwrap(), utc("2023-05-20T10:01:00Z"));
+    processes.accrue_duration(utc("2023-05-20T10:01:30Z"));
+    assert_eq!(processes.0[0].duration, 0);
+
+    // Once a run reaches the minimum, it counts from its start
+    processes.accrue_duration(utc("2023-05-20T10:02:00Z"));
+    assert_eq!(processes.0[0].duration, 60);
+    processes.accrue_duration(utc("2023-05-20T10:02:15Z"));
+    assert_eq!(processes.0[0].duration, 75);
+}
+
+#[tokio::test]
+async fn test_per_process_poll_intervals() {
+    use clock::{Clock, FakeClock};
+    use process_scanner::FakeScanner;
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let start = utc("2023-05-20T10:00:00Z");
+    let clock = leak(FakeClock::new(start));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 30,
+        ..Default::default()
+    }));
+
+    let mut mpv = process("mpv", 0);
+    mpv.poll_interval = Some(5);
+    let mut steam = process("steam", 0);
+    steam.poll_interval = Some(120);
+    let processes = leak(RwLock::new(Processes(vec![mpv, steam])));
+
+    let scanner = FakeScanner::default();
+    scanner.spawn("mpv");
+    scanner.spawn("steam");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[1].is_running)).await;
+    assert_eq!(scanner.scan_count(), 1);
+
+    // Only mpv is due after its own interval, and the system is scanned once for it
+    clock.advance(Duration::from_secs(5));
+    let mpv_check = clock.now();
+    wait_until(|| {
+        processes
+            .try_read()
+            .is_ok_and(|p| p.0[0].last_seen_date == mpv_check)
+    })
+    .await;
+    assert_eq!(scanner.scan_count(), 2);
+    assert_eq!(processes.read().await.0[1].last_seen_date, start);
+
+    // Nothing is due before the next interval, so the system is not scanned
+    clock.advance(Duration::from_secs(4));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(scanner.scan_count(), 2);
+
+    clock.set(start + chrono::Duration::seconds(120));
+    wait_until(|| {
+        processes
+            .try_read()
+            .is_ok_and(|p| p.0[1].last_seen_date == start + chrono::Duration::seconds(120))
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_process_events_update_running_right_away() {
+    use clock::FakeClock;
+    use process_scanner::{FakeEvents, FakeScanner};
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let start = utc("2023-05-20T10:00:00Z");
+    let clock = leak(FakeClock::new(start));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 15,
+        ..Default::default()
+    }));
+    let processes = leak(RwLock::new(Processes(vec![
+        process("mpv", 0),
+        process("vlc", 0),
+    ])));
+
+    let scanner = FakeScanner::default();
+    scanner.report_events(FakeEvents::All);
+    let pid = scanner.spawn("mpv");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+
+    // The exit is seen the moment it happens, and the run is counted up to it
+    clock.advance(Duration::from_secs(7));
+    scanner.kill(pid);
+    wait_until(|| processes.try_read().is_ok_and(|p| !p.0[0].is_running)).await;
+    let mpv = processes.read().await.0[0].clone();
+    assert_eq!(mpv.last_seen_date, utc("2023-05-20T10:00:07Z"));
+    assert_eq!(mpv.duration, 7);
+
+    scanner.spawn("vlc");
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[1].is_running)).await;
+
+    // Processes are only scanned once, since everything else is reported
+    clock.advance(Duration::from_secs(60));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(scanner.scan_count(), 1);
+}
+
+#[tokio::test]
+async fn test_polling_resumes_when_process_events_stop() {
+    use clock::FakeClock;
+    use process_scanner::{FakeEvents, FakeScanner};
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let clock = leak(FakeClock::new(utc("2023-05-20T10:00:00Z")));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 15,
+        ..Default::default()
+    }));
+    let processes = leak(RwLock::new(Processes(vec![
+        process("mpv", 0),
+        process("vlc", 0),
+    ])));
+
+    let scanner = FakeScanner::default();
+    scanner.report_events(FakeEvents::All);
+    scanner.spawn("mpv");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+    assert_eq!(scanner.scan_count(), 1);
+
+    // Processes that start once events stopped are found by the next scans instead
+    scanner.stop_events();
+    wait_until(|| scanner.scan_count() == 2).await;
+    scanner.spawn("vlc");
+    clock.advance(Duration::from_secs(15));
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[1].is_running)).await;
+    assert!(processes.read().await.0[0].is_running);
+}
+
+#[tokio::test]
+async fn test_exits_are_watched_without_process_events() {
+    use clock::FakeClock;
+    use process_scanner::{FakeEvents, FakeScanner};
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let start = utc("2023-05-20T10:00:00Z");
+    let clock = leak(FakeClock::new(start));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 15,
+        ..Default::default()
+    }));
+    let processes = leak(RwLock::new(Processes(vec![process("mpv", 0)])));
+
+    let scanner = FakeScanner::default();
+    scanner.report_events(FakeEvents::Exits);
+    let pid = scanner.spawn("mpv");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+
+    clock.advance(Duration::from_secs(3));
+    scanner.kill(pid);
+    wait_until(|| processes.try_read().is_ok_and(|p| !p.0[0].is_running)).await;
+    assert_eq!(
+        processes.read().await.0[0].last_seen_date,
+        utc("2023-05-20T10:00:03Z")
+    );
+
+    // New processes are still only found by the next scan
+    scanner.spawn("mpv");
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!processes.read().await.0[0].is_running);
+
+    clock.advance(Duration::from_secs(12));
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+}
+
+#[tokio::test]
+async fn test_usage_is_summed_across_instances() {
+    use clock::FakeClock;
+    use process_scanner::{FakeScanner, ResourceUsage};
+    use server_utils::background_tasks::check_running_processes;
+    use std::time::Duration;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let clock = leak(FakeClock::new(utc("2023-05-20T10:00:00Z")));
+    let config = leak(RwLock::new(Config {
+        poll_interval: 10,
+        ..Default::default()
+    }));
+    let processes = leak(RwLock::new(Processes(vec![process("mpv", 0)])));
+
+    let usage = |cpu_millis, rss_kib| ResourceUsage {
+        cpu_millis,
+        rss_kib,
+    };
+
+    let scanner = FakeScanner::default();
+    let first = scanner.spawn("mpv");
+    let second = scanner.spawn("mpv");
+    scanner.set_usage(first, usage(1000, 100));
+    scanner.set_usage(second, usage(2000, 300));
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    // The CPU time used before the server started is not counted
+    wait_until(|| {
+        processes
+            .try_read()
+            .is_ok_and(|p| p.0[0].usage.rss_samples == 1)
+    })
+    .await;
+    let mpv_usage = processes.read().await.0[0].usage;
+    assert_eq!(mpv_usage.cpu_millis, 0);
+    assert_eq!(mpv_usage.peak_rss_kib, 400);
+    assert_eq!(mpv_usage.peak_instances, 2);
+
+    // A new instance counts all of its CPU time
+    scanner.set_usage(first, usage(4000, 200));
+    let third = scanner.spawn("mpv");
+    scanner.set_usage(third, usage(500, 300));
+    clock.advance(Duration::from_secs(10));
+
+    wait_until(|| {
+        processes
+            .try_read()
+            .is_ok_and(|p| p.0[0].usage.rss_samples == 2)
+    })
+    .await;
+    let mpv_usage = processes.read().await.0[0].usage;
+    assert_eq!(mpv_usage.cpu_millis, 3500);
+    assert_eq!(mpv_usage.peak_rss_kib, 800);
+    assert_eq!(mpv_usage.avg_rss_kib, 600);
+    assert_eq!(mpv_usage.peak_instances, 3);
+}
+
+#[test]
+fn test_launches_and_sessions() {
+    use process_scanner::ProcessSnapshot;
+    use structures::process::Processes;
+
+    let start = utc("2023-05-20T10:00:00Z");
+    let at = |seconds| start + chrono::Duration::seconds(seconds);
+    let mpv = |pid| ProcessSnapshot {
+        pid,
+        name: "mpv".into(),
+        uid: current_uid(),
+        cgroup: None,
+    };
+
+    let mut processes = Processes(vec![process("mpv", 0)]);
+
+    // A run that was already going on when the server started is not a launch
+    processes.update_running(&[mpv(9)], at(-100));
+    assert_eq!(processes.0[0].launches, 0);
+    processes.update_running(&[], at(-50));
+    assert_eq!(processes.0[0].launches, 0);
+
+    processes.update_running(&[mpv(1), mpv(2)], start);
+    processes.update_instances(["mpv", "mpv"]);
+    assert_eq!(processes.0[0].launches, 1);
+    assert_eq!(processes.0[0].instances, 2);
+
+    // Another check of the same run is not another launch
+    processes.update_running(&[mpv(1)], at(100));
+    processes.update_instances(["mpv"]);
+    processes.update_running(&[], at(100));
+    processes.update_instances([]);
+    assert_eq!(processes.0[0].launches, 1);
+    assert_eq!(processes.0[0].longest_session, 100);
+    assert_eq!(processes.0[0].instances, 0);
+
+    // With a minimum session length, a run is only a launch once it lasted long enough
+    processes.0[0].min_session = 30;
+    processes.update_running(&[mpv(3)], at(200));
+    processes.update_running(&[], at(210));
+    assert_eq!(processes.0[0].launches, 1);
+
+    processes.update_running(&[mpv(4)], at(300));
+    processes.update_running(&[mpv(4)], at(340));
+    processes.accrue_duration(at(340));
+    assert_eq!(processes.0[0].launches, 2);
+    processes.update_running(&[mpv(4)], at(500));
+    processes.update_running(&[], at(500));
+    assert_eq!(processes.0[0].launches, 2);
+    assert_eq!(processes.0[0].longest_session, 200);
+
+    // How many instances are running is not saved
+    processes.update_running(&[mpv(5)], at(600));
+    processes.update_instances(["mpv"]);
+    assert!(!serde_json::to_string(&processes)
+        .unwrap()
+        .contains("instances"));
+}
+
+#[test]
+fn test_parse_users() {
+    use structures::process::Users;
+
+    assert_eq!("me".parse(), Ok(Users::Me));
+    assert_eq!("any".parse(), Ok(Users::Any));
+    assert_eq!("1000, 1001".parse(), Ok(Users::Uids(vec![1000, 1001])));
+    assert!("1000,someone".parse::<Users>().is_err());
+
+    // Processes saved before users could be chosen only count the current user
+    let mut process = process("mpv", 0);
+    let serialized = serde_json::to_string(&process).unwrap();
+    assert!(!serialized.contains("users"));
+
+    process.users = Users::Uids(vec![1000]);
+    let serialized = serde_json::to_string(&process).unwrap();
+    assert!(serialized.contains(r#""users":{"uids":[1000]}"#));
+    let deserialized: structures::process::Process = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized.users, Users::Uids(vec![1000]));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_other_users_processes_are_not_counted() {
+    use clock::FakeClock;
+    use process_scanner::FakeScanner;
+    use server_utils::background_tasks::check_running_processes;
+    use structures::{
+        config::Config,
+        process::{Processes, Users},
+    };
+    use tokio::sync::RwLock;
+
+    let me = current_uid().unwrap();
+    let someone_else = me.wrapping_add(1);
+
+    let clock = leak(FakeClock::new(utc("2023-05-20T10:00:00Z")));
+    let config = leak(RwLock::new(Config::default()));
+
+    let mut vlc = process("vlc", 0);
+    vlc.users = Users::Any;
+    let mut steam = process("steam", 0);
+    steam.users = Users::Uids(vec![someone_else]);
+    let processes = leak(RwLock::new(Processes(vec![
+        process("mpv", 0),
+        vlc,
+        steam,
+        process("firefox", 0),
+    ])));
+
+    let scanner = FakeScanner::default();
+    scanner.spawn_as("mpv", Some(someone_else));
+    scanner.spawn_as("vlc", Some(someone_else));
+    scanner.spawn_as("steam", Some(someone_else));
+    scanner.spawn_as("firefox", Some(someone_else));
+    scanner.spawn("firefox");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[1].is_running)).await;
+
+    let processes = processes.read().await;
+    assert!(!processes.0[0].is_running);
+    assert!(processes.0[2].is_running);
+    // Only the current user's instance of firefox counts
+    assert!(processes.0[3].is_running);
+    assert_eq!(processes.0[3].instances, 1);
+}
+
+#[test]
+fn test_cgroup_attributes() {
+    use process_scanner::{glob_matches, Cgroup};
+
+    let flatpak = Cgroup::from_path(
+        "/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-org.mozilla.firefox-12345.scope",
+    );
+    assert_eq!(
+        flatpak.unit.as_deref(),
+        Some("app-flatpak-org.mozilla.firefox-12345.scope")
+    );
+    assert_eq!(flatpak.flatpak_app.as_deref(), Some("org.mozilla.firefox"));
+    assert_eq!(flatpak.container_id, None);
+    assert!(flatpak.matches("app-flatpak-org.mozilla.firefox-*.scope"));
+    assert!(flatpak.matches("org.mozilla.firefox"));
+    assert!(flatpak.matches("/user.slice/*/app.slice/*"));
+    assert!(!flatpak.matches("app-flatpak-org.gnome.*"));
+
+    let id = "4f1d0e8a9b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e";
+    let docker = Cgroup::from_path(&format!("/system.slice/docker-{id}.scope"));
+    assert_eq!(docker.container_id.as_deref(), Some(id));
+    assert!(docker.matches("4f1d0e8a*"));
+    let cgroupfs = Cgroup::from_path(&format!("/kubepods/besteffort/pod1234/{id}"));
+    assert_eq!(cgroupfs.container_id.as_deref(), Some(id));
+    assert_eq!(cgroupfs.unit, None);
+
+    let service = Cgroup::from_path("/system.slice/nginx.service");
+    assert_eq!(service.unit.as_deref(), Some("nginx.service"));
+    assert_eq!(service.flatpak_app, None);
+
+    // The systemd hierarchy is used when cgroup v1 is still mounted
+    let hybrid = Cgroup::from_proc_file(
+        "4:memory:/user.slice\n1:name=systemd:/system.slice/sshd.service\n0::/system.slice/sshd.service\n",
+    )
+    .unwrap();
+    assert_eq!(hybrid.unit.as_deref(), Some("sshd.service"));
+    let unified = Cgroup::from_proc_file("0::/system.slice/cron.service\n").unwrap();
+    assert_eq!(unified.path, "/system.slice/cron.service");
+    assert_eq!(Cgroup::from_proc_file(""), None);
+
+    assert!(glob_matches("*", ""));
+    assert!(glob_matches("a*c?e", "abbbcde"));
+    assert!(glob_matches("*.scope", "app-1.scope.scope"));
+    assert!(!glob_matches("a*c", "abcd"));
+    assert!(!glob_matches("?", ""));
+    assert!(glob_matches("caf?-*", "café-1"));
+    assert!(glob_matches("*é", "éaé"));
+    assert!(!glob_matches("caf??", "café"));
+}
+
+#[tokio::test]
+async fn test_processes_are_matched_by_cgroup() {
+    use clock::FakeClock;
+    use process_scanner::FakeScanner;
+    use server_utils::background_tasks::check_running_processes;
+    use structures::{config::Config, process::Processes};
+    use tokio::sync::RwLock;
+
+    let clock = leak(FakeClock::new(utc("2023-05-20T10:00:00Z")));
+    let config = leak(RwLock::new(Config::default()));
+
+    let mut firefox = process("firefox", 0);
+    firefox.cgroup = Some("app-flatpak-org.mozilla.firefox-*.scope".into());
+    let mut thunderbird = process("thunderbird", 0);
+    thunderbird.cgroup = Some("org.mozilla.Thunderbird".into());
+    let processes = leak(RwLock::new(Processes(vec![
+        firefox,
+        thunderbird,
+        process("bwrap", 0),
+    ])));
+
+    let scanner = FakeScanner::default();
+    scanner.spawn_in_cgroup(
+        "bwrap",
+        "/user.slice/user-1000.slice/user@1000.service/app.slice/app-flatpak-org.mozilla.firefox-77.scope",
+    );
+    scanner.spawn("bwrap");
+    // A process named like a tracked process that is matched by cgroup is not an instance of it
+    scanner.spawn("thunderbird");
+
+    tokio::spawn(check_running_processes(
+        config,
+        processes,
+        scanner.clone(),
+        clock,
+    ));
+
+    wait_until(|| processes.try_read().is_ok_and(|p| p.0[0].is_running)).await;
+
+    let processes = processes.read().await;
+    assert_eq!(processes.0[0].instances, 1);
+    assert!(!processes.0[1].is_running);
+    // The same instance counts for every tracked process it matches
+    assert_eq!(processes.0[2].instances, 2);
+}
+
+#[test]
+fn test_combine_usage() {
+    use structures::process::Usage;
+
+    let mut usage = Usage::default();
+    usage.record(1000, 100, 1);
+    usage.record(500, 400, 2);
+
+    let mut other = Usage::default();
+    other.record(2000, 700, 1);
+
+    usage.combine(&other);
+
+    assert_eq!(
+        usage,
+        Usage {
+            cpu_millis: 3500,
+            peak_rss_kib: 700,
+            avg_rss_kib: 400,
+            rss_samples: 3,
+            peak_instances: 2,
+        }
+    );
+}
+
+#[test]
+fn test_rank_processes() {
+    use commands::Rank;
+
+    let mut mpv = process("mpv", 100);
+    mpv.usage.record(5000, 100, 1);
+    let mut steam = process("steam", 200);
+    steam.usage.record(1000, 900, 3);
+
+    let mut processes = [&mpv, &steam];
+    processes.sort_by_key(|process| std::cmp::Reverse(Rank::Cpu.value_of(process)));
+    assert_eq!(processes[0].name, "mpv");
+
+    processes.sort_by_key(|process| std::cmp::Reverse(Rank::PeakRss.value_of(process)));
+    assert_eq!(processes[0].name, "steam");
+
+    assert_eq!(Rank::PeakInstances.value_of(&steam), 3);
+    assert_eq!(Rank::Duration.value_of(&mpv), 100);
+}
+
+#[test]
+fn test_format_kib() {
+    assert_eq!(format_kib(512), "512 KiB");
+    assert_eq!(format_kib(82_432), "80.5 MiB");
+    assert_eq!(format_kib(1_310_720), "1.25 GiB");
+}
+
+#[test]
+fn test_running_pids_follow_forks_and_execs() {
+    use process_scanner::{ProcessEvent, ProcessSnapshot};
+    use server_utils::running_pids::RunningPids;
+    use structures::process::Processes;
+
+    let processes = Processes(vec![process("mpv", 0), process("vlc", 0)]);
+    let snapshot = |pid: u32, name: &str| ProcessSnapshot {
+        pid,
+        name: name.into(),
+        uid: current_uid(),
+        cgroup: None,
+    };
+
+    let mut running_pids = RunningPids::default();
+    let new_pids = running_pids.replace(&processes, &[snapshot(1, "mpv"), snapshot(2, "bash")]);
+    assert_eq!(new_pids, [1]);
+
+    // A copy of a tracked process is another instance of it
+    let changed = running_pids.apply(
+        &processes,
+        &ProcessEvent::Forked {
+            parent: 1,
+            child: 3,
+        },
+    );
+    assert_eq!(changed, ["mpv"]);
+    assert!(running_pids
+        .apply(
+            &processes,
+            &ProcessEvent::Forked {
+                parent: 2,
+                child: 4
+            }
+        )
+        .is_empty());
+
+    // An instance that runs another program stops being an instance of the old one
+    let changed = running_pids.apply(&processes, &ProcessEvent::Executed(snapshot(3, "vlc")));
+    assert_eq!(changed, ["mpv", "vlc"]);
+    let changed = running_pids.apply(&processes, &ProcessEvent::Executed(snapshot(1, "bash")));
+    assert_eq!(changed, ["mpv"]);
+    assert!(!running_pids.is_running("mpv"));
+    assert!(running_pids.is_running("vlc"));
+
+    let changed = running_pids.apply(&processes, &ProcessEvent::Exited { pid: 3 });
+    assert_eq!(changed, ["vlc"]);
+    assert!(!running_pids.is_running("vlc"));
+}
+
+#[test]
+fn test_timestamps_serialize_as_utc() {
+    let process = process("mpv", 0);
+
+    let serialized = serde_json::to_string(&process).unwrap();
+    assert!(serialized.contains(r#""added_date":"2023-05-27T16:42:03Z""#));
+
+    let deserialized: structures::process::Process = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized.added_date, process.added_date);
+}
+
+#[test]
+fn test_timestamps_with_offset_are_converted_to_utc() {
+    let serialized = serde_json::to_string(&process("mpv", 0))
+        .unwrap()
+        .replace("2023-05-27T16:42:03Z", "2023-05-27T18:42:03+02:00");
+
+    let deserialized: structures::process::Process = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(deserialized.added_date, utc("2023-05-27T16:42:03Z"));
+}
+
+#[test]
+fn test_naive_timestamps_are_migrated_from_local_time() {
+    // Older versions saved dates in local time without any offset
+    let serialized = serde_json::to_string(&process("mpv", 0))
+        .unwrap()
+        .replace("2023-05-27T16:42:03Z", "2023-05-20T10:00:00");
+
+    let deserialized: structures::process::Process = serde_json::from_str(&serialized).unwrap();
+
+    let naive =
+        chrono::NaiveDateTime::parse_from_str("2023-05-20 10:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(deserialized.added_date, local_to_utc(naive));
+    assert_eq!(
+        deserialized
+            .added_date
+            .with_timezone(&chrono::Local)
+            .naive_local(),
+        naive
+    );
+}
+
+#[test]
+fn test_parse_display_timezone() {
+    assert_eq!("local".parse(), Ok(DisplayTimezone::Local));
+    assert_eq!(
+        "UTC".parse(),
+        Ok(DisplayTimezone::Fixed(
+            chrono::FixedOffset::east_opt(0).unwrap()
+        ))
+    );
+    assert_eq!(
+        "+02:00".parse(),
+        Ok(DisplayTimezone::Fixed(
+            chrono::FixedOffset::east_opt(2 * 3600).unwrap()
+        ))
+    );
+    assert_eq!(
+        "-0530".parse(),
+        Ok(DisplayTimezone::Fixed(
+            chrono::FixedOffset::west_opt(5 * 3600 + 30 * 60).unwrap()
+        ))
+    );
+    assert_eq!(
+        "Europe/Sofia".parse(),
+        Ok(DisplayTimezone::Named(chrono_tz::Europe::Sofia))
+    );
+
+    assert!("Mars/Olympus".parse::<DisplayTimezone>().is_err());
+    assert!("+25:00".parse::<DisplayTimezone>().is_err());
+}
+
+#[test]
+fn test_format_datetime_in_timezone() {
+    let summer = utc("2023-07-01T12:00:00Z");
+    let winter = utc("2023-01-01T12:00:00Z");
+
+    let utc_zone = "UTC".parse().unwrap();
+    assert_eq!(format_datetime(&summer, utc_zone), "2023/07/01 12:00:00");
+
+    let fixed = "+02:00".parse().unwrap();
+    assert_eq!(format_datetime(&summer, fixed), "2023/07/01 14:00:00");
+    assert_eq!(format_datetime(&winter, fixed), "2023/01/01 14:00:00");
+
+    // Named zones follow daylight saving time
+    let sofia = "Europe/Sofia".parse().unwrap();
+    assert_eq!(format_datetime(&summer, sofia), "2023/07/01 15:00:00");
+    assert_eq!(format_datetime(&winter, sofia), "2023/01/01 14:00:00");
+}
+
+#[test]
+fn test_combine_hosts() {
+    use structures::host_log::{combine_hosts, HostLog};
+
+    let mut desktop_mpv = process("mpv", 3600);
+    desktop_mpv.added_date = utc("2023-05-10T10:00:00Z");
+    desktop_mpv.last_seen_date = utc("2023-05-10T12:00:00Z");
+    let mut laptop_mpv = process("mpv", 1800);
+    laptop_mpv.is_running = true;
+    laptop_mpv.added_date = utc("2023-05-01T10:00:00Z");
+    laptop_mpv.last_seen_date = utc("2023-05-21T10:00:00Z");
+    let laptop_vlc = process("vlc", 60);
+
+    let laptop = HostLog {
+        host: "laptop".into(),
+        updated_date: utc("2023-05-21T10:00:00Z"),
+        processes: vec![laptop_mpv, laptop_vlc],
+    };
+
+    let combined = combine_hosts(&[desktop_mpv.clone()], [&laptop]);
+
+    assert_eq!(combined.len(), 2);
+    assert_eq!(combined[0].duration, 5400);
+    assert!(combined[0].is_running);
+    assert_eq!(combined[0].added_date, utc("2023-05-01T10:00:00Z"));
+    assert_eq!(combined[0].last_seen_date, utc("2023-05-21T10:00:00Z"));
+    assert_eq!(
+        (combined[1].name.as_str(), combined[1].duration),
+        ("vlc", 60)
+    );
+
+    // Combining again gives the same totals since every host log holds totals, not increments
+    assert_eq!(combine_hosts(&[desktop_mpv], [&laptop])[0].duration, 5400);
+}
+
+#[tokio::test]
+async fn test_sync_hosts_through_directory() {
+    use clock::FakeClock;
+    use server_utils::background_tasks::sync_hosts_once;
+    use structures::{config::Config, host_log::SyncedHosts, process::Processes};
+    use tokio::sync::RwLock;
+
+    let sync_dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-sync",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&sync_dir);
+
+    let clock = FakeClock::new(utc("2023-05-20T10:00:00Z"));
+    let host = |name: &str, duration| {
+        let mpv = process("mpv", duration);
+
+        (
+            RwLock::new(Config {
+                sync_dir: Some(sync_dir.clone()),
+                host_name: Some(name.into()),
+                data_dir: Some(sync_dir.join("data").join(name)),
+                ..Default::default()
+            }),
+            RwLock::new(Processes(vec![mpv])),
+            RwLock::new(SyncedHosts::default()),
+        )
+    };
+
+    let desktop = host("desktop", 3600);
+    let laptop = host("laptop", 1800);
+
+    for _ in 0..2 {
+        for (config, processes, synced_hosts) in [&desktop, &laptop] {
+            sync_hosts_once(config, processes, synced_hosts, &clock)
+                .await
+                .unwrap();
+        }
+    }
+
+    let (_, processes, synced_hosts) = &desktop;
+    let synced_hosts = synced_hosts.read().await;
+    let processes = processes.read().await;
+
+    assert_eq!(synced_hosts.local_host, "desktop");
+    assert_eq!(
+        synced_hosts.others.keys().collect::<Vec<_>>(),
+        vec!["laptop"]
+    );
+    assert_eq!(
+        synced_hosts.select("desktop", &processes).unwrap()[0].duration,
+        3600
+    );
+    assert_eq!(
+        synced_hosts.select("laptop", &processes).unwrap()[0].duration,
+        1800
+    );
+    assert_eq!(
+        synced_hosts.select("all", &processes).unwrap()[0].duration,
+        5400
+    );
+
+    let error = synced_hosts.select("phone", &processes).unwrap_err();
+    assert!(error.contains("desktop") && error.contains("laptop"));
+    drop((synced_hosts, processes));
+
+    // A renamed host removes the log it wrote under its old name
+    desktop.0.write().await.host_name = Some("workstation".into());
+
+    for (config, processes, synced_hosts) in [&desktop, &laptop] {
+        sync_hosts_once(config, processes, synced_hosts, &clock)
+            .await
+            .unwrap();
+    }
+
+    assert!(!sync_dir.join("desktop.json").exists());
+
+    let (_, processes, synced_hosts) = &laptop;
+    let synced_hosts = synced_hosts.read().await;
+    assert_eq!(
+        synced_hosts.others.keys().collect::<Vec<_>>(),
+        vec!["workstation"]
+    );
+    assert_eq!(
+        synced_hosts
+            .select("all", &*processes.read().await)
+            .unwrap()[0]
+            .duration,
+        5400
+    );
+    drop(synced_hosts);
+
+    // A copy of the machine from before the rename has its own data dir, so the logs of the original are never
+    // removed by it
+    let copy = host("desktop", 3600);
+    copy.0.write().await.data_dir = Some(sync_dir.join("data").join("copy"));
+    for (config, processes, synced_hosts) in [&copy, &desktop] {
+        sync_hosts_once(config, processes, synced_hosts, &clock)
+            .await
+            .unwrap();
+    }
+    assert!(sync_dir.join("desktop.json").exists());
+    assert!(sync_dir.join("workstation.json").exists());
+
+    std::fs::remove_dir_all(&sync_dir).unwrap();
+}
+
+#[test]
+fn test_host_log_file_names() {
+    use structures::host_log::HostLog;
+
+    assert_eq!(
+        HostLog::file_name("desktop-1.local"),
+        "desktop-1.local.json"
+    );
+
+    // Names that only differ in characters that cannot be used in file names do not share a file
+    let spaced = HostLog::file_name("my host");
+    let slashed = HostLog::file_name("my/host");
+    assert!(spaced.starts_with("my_host-") && spaced.ends_with(".json"));
+    assert_ne!(spaced, slashed);
+    assert_ne!(spaced, HostLog::file_name("my_host"));
+}
+
+#[test]
+fn test_socket_name() {
+    use interprocess::local_socket::NameTypeSupport;
+    use std::path::PathBuf;
+
+    let runtime_dir = Some(PathBuf::from("/run/user/1000"));
+
+    assert_eq!(
+        socket_name_from(
+            None,
+            None,
+            runtime_dir.clone(),
+            NameTypeSupport::Both,
+            DEFAULT_PROFILE
+        ),
+        "/run/user/1000/simple-process-tracker.sock"
+    );
+
+    // Every profile has its own socket
+    assert_eq!(
+        socket_name_from(
+            None,
+            None,
+            runtime_dir.clone(),
+            NameTypeSupport::Both,
+            "work"
+        ),
+        "/run/user/1000/simple-process-tracker-work.sock"
+    );
+
+    // The command line takes priority over the environment
+    assert_eq!(
+        socket_name_from(
+            Some("@flag.sock"),
+            Some("/tmp/env.sock"),
+            runtime_dir.clone(),
+            NameTypeSupport::Both,
+            "work"
+        ),
+        "@flag.sock"
+    );
+    assert_eq!(
+        socket_name_from(
+            None,
+            Some("/tmp/env.sock"),
+            runtime_dir,
+            NameTypeSupport::Both,
+            DEFAULT_PROFILE
+        ),
+        "/tmp/env.sock"
+    );
+
+    // Without a runtime dir the socket name is made unique per user
+    let fallback = socket_name_from(
+        None,
+        None,
+        None,
+        NameTypeSupport::OnlyPaths,
+        DEFAULT_PROFILE,
+    );
+    assert!(fallback.starts_with("/tmp/simple-process-tracker-") && fallback.ends_with(".sock"));
+    assert_ne!(fallback, "/tmp/simple-process-tracker.sock");
+
+    let namespaced = socket_name_from(
+        None,
+        Some(""),
+        None,
+        NameTypeSupport::OnlyNamespaced,
+        "work",
+    );
+    assert!(
+        namespaced.starts_with("@simple-process-tracker-") && namespaced.ends_with("-work.sock")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_socket_owner_is_checked() {
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-socket-owner",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let socket = dir.join("server.sock");
+    let socket_name = socket.to_str().unwrap();
+    assert!(check_socket_owner(socket_name).is_err());
+
+    std::fs::write(&socket, "").unwrap();
+    assert!(check_socket_owner(socket_name).is_ok());
+
+    // Only root can give a file to another user
+    if current_uid() == Some(0) {
+        std::os::unix::fs::lchown(&socket, Some(65534), None).unwrap();
+        assert!(check_socket_owner(socket_name)
+            .unwrap_err()
+            .contains("belongs to uid 65534"));
+    }
+
+    _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_profile_names() {
+    assert_eq!(parse_profile_name("work_2-b").unwrap(), "work_2-b");
+
+    for invalid in ["", "../work", "work/personal", "work sock", "@work"] {
+        assert!(parse_profile_name(invalid).is_err(), "{invalid}");
+    }
+
+    // Named profiles live next to each other, apart from the default profile
+    let work = get_profile_dir("work").unwrap();
+    let default = get_profile_dir(DEFAULT_PROFILE).unwrap();
+    assert_eq!(work, default.join("profiles").join("work"));
+}
+
+#[test]
+fn test_data_dir() {
+    use std::path::{Path, PathBuf};
+
+    let default_base_dir = Some(PathBuf::from(
+        "/home/user/.local/share/simple-process-tracker",
+    ));
+
+    assert_eq!(
+        data_dir_from(None, None, default_base_dir.clone(), DEFAULT_PROFILE).unwrap(),
+        Path::new("/home/user/.local/share/simple-process-tracker")
+    );
+    assert_eq!(
+        data_dir_from(None, None, default_base_dir.clone(), "work").unwrap(),
+        Path::new("/home/user/.local/share/simple-process-tracker/profiles/work")
+    );
+
+    // The environment takes priority over the config, which is already specific to a profile
+    assert_eq!(
+        data_dir_from(
+            None,
+            Some(Path::new("/data/work")),
+            default_base_dir.clone(),
+            "work"
+        )
+        .unwrap(),
+        Path::new("/data/work")
+    );
+    assert_eq!(
+        data_dir_from(
+            Some(PathBuf::from("/env")),
+            Some(Path::new("/data/work")),
+            default_base_dir,
+            "work"
+        )
+        .unwrap(),
+        Path::new("/env/profiles/work")
+    );
+}
+
+#[test]
+fn test_migrate_data_files() {
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-migrate",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+
+    let config_dir = dir.join("config");
+    let data_dir = dir.join("data");
+    std::fs::create_dir_all(&config_dir).unwrap();
+
+    for file_name in ["config.json", "processes.json", "journal.json"] {
+        std::fs::write(config_dir.join(file_name), file_name).unwrap();
+    }
+
+    let moved = migrate_data_files(&config_dir, &data_dir).unwrap();
+
+    // Only the files that older versions kept next to the settings are moved
+    assert_eq!(moved, [data_dir.join("processes.json")]);
+    assert!(config_dir.join("config.json").exists());
+    assert!(config_dir.join("journal.json").exists());
+    assert!(!config_dir.join("processes.json").exists());
+    assert_eq!(
+        std::fs::read_to_string(data_dir.join("processes.json")).unwrap(),
+        "processes.json"
+    );
+
+    // A file that already exists in the data dir is newer and is not overwritten
+    std::fs::write(config_dir.join("processes.json"), "older").unwrap();
+    assert!(migrate_data_files(&config_dir, &data_dir)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        std::fs::read_to_string(data_dir.join("processes.json")).unwrap(),
+        "processes.json"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use simple_process_tracker_rs::{
+    clock::FakeClock,
+    commands::{self, DurationCalculation, MoveDirection},
+    server_utils::user_commands::{
+        add_new_process, change_duration, move_process, redo_operation, remove_processes,
+        undo_operation,
+    },
+    structures::{
+        journal::{Journal, Operation, JOURNAL_CAPACITY},
+        process::Processes,
+    },
+};
+use tokio::sync::RwLock;
+
+use common::{process, utc};
+
+fn names(processes: &Processes) -> Vec<&str> {
+    processes
+        .0
+        .iter()
+        .map(|process| process.name.as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_undo_and_redo_remove() {
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+    let processes = RwLock::new(Processes(vec![
+        process("mpv", 60),
+        process("vlc", 120),
+        process("firefox", 180),
+    ]));
+    let journal = RwLock::default();
+
+    remove_processes(1, &processes, &journal, &clock)
+        .await
+        .unwrap();
+    assert_eq!(names(&*processes.read().await), ["mpv", "firefox"]);
+
+    let response = undo_operation(&processes, &journal).await.unwrap();
+    assert_eq!(response, "undid removed vlc");
+    // The process comes back with its duration and at its old ID
+    assert_eq!(names(&*processes.read().await), ["mpv", "vlc", "firefox"]);
+    assert_eq!(processes.read().await.0[1].duration, 120);
+
+    let response = redo_operation(&processes, &journal).await.unwrap();
+    assert_eq!(response, "redid removed vlc");
+    assert_eq!(names(&*processes.read().await), ["mpv", "firefox"]);
+
+    assert!(redo_operation(&processes, &journal).await.is_err());
+}
+
+#[tokio::test]
+async fn test_undo_duration_keeps_time_tracked_since() {
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+    let processes = RwLock::new(Processes(vec![process("mpv", 100)]));
+    let journal = RwLock::default();
+
+    change_duration(
+        commands::Duration {
+            id: 0,
+            operation: DurationCalculation::Subtract { seconds: 99999 },
+        },
+        &processes,
+        &journal,
+        &clock,
+    )
+    .await
+    .unwrap();
+    assert_eq!(processes.read().await.0[0].duration, 0);
+
+    // The process keeps running after the mistake
+    processes.write().await.0[0].duration += 10;
+
+    undo_operation(&processes, &journal).await.unwrap();
+    assert_eq!(processes.read().await.0[0].duration, 110);
+}
+
+#[tokio::test]
+async fn test_undo_move_and_add() {
+    let clock = FakeClock::new(utc("2023-05-20T14:00:00Z"));
+    let processes = RwLock::new(Processes(vec![
+        process("mpv", 60),
+        process("vlc", 120),
+        process("firefox", 180),
+    ]));
+    let journal = RwLock::default();
+
+    move_process(
+        commands::Move {
+            id: 2,
+            direction: MoveDirection::Top,
+        },
+        &processes,
+        &journal,
+        &clock,
+    )
+    .await
+    .unwrap();
+    assert_eq!(names(&*processes.read().await), ["firefox", "mpv", "vlc"]);
+
+    add_new_process(
+        commands::Add {
+            name: "strawberry".into(),
+            duration: None,
+            notes: None,
+            icon: None,
+            added_date: None,
+            poll_interval: None,
+            min_session: None,
+            users: None,
+            cgroup: None,
+        },
+        &processes,
+        &journal,
+        &clock,
+    )
+    .await
+    .unwrap();
+
+    undo_operation(&processes, &journal).await.unwrap();
+    assert_eq!(names(&*processes.read().await), ["firefox", "mpv", "vlc"]);
+
+    undo_operation(&processes, &journal).await.unwrap();
+    assert_eq!(names(&*processes.read().await), ["mpv", "vlc", "firefox"]);
+
+    assert_eq!(
+        undo_operation(&processes, &journal)
+            .await
+            .unwrap_err()
+            .to_string(),
+        "nothing to undo"
+    );
+}
+
+#[test]
+fn test_undo_fails_without_changes_if_process_is_gone() {
+    let mut processes = Processes(vec![process("mpv", 60)]);
+    let mut journal = Journal::default();
+
+    journal.record(
+        utc("2023-05-20T14:00:00Z"),
+        "import from ./export.json",
+        Operation::Batch(vec![
+            Operation::Add {
+                id: 1,
+                process: process("vlc", 60),
+            },
+            Operation::Update {
+                before: Box::new(process("mpv", 0)),
+                after: Box::new(process("mpv", 60)),
+            },
+        ]),
+    );
+
+    // The import added vlc, which was then removed by hand. Reverting the update of mpv must not stick
+    assert!(journal.undo(&mut processes).is_err());
+    assert_eq!(names(&processes), ["mpv"]);
+    assert_eq!(processes.0[0].duration, 60);
+    assert_eq!(journal.undo.len(), 1);
+}
+
+#[test]
+fn test_journal_is_bounded_and_survives_serialization() {
+    let mut journal = Journal::default();
+
+    for i in 0..JOURNAL_CAPACITY + 5 {
+        journal.record(
+            utc("2023-05-20T14:00:00Z"),
+            &format!("removed process {i}"),
+            Operation::Remove {
+                id: 0,
+                process: process(&format!("process {i}"), 0),
+            },
+        );
+    }
+
+    let journal: Journal = serde_json::from_str(&serde_json::to_string(&journal).unwrap()).unwrap();
+
+    assert_eq!(journal.undo.len(), JOURNAL_CAPACITY);
+    assert_eq!(journal.undo[0].description, "removed process 5");
+}
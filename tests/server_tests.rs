@@ -0,0 +1,80 @@
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    time::Duration,
+};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "simple-process-tracker-test-{}-{name}",
+        std::process::id()
+    ));
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+/// Launch the server with its own config dir, data dir and socket, and wait until it accepts connections
+fn launch_server(dir: &Path) -> (Child, PathBuf) {
+    let socket = dir.join("server.sock");
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_simple-process-tracker-cli"))
+        .args(["--socket", socket.to_str().unwrap(), "launch"])
+        .env("SPT_CONFIG_DIR", dir.join("config"))
+        .env("SPT_DATA_DIR", dir.join("data"))
+        .spawn()
+        .unwrap();
+
+    for _ in 0..100 {
+        if UnixStream::connect(&socket).is_ok() {
+            return (server, socket);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    _ = server.kill();
+    _ = server.wait();
+    panic!("server did not start");
+}
+
+/// Send a raw request line and read the response
+fn send(socket: &Path, request: &str) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).unwrap();
+    stream.write_all(format!("{request}\n").as_bytes()).unwrap();
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).unwrap();
+
+    serde_json::from_str(&response).unwrap()
+}
+
+#[test]
+fn test_client_only_commands_are_rejected() {
+    let dir = temp_dir("client-only-commands");
+    let (mut server, socket) = launch_server(&dir);
+
+    assert_eq!(
+        send(&socket, r#""Profiles""#),
+        Err("not a server command".to_string())
+    );
+    assert!(send(&socket, r#"{"Launch":{}}"#).is_err());
+    assert_eq!(
+        send(&socket, r#"{"Launch":{"allow":[]}}"#),
+        Err("not a server command".to_string())
+    );
+    assert_eq!(
+        send(&socket, r#"{"Processes":{"users":false,"cgroups":false}}"#),
+        Err("not a server command".to_string())
+    );
+
+    // The server is still running and can be stopped
+    assert!(send(&socket, r#""Quit""#).is_ok());
+    assert!(server.wait().unwrap().success());
+
+    _ = std::fs::remove_dir_all(&dir);
+}